@@ -73,6 +73,7 @@ pub enum Error {
     Unauthorized,
     Forbidden,
     NotFound(String),
+    Conflict(String), // состояние изменилось, например offset докачки
     BlockingError(String),
 }
 
@@ -87,6 +88,10 @@ impl ResponseError for Error {
                 let error: ErrorResponse = message.into();
                 HttpResponse::NotFound().json(error)
             }
+            Error::Conflict(message) => {
+                let error: ErrorResponse = message.into();
+                HttpResponse::Conflict().json(error)
+            }
             Error::Forbidden => {
                 let error: ErrorResponse = "Forbidden".into();
                 HttpResponse::Forbidden().json(error)
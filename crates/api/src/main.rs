@@ -20,6 +20,10 @@ use actix_web::{
 
 use actix_files::Files;
 use crate::routes::routes;
+use crate::utils::{
+    PageCache, SearchBackend, Storage, LocalStorage,
+    get_search_backend, get_storage, get_private_storage,
+};
 
 #[macro_use]
 mod utils;
@@ -28,18 +32,113 @@ mod views;
 
 #[derive(Clone)]
 pub struct AppState {
-    key: Arc<String>,
+    key:             Arc<String>,
+    cache:           PageCache,
+    search:          Arc<dyn SearchBackend>, // см. SEARCH_BACKEND
+    storage:         Arc<dyn Storage>,       // см. STORAGE_BACKEND
+    private_storage: Arc<dyn Storage>,       // вложения заказов, см. get_private_storage
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
+
+    // служебные команды вместо запуска сервера:
+    // `recount` - отчет о разошедшихся счетчиках, `recount --fix` - пересчет
+    let args: Vec<String> = env::args().collect();
+    if args.len() > 1 && args[1] == "recount" {
+        let is_fix = args.iter().any(|a| a == "--fix");
+        let diffs = utils::recount_counters(&utils::establish_connection(), is_fix)
+            .expect("recount failed");
+        for diff in diffs.iter() {
+            println!(
+                "{}.{} id={} ({}): {} -> {}",
+                diff.table, diff.field, diff.id, diff.name, diff.stored, diff.actual,
+            );
+        }
+        println!("discrepancies: {}", diffs.len());
+        return Ok(());
+    }
+    // `tag-slugs` - добавить и заполнить tags.slug в базе, созданной до него
+    if args.len() > 1 && args[1] == "tag-slugs" {
+        let count = models::Tag::fill_slugs();
+        println!("tags: filled {} slugs", count);
+        return Ok(());
+    }
+    // `search-words` - пересобрать словарь для исправления опечаток в поиске,
+    // запускается по cron: при сохранении объектов словарь не обновляется
+    if args.len() > 1 && args[1] == "search-words" {
+        models::SearchItem::refresh_words();
+        return Ok(());
+    }
+    // `search-rebuild` - пересобрать поисковый индекс выбранного бэкенда по базе
+    if args.len() > 1 && args[1] == "search-rebuild" {
+        let search = get_search_backend();
+        match search.rebuild() {
+            Ok(count) => println!("{}: indexed {} items", search.name(), count),
+            Err(e) => println!("{}: {}", search.name(), e),
+        };
+        return Ok(());
+    }
+
+    // `cleanup-media` - отчет о файлах хранилища без ссылок из базы,
+    // `--quarantine` - перенести их в карантин, `--delete` - удалить
+    if args.len() > 1 && args[1] == "cleanup-media" {
+        for storage in [get_storage(), get_private_storage()].iter() {
+            let report = utils::cleanup_storage (
+                &utils::establish_connection(),
+                &**storage,
+                utils::CleanupMode::from_args(&args),
+            ).expect("cleanup failed");
+            for file in report.orphans.iter() {
+                println!("{} {} bytes, {}{}", file.key, file.size, file.modified, if file.done { "" } else { " (kept)" });
+            }
+            println!(
+                "{} {} {:?}: scanned {}, orphans {} ({} bytes), skipped recent {}",
+                report.storage, report.url, report.mode, report.scanned, report.orphans.len(), report.size, report.skipped,
+            );
+        }
+        return Ok(());
+    }
+
+    // `migrate-order-files` - перенести вложения заказов, загруженные до
+    // закрытого хранилища, из общего /media в закрытое. после этого старые
+    // адреса перестают открываться, /order_file отдает только закрытые файлы
+    if args.len() > 1 && args[1] == "migrate-order-files" {
+        let count = models::OrderFile::move_to_private(&*get_storage(), &*get_private_storage());
+        println!("moved {} order files", count);
+        return Ok(());
+    }
+    // `file-metadata` - разобрать файлы, загруженные до file_metadata
+    if args.len() > 1 && args[1] == "file-metadata" {
+        for storage in [get_storage(), get_private_storage()].iter() {
+            let count = models::FileMetadata::fill_missing(&**storage);
+            println!("{} {}: parsed {} files", storage.name(), storage.url(""), count);
+        }
+        return Ok(());
+    }
+    // `image-variants` - подготовить варианты картинок, загруженных до них
+    if args.len() > 1 && args[1] == "image-variants" {
+        for storage in [get_storage(), get_private_storage()].iter() {
+            let count = models::Image::fill_missing(&**storage);
+            println!("{} {}: processed {} images", storage.name(), storage.url(""), count);
+        }
+        return Ok(());
+    }
+
     let app_state = AppState {
-        key: Arc::new(env::var("KEY").unwrap()),
+        key:             Arc::new(env::var("KEY").unwrap()),
+        cache:           PageCache::default(),
+        search:          get_search_backend(),
+        storage:         get_storage(),
+        private_storage: get_private_storage(),
     };
 
+    // при STORAGE_BACKEND=s3 файлы раздает само хранилище.
+    // список файлов каталога не отдаем
+    let media_root = LocalStorage::open().get_root();
     HttpServer::new(move || {
-        let _files = Files::new("/media", "media/").show_files_listing();
+        let _files = Files::new("/media", &media_root);
 
         App::new()
             .wrap(Compress::default())
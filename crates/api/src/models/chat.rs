@@ -11,10 +11,10 @@ use diesel::{
     NullableExpressionMethods,
 };
 use serde::{Serialize, Deserialize};
-use crate::utils::establish_connection;
+use crate::utils::{establish_connection, UploadedFiles};
 use crate::schema;
 use crate::errors::Error;
-use crate::models::SmallFile;
+use crate::models::{SmallFile, FileMetadata};
 
 
 #[derive(Debug, Queryable, Serialize, Identifiable)]
@@ -41,6 +41,9 @@ pub struct NewChat {
 // 2. измененное
 // 3. удаленное
 
+// files.item_types вложений сообщений
+pub const MESSAGE_FILES_TYPES: i16 = 11;
+
 #[derive(Debug ,Queryable, Serialize, Identifiable)]
 pub struct Message {
     pub id:      i32,
@@ -62,24 +65,28 @@ impl Message {
         let mut docs = Vec::new();
 
         let _connection = establish_connection();
-        let _files = files
+        let mut _files = files
             .filter(schema::files::item_id.eq(self.id))
-            .filter(schema::files::item_types.eq(self.types))
+            .filter(schema::files::item_types.eq(MESSAGE_FILES_TYPES))
             .select((
                 schema::files::id,
                 schema::files::types,
                 schema::files::src,
-                schema::files::description.nullable()
+                schema::files::description.nullable(),
+                schema::files::width,
+                schema::files::height,
+                schema::files::size
             )) 
             .load::<SmallFile>(&_connection)
             .expect("E");
-        
+        FileMetadata::attach(&_connection, &mut _files);
+
         for file in _files.iter() {
             match file.types {
-                11 => photos.push(file.clone()),
-                12 => videos.push(file.clone()),
-                13 => audios.push(file.clone()),
-                14 => docs.push(file.clone()),
+                1 => photos.push(file.clone()),
+                2 => videos.push(file.clone()),
+                3 => audios.push(file.clone()),
+                4 => docs.push(file.clone()),
                 _ => todo!(),
             };
         }
@@ -91,10 +98,10 @@ impl Message {
         user_id: i32,
         chat_id: i32,
         content: Option<String>,
-        photos:  Option<Vec<String>>,
-        videos:  Option<Vec<String>>,
-        audios:  Option<Vec<String>>,
-        docs:    Option<Vec<String>>,
+        photos:  Option<Vec<UploadedFiles>>,
+        videos:  Option<Vec<UploadedFiles>>,
+        audios:  Option<Vec<UploadedFiles>>,
+        docs:    Option<Vec<UploadedFiles>>,
     ) -> Result<Message, Error> {
         use chrono::Duration;
         use crate::models::NewFile;
@@ -116,22 +123,30 @@ impl Message {
         let _id = _message.id;
         if photos.is_some() {
             for i in photos.unwrap() {
-                NewFile::create(user_id, _id, 11, 1, i.clone());
+                diesel::insert_into(schema::files::table)
+                    .values(&NewFile::create(user_id, _id, MESSAGE_FILES_TYPES, 1, &i))
+                    .execute(&_connection)?;
             }
         }
         if videos.is_some() {
             for i in videos.unwrap() {
-                NewFile::create(user_id, _id, 11, 2, i.clone());
+                diesel::insert_into(schema::files::table)
+                    .values(&NewFile::create(user_id, _id, MESSAGE_FILES_TYPES, 2, &i))
+                    .execute(&_connection)?;
             }
         }
         if audios.is_some() {
             for i in audios.unwrap() {
-                NewFile::create(user_id, _id, 11, 3, i.clone());
+                diesel::insert_into(schema::files::table)
+                    .values(&NewFile::create(user_id, _id, MESSAGE_FILES_TYPES, 3, &i))
+                    .execute(&_connection)?;
             }
         }
         if docs.is_some() {
             for i in docs.unwrap() {
-                NewFile::create(user_id, _id, 11, 4, i.clone());
+                diesel::insert_into(schema::files::table)
+                    .values(&NewFile::create(user_id, _id, MESSAGE_FILES_TYPES, 4, &i))
+                    .execute(&_connection)?;
             }
         }
         return Ok(_message);
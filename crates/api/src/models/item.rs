@@ -6,16 +6,20 @@ use crate::diesel::{
     RunQueryDsl,
     ExpressionMethods,
     NullableExpressionMethods,
-    PgTextExpressionMethods,
+    Connection,
 };
+use diesel::sql_types::{Integer, SmallInt};
 use serde::{Serialize,Deserialize};
 use crate::models::{
     User,
     Tag,
+    SlugRedirect,
     TechCategories,
     Serve,
     SmallTag,
     SmallFile,
+    FileMetadata,
+    Image,
 };
 
 use crate::schema::{
@@ -25,7 +29,13 @@ use crate::schema::{
     item_comments,
     item_contents,
 };
-use crate::utils::{establish_connection, OwnerResp,};
+use crate::utils::{
+    establish_connection,
+    update_category_counts,
+    update_tag_counts,
+    OwnerResp,
+    ImageVariant,
+};
 use crate::errors::Error;
 
 
@@ -63,12 +73,13 @@ impl CatDetail {
 
 #[derive(Serialize, Queryable, Clone)]
 pub struct Cat {
-    pub name:  String,
-    pub slug:  String,
-    pub count: i16,
-    pub id:    i32,
-    pub image: Option<String>,
-    pub types: i16,
+    pub name:      String,
+    pub slug:      String,
+    pub count:     i16,
+    pub id:        i32,
+    pub image:     Option<String>,
+    pub types:     i16,
+    pub parent_id: Option<i32>,
 }
 impl Cat {
     pub fn get_image(&self) -> String {
@@ -91,8 +102,9 @@ impl Cat {
         };
 
         let _connection = establish_connection();
+        // объекты категории вместе с объектами ее подкатегорий
         let ids = category
-            .filter(schema::category::categories_id.eq(self.id))
+            .filter(schema::category::categories_id.eq_any(Categories::get_descendant_ids(self.id)))
             .filter(schema::category::types.eq(types))
             .select(schema::category::item_id)
             .load::<i32>(&_connection)
@@ -168,7 +180,7 @@ impl Blog {
             .expect("E");
         let _tags = tags
             .filter(schema::tags::id.eq_any(_tag_items))
-            .select((schema::tags::name, schema::tags::count))
+            .select((schema::tags::id, schema::tags::name, schema::tags::slug, schema::tags::count))
             .load::<SmallTag>(&_connection)
             .expect("E");
         return _tags;
@@ -208,7 +220,7 @@ impl Service {
             .expect("E");
         let _tags = tags
             .filter(schema::tags::id.eq_any(_tag_items))
-            .select((schema::tags::name, schema::tags::count))
+            .select((schema::tags::id, schema::tags::name, schema::tags::slug, schema::tags::count))
             .load::<SmallTag>(&_connection)
             .expect("E");
         return _tags;
@@ -250,7 +262,7 @@ impl Store {
             .expect("E");
         let _tags = tags
             .filter(schema::tags::id.eq_any(_tag_items))
-            .select((schema::tags::name, schema::tags::count))
+            .select((schema::tags::id, schema::tags::name, schema::tags::slug, schema::tags::count))
             .load::<SmallTag>(&_connection)
             .expect("E");
         return _tags;
@@ -291,7 +303,7 @@ impl Wiki {
             .expect("E");
         let _tags = tags
             .filter(schema::tags::id.eq_any(_tag_items))
-            .select((schema::tags::name, schema::tags::count))
+            .select((schema::tags::id, schema::tags::name, schema::tags::slug, schema::tags::count))
             .load::<SmallTag>(&_connection)
             .expect("E");
         return _tags;
@@ -331,7 +343,7 @@ impl Work {
             .expect("E");
         let _tags = tags
             .filter(schema::tags::id.eq_any(_tag_items))
-            .select((schema::tags::name, schema::tags::count))
+            .select((schema::tags::id, schema::tags::name, schema::tags::slug, schema::tags::count))
             .load::<SmallTag>(&_connection)
             .expect("E");
         return _tags;
@@ -385,7 +397,7 @@ impl Help {
             .expect("E");
         let _tags = tags
             .filter(schema::tags::id.eq_any(_tag_items))
-            .select((schema::tags::name, schema::tags::count))
+            .select((schema::tags::id, schema::tags::name, schema::tags::slug, schema::tags::count))
             .load::<SmallTag>(&_connection)
             .expect("E");
         return _tags;
@@ -398,7 +410,7 @@ pub struct FeaturedItem {
     pub title: String,
 }
 
-#[derive(Debug, Serialize, Queryable, Identifiable, Associations)]
+#[derive(Debug, Clone, Serialize, Queryable, Identifiable, Associations)]
 #[table_name="categories"]
 pub struct Categories {
     pub id:          i32,
@@ -413,26 +425,25 @@ pub struct Categories {
     pub seconds:     i32,
     pub types:       i16,
     pub slug:        String,
+    pub parent_id:   Option<i32>,
 }
 
 impl Categories {
     pub fn get_tags(types: i16) -> Result<Vec<SmallTag>, Error> {
-        use crate::schema::{
-            tags_items::dsl::tags_items,
-            tags::dsl::tags,
-        };
+        // теги типа объектов; count - число опубликованных объектов
+        // этого типа с тегом, самые частые первыми
         let _connection = establish_connection();
-
-        let _tag_items = tags_items
-            .filter(schema::tags_items::types.eq(types))
-            .select(schema::tags_items::tag_id)
-            .load::<i32>(&_connection)
-            .expect("E");
-        let _tags = tags
-            .filter(schema::tags::id.eq_any(_tag_items))
-            .select((schema::tags::name, schema::tags::count))
-            .load::<SmallTag>(&_connection)
-            .expect("E");
+        let _tags = diesel::sql_query("
+            SELECT t.id, t.name, t.slug, COUNT(DISTINCT l.item_id)::SMALLINT AS count
+            FROM tags_items l
+            INNER JOIN tags t ON t.id = l.tag_id
+            INNER JOIN items i ON i.id = l.item_id AND i.item_types < 10
+            WHERE l.types = $1
+            GROUP BY t.id
+            ORDER BY count DESC, t.name
+        ")
+            .bind::<SmallInt, _>(types)
+            .load::<SmallTag>(&_connection)?;
         return Ok(_tags);
     }
     pub fn get_featured_items(&self, types: i16, id: i32) -> (Option<FeaturedItem>, Option<FeaturedItem>) {
@@ -548,7 +559,7 @@ impl Categories {
         let _connection = establish_connection();
         let _items: Vec<Blog>;
         let ids = category
-            .filter(schema::category::categories_id.eq(cat_id))
+            .filter(schema::category::categories_id.eq_any(Categories::get_descendant_ids(cat_id)))
             .filter(schema::category::types.eq(1))
             .select(schema::category::item_id)
             .load::<i32>(&_connection)
@@ -630,7 +641,7 @@ impl Categories {
         let _connection = establish_connection();
         let _items: Vec<Service>;
         let ids = category
-            .filter(schema::category::categories_id.eq(cat_id))
+            .filter(schema::category::categories_id.eq_any(Categories::get_descendant_ids(cat_id)))
             .filter(schema::category::types.eq(2))
             .select(schema::category::item_id)
             .load::<i32>(&_connection)
@@ -711,7 +722,7 @@ impl Categories {
         let _connection = establish_connection();
         let _items: Vec<Store>;
         let ids = category
-            .filter(schema::category::categories_id.eq(cat_id))
+            .filter(schema::category::categories_id.eq_any(Categories::get_descendant_ids(cat_id)))
             .filter(schema::category::types.eq(3))
             .select(schema::category::item_id)
             .load::<i32>(&_connection)
@@ -796,7 +807,7 @@ impl Categories {
         let _connection = establish_connection();
         let _items: Vec<Wiki>;
         let ids = category
-            .filter(schema::category::categories_id.eq(cat_id))
+            .filter(schema::category::categories_id.eq_any(Categories::get_descendant_ids(cat_id)))
             .filter(schema::category::types.eq(4))
             .select(schema::category::item_id)
             .load::<i32>(&_connection)
@@ -879,7 +890,7 @@ impl Categories {
         let _connection = establish_connection();
         let _items: Vec<Work>;
         let ids = category
-            .filter(schema::category::categories_id.eq(cat_id))
+            .filter(schema::category::categories_id.eq_any(Categories::get_descendant_ids(cat_id)))
             .filter(schema::category::types.eq(5))
             .select(schema::category::item_id)
             .load::<i32>(&_connection)
@@ -960,7 +971,7 @@ impl Categories {
         let _connection = establish_connection();
         let _items: Vec<Help>;
         let ids = category
-            .filter(schema::category::categories_id.eq(cat_id))
+            .filter(schema::category::categories_id.eq_any(Categories::get_descendant_ids(cat_id)))
             .filter(schema::category::types.eq(6))
             .select(schema::category::item_id)
             .load::<i32>(&_connection)
@@ -1006,6 +1017,10 @@ impl Categories {
             return "/static/images/img.jpg".to_string();
         }
     }
+    pub fn get_image_variants(&self) -> Vec<ImageVariant> {
+        // thumb / card / full картинки в формате исходника и webp
+        return Image::get_variants(&self.image);
+    }
     pub fn get_categories_for_types(types: i16) -> Result<Vec<Cat>, Error> {
         use crate::schema::categories::dsl::categories;
         let _connection = establish_connection();
@@ -1018,6 +1033,7 @@ impl Categories {
                 schema::categories::id,
                 schema::categories::image,
                 schema::categories::types,
+                schema::categories::parent_id,
             ))
             .load::<Cat>(&_connection)
             .expect("E");
@@ -1034,11 +1050,154 @@ impl Categories {
                 schema::categories::id,
                 schema::categories::image,
                 schema::categories::types,
+                schema::categories::parent_id,
             ))
             .load::<Cat>(&_connection)
             .expect("E");
         return Ok(cats);
     }
+
+    pub fn get_descendant_ids(id: i32) -> Vec<i32> {
+        // id категории и всех ее подкатегорий на любой глубине
+        use crate::schema::categories::dsl::categories;
+
+        let _connection = establish_connection();
+        let mut ids = vec![id];
+        let mut level = vec![id];
+        while !level.is_empty() {
+            level = categories
+                .filter(schema::categories::parent_id.eq_any(level))
+                .filter(schema::categories::id.ne_all(ids.clone()))
+                .select(schema::categories::id)
+                .load::<i32>(&_connection)
+                .expect("E");
+            ids.extend(level.iter());
+        }
+        return ids;
+    }
+    pub fn get_ancestor_ids(id: i32) -> Vec<i32> {
+        // id родителей категории - от ближайшего к корневому
+        use crate::schema::categories::dsl::categories;
+
+        let _connection = establish_connection();
+        let mut ids: Vec<i32> = Vec::new();
+        let mut parent = categories
+            .filter(schema::categories::id.eq(id))
+            .select(schema::categories::parent_id)
+            .first::<Option<i32>>(&_connection)
+            .unwrap_or(None);
+        while parent.is_some() {
+            let _id = parent.unwrap();
+            if _id == id || ids.contains(&_id) {
+                break;
+            }
+            ids.push(_id);
+            parent = categories
+                .filter(schema::categories::id.eq(_id))
+                .select(schema::categories::parent_id)
+                .first::<Option<i32>>(&_connection)
+                .unwrap_or(None);
+        }
+        return ids;
+    }
+    pub fn get_breadcrumbs(id: i32) -> Vec<SmallCat> {
+        // цепочка категорий от корневой до текущей включительно
+        use crate::schema::categories::dsl::categories;
+
+        let _connection = establish_connection();
+        let mut ids = Categories::get_ancestor_ids(id);
+        ids.reverse();
+        ids.push(id);
+
+        let mut list = Vec::new();
+        for _id in ids.iter() {
+            let _cat = categories
+                .filter(schema::categories::id.eq(_id))
+                .select((
+                    schema::categories::name,
+                    schema::categories::slug,
+                    schema::categories::count
+                ))
+                .first::<SmallCat>(&_connection);
+            if _cat.is_ok() {
+                list.push(_cat.expect("E"));
+            }
+        }
+        return list;
+    }
+    pub fn move_to(&self, parent_id: Option<i32>) -> Result<(), Error> {
+        // переносим категорию вместе с подкатегориями.
+        // счетчики старых и новых родителей пересчитываются в той же транзакции.
+        let _connection = establish_connection();
+        let mut ids = Categories::get_ancestor_ids(self.id);
+        if parent_id.is_some() {
+            ids.push(parent_id.unwrap());
+        }
+        return _connection.transaction(|| {
+            diesel::update(self)
+                .set(schema::categories::parent_id.eq(parent_id))
+                .execute(&_connection)?;
+            update_category_counts(&_connection, ids)?;
+            Ok(())
+        });
+    }
+    pub fn get_actual_slug(slug: &str, types: i16) -> String {
+        // slug категории; если категорию влили в другую - slug той
+        use crate::schema::categories::dsl::categories;
+
+        let _connection = establish_connection();
+        let is_exists = categories
+            .filter(schema::categories::slug.eq(slug))
+            .filter(schema::categories::types.eq(types))
+            .select(schema::categories::id)
+            .first::<i32>(&_connection)
+            .is_ok();
+        if !is_exists {
+            if let Some(new_id) = SlugRedirect::get_new_id(2, slug) {
+                let new_slug = categories
+                    .filter(schema::categories::id.eq(new_id))
+                    .filter(schema::categories::types.eq(types))
+                    .select(schema::categories::slug)
+                    .first::<String>(&_connection);
+                if new_slug.is_ok() {
+                    return new_slug.expect("E");
+                }
+            }
+        }
+        return slug.to_string();
+    }
+    pub fn merge(&self, target: &Categories) -> Result<(), Error> {
+        // вливаем категорию в target: связи с объектами переносятся без дублей,
+        // подкатегории переходят к target, старый slug запоминается
+        // для перенаправления, сама категория удаляется
+        use crate::schema::{
+            category::dsl::category,
+            categories::dsl::categories,
+        };
+
+        let _connection = establish_connection();
+        let mut ids = Categories::get_ancestor_ids(self.id);
+        ids.push(target.id);
+        return _connection.transaction(|| {
+            diesel::update(category.filter(schema::category::categories_id.eq(self.id)))
+                .set(schema::category::categories_id.eq(target.id))
+                .execute(&_connection)?;
+            diesel::sql_query("
+                DELETE FROM category a USING category b
+                WHERE a.categories_id = $1 AND b.categories_id = $1
+                AND a.item_id = b.item_id AND a.types = b.types AND a.id > b.id
+            ")
+                .bind::<Integer, _>(target.id)
+                .execute(&_connection)?;
+            diesel::update(categories.filter(schema::categories::parent_id.eq(self.id)))
+                .set(schema::categories::parent_id.eq(target.id))
+                .execute(&_connection)?;
+            SlugRedirect::create(&_connection, 2, self.slug.clone(), self.id, target.id)?;
+            diesel::delete(self).execute(&_connection)?;
+            update_category_counts(&_connection, ids)?;
+            Ok(())
+        });
+    }
 }
 
 #[derive(Insertable)]
@@ -1055,6 +1214,7 @@ pub struct NewCategories {
     pub seconds:     i32,
     pub types:       i16,
     pub slug:        String,
+    pub parent_id:   Option<i32>,
 }
 
 #[derive(Queryable, Serialize, Deserialize, AsChangeset, Debug)]
@@ -1086,6 +1246,7 @@ pub struct Item {
     pub view:        i32,
     pub height:      f64,
     pub seconds:     i32,
+    pub updated:     chrono::NaiveDateTime,
 }
 
 impl Item {
@@ -1162,6 +1323,10 @@ impl Item {
             return "/static/images/img.jpg".to_string();
         }
     }
+    pub fn get_image_variants(&self) -> Vec<ImageVariant> {
+        // thumb / card / full картинки в формате исходника и webp
+        return Image::get_variants(&self.image);
+    }
     pub fn get_files(&self) -> (Vec<SmallFile>, Vec<SmallFile>, Vec<SmallFile>, Vec<SmallFile>) { 
         use schema::files::dsl::files;
 
@@ -1171,18 +1336,23 @@ impl Item {
         let mut docs = Vec::new();
 
         let _connection = establish_connection();
-        let _files = files
+        let mut _files = files
             .filter(schema::files::item_id.eq(self.id))
             .filter(schema::files::item_types.eq(self.types))
+            .order(schema::files::position)
             .select((
                 schema::files::id,
                 schema::files::types,
                 schema::files::src,
-                schema::files::description.nullable()
+                schema::files::description.nullable(),
+                schema::files::width,
+                schema::files::height,
+                schema::files::size
             )) 
             .load::<SmallFile>(&_connection)
             .expect("E");
-        
+        FileMetadata::attach(&_connection, &mut _files);
+
         for file in _files.iter() {
             match file.types {
                 1 => photos.push(file.clone()),
@@ -1195,16 +1365,41 @@ impl Item {
         return (photos, videos, audios, docs);
     }
     pub fn get_images_ids(&self) -> Vec<i32> {
+        // фото объекта в порядке галереи
         use schema::files::dsl::files;
 
         let _connection = establish_connection();
         return files
             .filter(schema::files::item_id.eq(self.id))
+            .filter(schema::files::item_types.eq(self.types))
             .filter(schema::files::types.eq(1))
+            .order((schema::files::position, schema::files::id))
             .select(schema::files::id)
             .load::<i32>(&_connection)
             .expect("E");
     }
+    pub fn get_album(&self) -> Vec<SmallFile> {
+        // фото объекта в порядке галереи, с размерами и вариантами
+        use schema::files::dsl::files;
+
+        let _connection = establish_connection();
+        return files
+            .filter(schema::files::item_id.eq(self.id))
+            .filter(schema::files::item_types.eq(self.types))
+            .filter(schema::files::types.eq(1))
+            .order((schema::files::position, schema::files::id))
+            .select((
+                schema::files::id,
+                schema::files::types,
+                schema::files::src,
+                schema::files::description.nullable(),
+                schema::files::width,
+                schema::files::height,
+                schema::files::size
+            ))
+            .load::<SmallFile>(&_connection)
+            .expect("E");
+    }
 
     pub fn get_categories(&self) -> Result<Vec<SmallCat>, Error> {
         use crate::schema::{
@@ -1266,7 +1461,7 @@ impl Item {
             .expect("E");
         let _tags = tags
             .filter(schema::tags::id.eq_any(_tag_items))
-            .select((schema::tags::name, schema::tags::count))
+            .select((schema::tags::id, schema::tags::name, schema::tags::slug, schema::tags::count))
             .load::<SmallTag>(&_connection)
             .expect("E");
         return Ok(_tags);
@@ -1336,64 +1531,6 @@ impl Item {
                 .expect("E.");
         }
     }
-    pub fn search_blogs (
-        q:        &String,
-        limit:    i64,
-        offset:   i64,
-        is_admin: bool
-    ) -> (Vec<Blog>, usize) {
-        use crate::schema::{
-            items::dsl::items,
-            item_contents::dsl::item_contents,
-        };
-
-        let _connection = establish_connection();
-        let item_ids = item_contents
-            .filter(schema::item_contents::item_title.ilike(&q))
-            .or_filter(schema::item_contents::title.ilike(&q))
-            .or_filter(schema::item_contents::content.ilike(&q))
-            .filter(schema::item_contents::item_types.eq(1))
-            .select(schema::item_contents::item_id)
-            .distinct()
-            .load::<i32>(&_connection)
-            .expect("E.");
-        let count = item_ids.len();
-        if is_admin {
-             return (items
-                .filter(schema::items::id.eq_any(item_ids))
-                .order(schema::items::created.desc())
-                .limit(limit)
-                .offset(offset)
-                .select((
-                    schema::items::id,
-                    schema::items::slug,
-                    schema::items::image.nullable(),
-                    schema::items::description.nullable(),
-                    schema::items::item_types,
-                    schema::items::title,
-                    schema::items::created,
-                ))
-                .load::<Blog>(&_connection)
-                .expect("E."), count);
-        } else {
-            return (items
-                .filter(schema::items::id.eq_any(item_ids))
-                .order(schema::items::created.desc())
-                .limit(limit)
-                .offset(offset)
-                .select((
-                    schema::items::id,
-                    schema::items::slug,
-                    schema::items::image.nullable(),
-                    schema::items::description.nullable(),
-                    schema::items::item_types,
-                    schema::items::title,
-                    schema::items::created,
-                ))
-                .load::<Blog>(&_connection)
-                .expect("E."), count);
-        }
-    }
 
     pub fn get_services (
         limit:    i64,
@@ -1438,63 +1575,6 @@ impl Item {
                 .expect("E.");
         }
     }
-    pub fn search_services (
-        q:        &String,
-        limit:    i64,
-        offset:   i64,
-        is_admin: bool
-    ) -> (Vec<Service>, usize) {
-        use crate::schema::{
-            items::dsl::items,
-            item_contents::dsl::item_contents,
-        };
-
-        let _connection = establish_connection();
-        let item_ids = item_contents
-            .filter(schema::item_contents::item_title.ilike(&q))
-            .or_filter(schema::item_contents::title.ilike(&q))
-            .or_filter(schema::item_contents::content.ilike(&q))
-            .filter(schema::item_contents::item_types.eq(2))
-            .select(schema::item_contents::item_id)
-            .distinct()
-            .load::<i32>(&_connection)
-            .expect("E.");
-        let count = item_ids.len();
-        if is_admin {
-             return (items
-                .filter(schema::items::id.eq_any(item_ids))
-                .order(schema::items::created.desc())
-                .limit(limit)
-                .offset(offset)
-                .select((
-                    schema::items::id,
-                    schema::items::slug,
-                    schema::items::image.nullable(),
-                    schema::items::description.nullable(),
-                    schema::items::item_types,
-                    schema::items::title,
-                ))
-                .load::<Service>(&_connection)
-                .expect("E."), count);
-        } else {
-            return (items
-                .filter(schema::items::id.eq_any(item_ids))
-                .filter(schema::items::item_types.lt(10))
-                .order(schema::items::created.desc())
-                .limit(limit)
-                .offset(offset)
-                .select((
-                    schema::items::id,
-                    schema::items::slug,
-                    schema::items::image.nullable(),
-                    schema::items::description.nullable(),
-                    schema::items::item_types,
-                    schema::items::title,
-                ))
-                .load::<Service>(&_connection)
-                .expect("E."), count);
-        }
-    }
 
     pub fn get_stores (
           limit:    i64,
@@ -1543,67 +1623,6 @@ impl Item {
                 .expect("E.");
           }
     }
-    pub fn search_stores (
-          q:        &String,
-          limit:    i64,
-          offset:   i64,
-          is_admin: bool
-      ) -> (Vec<Store>, usize) {
-        use crate::schema::{
-            items::dsl::items,
-            item_contents::dsl::item_contents,
-        };
-
-        let _connection = establish_connection();
-        let item_ids = item_contents
-            .filter(schema::item_contents::item_title.ilike(&q))
-            .or_filter(schema::item_contents::title.ilike(&q))
-            .or_filter(schema::item_contents::content.ilike(&q))
-            .filter(schema::item_contents::item_types.eq(3))
-            .select(schema::item_contents::item_id)
-            .distinct()
-            .load::<i32>(&_connection)
-            .expect("E.");
-        let count = item_ids.len();
-        if is_admin {
-            return (items
-                .filter(schema::items::id.eq_any(item_ids))
-                .order(schema::items::created.desc())
-                .limit(limit)
-                .offset(offset)
-                .select((
-                    schema::items::id,
-                    schema::items::slug,
-                    schema::items::image.nullable(),
-                    schema::items::description.nullable(),
-                    schema::items::item_types,
-                    schema::items::title,
-                    schema::items::price,
-                    schema::items::price_acc.nullable(),
-                ))
-                .load::<Store>(&_connection)
-                .expect("E."), count);
-        } else {
-            return (items
-                .filter(schema::items::id.eq_any(item_ids))
-                .filter(schema::items::item_types.lt(10))
-                .order(schema::items::created.desc())
-                .limit(limit)
-                .offset(offset)
-                .select((
-                    schema::items::id,
-                    schema::items::slug,
-                    schema::items::image.nullable(),
-                    schema::items::description.nullable(),
-                    schema::items::item_types,
-                    schema::items::title,
-                    schema::items::price,
-                    schema::items::price_acc.nullable(),
-                ))
-                .load::<Store>(&_connection)
-                .expect("E."), count);
-          }
-    }
 
     pub fn get_works (
           limit:    i64,
@@ -1648,63 +1667,6 @@ impl Item {
                 .expect("E.");
         }
     }
-    pub fn search_works (
-          q:        &String,
-          limit:    i64,
-          offset:   i64,
-          is_admin: bool
-      ) -> (Vec<Work>, usize) {
-        use crate::schema::{
-            items::dsl::items,
-            item_contents::dsl::item_contents,
-        };
-
-        let _connection = establish_connection();
-        let item_ids = item_contents
-            .filter(schema::item_contents::item_title.ilike(&q))
-            .or_filter(schema::item_contents::title.ilike(&q))
-            .or_filter(schema::item_contents::content.ilike(&q))
-            .filter(schema::item_contents::item_types.eq(5))
-            .select(schema::item_contents::item_id)
-            .distinct()
-            .load::<i32>(&_connection)
-            .expect("E.");
-        let count = item_ids.len();
-        if is_admin {
-            return (items
-                .filter(schema::items::id.eq_any(item_ids))
-                .order(schema::items::created.desc())
-                .limit(limit)
-                .offset(offset)
-                .select((
-                    schema::items::id,
-                    schema::items::slug,
-                    schema::items::image.nullable(),
-                    schema::items::description.nullable(),
-                    schema::items::item_types,
-                    schema::items::title,
-                ))
-                .load::<Work>(&_connection)
-                .expect("E."), count);
-        } else {
-            return (items
-                .filter(schema::items::id.eq_any(item_ids))
-                .filter(schema::items::item_types.lt(10))
-                .order(schema::items::created.desc())
-                .limit(limit)
-                .offset(offset)
-                .select((
-                    schema::items::id,
-                    schema::items::slug,
-                    schema::items::image.nullable(),
-                    schema::items::description.nullable(),
-                    schema::items::item_types,
-                    schema::items::title,
-                ))
-                .load::<Work>(&_connection)
-                .expect("E."), count);
-        }
-    }
 
     pub fn get_wikis (
           limit:    i64,
@@ -1751,65 +1713,6 @@ impl Item {
                 .expect("E.");
         }
     }
-    pub fn search_wikis (
-          q:        &String,
-          limit:    i64,
-          offset:   i64,
-          is_admin: bool
-      ) -> (Vec<Wiki>, usize) {
-        use crate::schema::{
-            items::dsl::items,
-            item_contents::dsl::item_contents,
-        };
-
-        let _connection = establish_connection();
-        let item_ids = item_contents
-            .filter(schema::item_contents::item_title.ilike(&q))
-            .or_filter(schema::item_contents::title.ilike(&q))
-            .or_filter(schema::item_contents::content.ilike(&q))
-            .filter(schema::item_contents::item_types.eq(4))
-            .select(schema::item_contents::item_id)
-            .distinct()
-            .load::<i32>(&_connection)
-            .expect("E.");
-        let count = item_ids.len();
-        if is_admin {
-            return (items
-                .filter(schema::items::id.eq_any(item_ids))
-                .order(schema::items::created.desc())
-                .limit(limit)
-                .offset(offset)
-                .select((
-                    schema::items::id,
-                    schema::items::slug,
-                    schema::items::image.nullable(),
-                    schema::items::description.nullable(),
-                    schema::items::item_types,
-                    schema::items::title,
-                    schema::items::created
-                ))
-                .load::<Wiki>(&_connection)
-                .expect("E."), count);
-        } else {
-            return (items
-                .filter(schema::items::id.eq_any(item_ids))
-                .filter(schema::items::item_types.lt(10))
-                .order(schema::items::created.desc())
-                .limit(limit)
-                .offset(offset)
-                .select((
-                    schema::items::id,
-                    schema::items::slug,
-                    schema::items::image.nullable(),
-                    schema::items::description.nullable(),
-                    schema::items::item_types,
-                    schema::items::title,
-                    schema::items::created,
-                ))
-                .load::<Wiki>(&_connection)
-                .expect("E."), count);
-        }
-    }
 
     pub fn get_helps (
         limit:    i64,
@@ -1851,59 +1754,6 @@ impl Item {
         }
     }
 
-    pub fn search_helps (
-        q:        &String,
-        limit:    i64,
-        offset:   i64,
-        is_admin: bool
-    ) -> (Vec<Help>, usize) {
-        use crate::schema::{
-            items::dsl::items,
-            item_contents::dsl::item_contents,
-        };
-
-        let _connection = establish_connection();
-        let item_ids = item_contents
-            .filter(schema::item_contents::item_title.ilike(&q))
-            .or_filter(schema::item_contents::title.ilike(&q))
-            .or_filter(schema::item_contents::content.ilike(&q))
-            .filter(schema::item_contents::item_types.eq(6))
-            .select(schema::item_contents::item_id)
-            .distinct()
-            .load::<i32>(&_connection)
-            .expect("E.");
-        let count = item_ids.len();
-        if is_admin {
-            return (items
-                .filter(schema::items::id.eq_any(item_ids))
-                .order(schema::items::created.desc())
-                .limit(limit)
-                .offset(offset)
-                .select((
-                    schema::items::id,
-                    schema::items::title,
-                    schema::items::description.nullable(),
-                    schema::items::item_types,
-                ))
-                .load::<Help>(&_connection)
-                .expect("E."), count);
-        } else {
-            return (items
-                .filter(schema::items::id.eq_any(item_ids))
-                .order(schema::items::created.desc())
-                .limit(limit)
-                .offset(offset)
-                .select((
-                    schema::items::id,
-                    schema::items::title,
-                    schema::items::description.nullable(),
-                    schema::items::item_types,
-                ))
-                .load::<Help>(&_connection)
-                .expect("E."), count);
-        }
-    }
-
     pub fn get_blogs_list_for_ids (
         page:  i32,
         limit: i32,
@@ -2431,9 +2281,29 @@ impl Item {
             5 => 15,
             _ => 11,
         };
-        let o = diesel::update(self)
-            .set(schema::items::item_types.eq(_case))
-            .execute(&_connection);
+        let _category_ids = self.get_categories_obj()
+            .expect("E")
+            .iter()
+            .map(|c| c.id)
+            .collect();
+        let _tag_ids = self.get_tags_obj()
+            .expect("E")
+            .iter()
+            .map(|t| t.id)
+            .collect();
+
+        // статус объекта и счетчики его категорий и тегов меняются вместе
+        let o = _connection.transaction::<_, diesel::result::Error, _>(|| {
+            diesel::update(self)
+                .set((
+                    schema::items::item_types.eq(_case),
+                    schema::items::updated.eq(chrono::Local::now().naive_utc() + chrono::Duration::hours(3)),
+                ))
+                .execute(&_connection)?;
+            update_category_counts(&_connection, _category_ids)?;
+            update_tag_counts(&_connection, _tag_ids)?;
+            Ok(())
+        });
 
         if o.is_ok() {
             return 1;
@@ -2452,9 +2322,29 @@ impl Item {
             15 => 5,
             _ => 1,
         };
-        let o = diesel::update(self)
-            .set(schema::items::item_types.eq(_case))
-            .execute(&_connection);
+        let _category_ids = self.get_categories_obj()
+            .expect("E")
+            .iter()
+            .map(|c| c.id)
+            .collect();
+        let _tag_ids = self.get_tags_obj()
+            .expect("E")
+            .iter()
+            .map(|t| t.id)
+            .collect();
+
+        // статус объекта и счетчики его категорий и тегов меняются вместе
+        let o = _connection.transaction::<_, diesel::result::Error, _>(|| {
+            diesel::update(self)
+                .set((
+                    schema::items::item_types.eq(_case),
+                    schema::items::updated.eq(chrono::Local::now().naive_utc() + chrono::Duration::hours(3)),
+                ))
+                .execute(&_connection)?;
+            update_category_counts(&_connection, _category_ids)?;
+            update_tag_counts(&_connection, _tag_ids)?;
+            Ok(())
+        });
 
         if o.is_ok() {
             return 1;
@@ -2483,6 +2373,7 @@ pub struct NewItem {
     pub view:        i32,
     pub height:      f64,
     pub seconds:     i32,
+    pub updated:     chrono::NaiveDateTime,
 }
 
 impl NewItem {
@@ -2497,6 +2388,7 @@ impl NewItem {
     ) -> Self {
         use chrono::Duration;
 
+        let created = chrono::Local::now().naive_utc() + Duration::hours(3);
         NewItem {
             title:       title,
             link:        link,
@@ -2505,7 +2397,7 @@ impl NewItem {
             item_types:  1,
             price:       0,
             user_id:     user_id,
-            created:     chrono::Local::now().naive_utc() + Duration::hours(3),
+            created:     created,
             position:    position,
             price_acc:   None,
             types:       types,
@@ -2513,6 +2405,7 @@ impl NewItem {
             view:        0,
             height:      0.0,
             seconds:     0,
+            updated:     created,
         }
     }
 }
@@ -2525,6 +2418,7 @@ pub struct EditItem {
     pub image:       Option<String>,
     pub position:    i16,
     pub slug:        String,
+    pub updated:     chrono::NaiveDateTime,
 }
 
 
@@ -1,13 +1,43 @@
-//use crate::schema;
+use std::collections::HashMap;
+use crate::schema;
 use crate::diesel::{
     Queryable,
     Insertable,
-    //QueryDsl,
-    //RunQueryDsl,
+    QueryDsl,
+    QueryResult,
+    RunQueryDsl,
+    ExpressionMethods,
+    BoolExpressionMethods,
+    TextExpressionMethods,
+    PgTextExpressionMethods,
+    Connection,
+    PgConnection,
+    pg::Pg,
+};
+use diesel::sql_types::{
+    Integer,
+    Nullable,
+    SmallInt,
+    Text,
 };
 use serde::{Serialize, Deserialize};
-use crate::schema::files;
-//use crate::utils::establish_connection;
+use crate::schema::{
+    blobs,
+    file_metadata,
+    files,
+    images,
+};
+use crate::utils::{
+    establish_connection,
+    extract_metadata,
+    get_image_variants,
+    process_image,
+    remove_image_variants,
+    ImageVariant,
+    Storage,
+    UploadedFiles,
+    METADATA_MAX_SIZE,
+};
 
 
 ///////////
@@ -31,12 +61,40 @@ use crate::schema::files;
 // 3. audios
 // 4. docs
 
-#[derive(Serialize, Queryable, Clone)]
+#[derive(Serialize, Clone)]
 pub struct SmallFile {
     pub id:          i32,
     pub types:       i16,
     pub src:         String,
     pub description: Option<String>,
+    pub width:       Option<i32>,
+    pub height:      Option<i32>,
+    pub size:        i32,
+    pub variants:    Vec<ImageVariant>,    // пусто, если это не картинка
+    pub meta:        Option<FileMetadata>, // см. FileMetadata::attach
+}
+// выбирается как (id, types, src, description, width, height, size);
+// адреса вариантов вычисляются по src и размерам
+impl Queryable<(Integer, SmallInt, Text, Nullable<Text>, Nullable<Integer>, Nullable<Integer>, Integer), Pg> for SmallFile {
+    type Row = (i32, i16, String, Option<String>, Option<i32>, Option<i32>, i32);
+
+    fn build(row: Self::Row) -> Self {
+        let variants = match (row.4, row.5) {
+            (Some(width), Some(height)) => get_image_variants(&row.2, width, height),
+            _ => Vec::new(),
+        };
+        SmallFile {
+            id:          row.0,
+            types:       row.1,
+            src:         row.2,
+            description: row.3,
+            width:       row.4,
+            height:      row.5,
+            size:        row.6,
+            variants:    variants,
+            meta:        None,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Identifiable, Queryable, Associations)]
@@ -52,6 +110,12 @@ pub struct File {
     pub position:    i16,
     pub view:        i32,
     pub seconds:     i32,
+    pub original_name: String,
+    pub mime:          String,
+    pub size:          i32,
+    pub width:         Option<i32>,
+    pub height:        Option<i32>,
+    pub created:       chrono::NaiveDateTime,
 }
 #[derive(Debug, Deserialize, Insertable)]
 #[table_name="files"]
@@ -65,6 +129,12 @@ pub struct NewFile {
     pub position:    i16,
     pub view:        i32,
     pub seconds:     i32,
+    pub original_name: String,
+    pub mime:          String,
+    pub size:          i32,
+    pub width:         Option<i32>,
+    pub height:        Option<i32>,
+    pub created:       chrono::NaiveDateTime,
 }
 
 impl NewFile {
@@ -73,18 +143,24 @@ impl NewFile {
         item_id:     i32,
         item_types:  i16,
         types:       i16,
-        src:         String
+        file:        &UploadedFiles
     ) -> Self {
         NewFile {
             user_id:     user_id,
             item_id:     item_id,
             item_types:  item_types,
             types:       types,
-            src:         src,
+            src:         file.get_src(),
             description: None,
             position:    0,
             view:        0,
             seconds:     0,
+            original_name: file.name.clone(),
+            mime:          file.mime.clone(),
+            size:          file.size,
+            width:         file.width,
+            height:        file.height,
+            created:       chrono::Local::now().naive_utc() + chrono::Duration::hours(3),
         }
     }
 }
@@ -95,3 +171,496 @@ pub struct EditFile {
     pub description: Option<String>,
     pub position:    i16,
 }
+
+// фильтры медиатеки; None - не фильтровать
+#[derive(Default)]
+pub struct MediaFilters {
+    pub user_id:    Option<i32>,
+    pub types:      Option<i16>,                   // фото, видео, аудио, документы
+    pub item_types: Option<i16>,                   // блог, услуга, товар ......
+    pub q:          Option<String>,                // подстрока описания или имени файла
+    pub date_from:  Option<chrono::NaiveDateTime>, // created >= date_from
+    pub date_to:    Option<chrono::NaiveDateTime>, // created < date_to
+    pub hide_messages: bool,                       // без вложений сообщений (item_types 11)
+}
+
+#[derive(Serialize)]
+pub struct MediaFile {
+    pub id:            i32,
+    pub user_id:       i32,
+    pub item_id:       i32,
+    pub item_types:    i16,
+    pub types:         i16,
+    pub src:           String,
+    pub description:   Option<String>,
+    pub position:      i16,
+    pub original_name: String,
+    pub mime:          String,
+    pub size:          i32,
+    pub width:         Option<i32>,
+    pub height:        Option<i32>,
+    pub created:       chrono::NaiveDateTime,
+    pub variants:      Vec<ImageVariant>,
+}
+
+impl File {
+    pub fn get_media_file(self) -> MediaFile {
+        let variants = match (self.width, self.height) {
+            (Some(width), Some(height)) => get_image_variants(&self.src, width, height),
+            _ => Vec::new(),
+        };
+        MediaFile {
+            id:            self.id,
+            user_id:       self.user_id,
+            item_id:       self.item_id,
+            item_types:    self.item_types,
+            types:         self.types,
+            src:           self.src,
+            description:   self.description,
+            position:      self.position,
+            original_name: self.original_name,
+            mime:          self.mime,
+            size:          self.size,
+            width:         self.width,
+            height:        self.height,
+            created:       self.created,
+            variants:      variants,
+        }
+    }
+    pub fn get_library(filters: &MediaFilters, page: i32, limit: i64) -> (Vec<MediaFile>, i16) {
+        // медиатека: все загрузки, новые сверху. берем на одну больше,
+        // чтобы узнать, есть ли следующая страница
+        use crate::schema::files::dsl::files;
+
+        let _connection = establish_connection();
+        let mut _query = files.into_boxed();
+        if let Some(user_id) = filters.user_id {
+            _query = _query.filter(schema::files::user_id.eq(user_id));
+        }
+        if let Some(types) = filters.types {
+            _query = _query.filter(schema::files::types.eq(types));
+        }
+        if let Some(item_types) = filters.item_types {
+            _query = _query.filter(schema::files::item_types.eq(item_types));
+        }
+        if filters.hide_messages {
+            _query = _query.filter(schema::files::item_types.ne(11));
+        }
+        if let Some(date_from) = filters.date_from {
+            _query = _query.filter(schema::files::created.ge(date_from));
+        }
+        if let Some(date_to) = filters.date_to {
+            _query = _query.filter(schema::files::created.lt(date_to));
+        }
+        if let Some(q) = filters.q.as_deref().filter(|q| !q.trim().is_empty()) {
+            let q = q.trim().replace("\\", "\\\\").replace("%", "\\%").replace("_", "\\_");
+            let pattern = "%".to_string() + &q + "%";
+            _query = _query.filter(
+                schema::files::description.ilike(pattern.clone())
+                    .or(schema::files::original_name.ilike(pattern))
+            );
+        }
+        let offset = (page.max(1) - 1) as i64 * limit;
+        let mut _files = _query
+            .order((schema::files::created.desc(), schema::files::id.desc()))
+            .limit(limit + 1)
+            .offset(offset)
+            .load::<File>(&_connection)
+            .expect("E.");
+
+        let mut next_page_number = 0;
+        if _files.len() as i64 > limit {
+            _files.truncate(limit as usize);
+            next_page_number = page.max(1) + 1;
+        }
+        let list = _files.into_iter().map(|f| f.get_media_file()).collect();
+        return (list, next_page_number as i16);
+    }
+    pub fn reuse(&self, user_id: i32, item_id: i32, item_types: i16) -> QueryResult<File> {
+        // тот же файл в галерее другого объекта: новая строка files
+        // с тем же адресом, ссылку в blobs добавит триггер.
+        // только файлы с types 1-4, см. reuse_file
+        use crate::schema::files::dsl::files;
+
+        let _connection = establish_connection();
+        let count = files
+            .filter(schema::files::item_id.eq(item_id))
+            .filter(schema::files::item_types.eq(item_types))
+            .count()
+            .get_result::<i64>(&_connection)?;
+        // позиция в SMALLINT: не заворачиваем в отрицательные
+        let position = i16::try_from(count)
+            .map_err(|e| diesel::result::Error::SerializationError(Box::new(e)))?;
+        let new_file = NewFile {
+            item_id:       item_id,
+            user_id:       user_id,
+            item_types:    item_types,
+            types:         self.types,
+            src:           self.src.clone(),
+            description:   self.description.clone(),
+            position:      position,
+            view:          0,
+            seconds:       0,
+            original_name: self.original_name.clone(),
+            mime:          self.mime.clone(),
+            size:          self.size,
+            width:         self.width,
+            height:        self.height,
+            created:       chrono::Local::now().naive_utc() + chrono::Duration::hours(3),
+        };
+        return diesel::insert_into(schema::files::table)
+            .values(&new_file)
+            .get_result::<File>(&_connection);
+    }
+    pub fn reorder(item_id: i32, item_types: i16, ids: &Vec<i32>) -> QueryResult<usize> {
+        // порядок галереи объекта: position = номер в ids.
+        // чужие id пропускаются, возвращает число переставленных файлов
+        let _connection = establish_connection();
+        return _connection.transaction(|| {
+            let mut count = 0;
+            for (position, id) in ids.iter().enumerate() {
+                let position = i16::try_from(position)
+                    .map_err(|e| diesel::result::Error::SerializationError(Box::new(e)))?;
+                count += diesel::update (
+                    schema::files::table
+                        .filter(schema::files::id.eq(id))
+                        .filter(schema::files::item_id.eq(item_id))
+                        .filter(schema::files::item_types.eq(item_types))
+                    )
+                    .set(schema::files::position.eq(position))
+                    .execute(&_connection)?;
+            }
+            Ok(count)
+        });
+    }
+}
+
+// обработанные загруженные картинки (files, items.image, categories.image и т.д.)
+#[derive(Debug, Serialize, Queryable, Insertable)]
+#[table_name="images"]
+pub struct Image {
+    pub src:    String,
+    pub width:  i32,
+    pub height: i32,
+}
+
+impl Image {
+    pub fn create(src: &str, width: i32, height: i32) {
+        let _connection = establish_connection();
+        diesel::insert_into(schema::images::table)
+            .values(&Image {
+                src:    src.to_string(),
+                width:  width,
+                height: height,
+            })
+            .on_conflict_do_nothing()
+            .execute(&_connection)
+            .expect("E.");
+    }
+    pub fn get(src: &str) -> Option<Image> {
+        use crate::schema::images::dsl::images;
+
+        let _connection = establish_connection();
+        return images
+            .filter(schema::images::src.eq(src))
+            .first::<Image>(&_connection)
+            .ok();
+    }
+    pub fn get_variants(src: &Option<String>) -> Vec<ImageVariant> {
+        // варианты картинки; пусто, если картинка не обрабатывалась
+        use crate::schema::images::dsl::images;
+
+        if src.is_none() {
+            return Vec::new();
+        }
+        let _connection = establish_connection();
+        return match images
+            .filter(schema::images::src.eq(src.as_deref().unwrap()))
+            .first::<Image>(&_connection) {
+                Ok(image) => get_image_variants(&image.src, image.width, image.height),
+                Err(_) => Vec::new(),
+            };
+    }
+    pub fn get_variants_list(srcs: Vec<String>) -> HashMap<String, Vec<ImageVariant>> {
+        // варианты картинок для списка одним запросом; нет в ответе -
+        // картинка не обрабатывалась
+        use crate::schema::images::dsl::images;
+
+        let mut list = HashMap::new();
+        if srcs.is_empty() {
+            return list;
+        }
+        let _connection = establish_connection();
+        let _images = images
+            .filter(schema::images::src.eq_any(srcs))
+            .load::<Image>(&_connection)
+            .expect("E.");
+        for image in _images.into_iter() {
+            let variants = get_image_variants(&image.src, image.width, image.height);
+            list.insert(image.src, variants);
+        }
+        return list;
+    }
+    pub fn fill_missing(storage: &dyn Storage) -> usize {
+        // картинки хранилища, загруженные до появления вариантов:
+        // готовим варианты, исходник не трогаем. возвращает число обработанных
+        use crate::schema::{
+            blobs::dsl::blobs,
+            items::dsl::items,
+            categories::dsl::categories,
+            files::dsl::files,
+            images::dsl::images,
+        };
+
+        let _connection = establish_connection();
+        let prefix = storage.url("") + "%";
+        let mut srcs = blobs
+            .filter(schema::blobs::src.like(&prefix))
+            .filter(schema::blobs::mime.like("image/%"))
+            .select(schema::blobs::src)
+            .load::<String>(&_connection)
+            .expect("E.");
+        srcs.extend(files
+            .filter(schema::files::src.like(&prefix))
+            .filter(schema::files::types.eq(1))
+            .select(schema::files::src)
+            .load::<String>(&_connection)
+            .expect("E."));
+        srcs.extend(items
+            .filter(schema::items::image.like(&prefix))
+            .select(schema::items::image)
+            .load::<Option<String>>(&_connection)
+            .expect("E.")
+            .into_iter()
+            .flatten());
+        srcs.extend(categories
+            .filter(schema::categories::image.like(&prefix))
+            .select(schema::categories::image)
+            .load::<Option<String>>(&_connection)
+            .expect("E.")
+            .into_iter()
+            .flatten());
+        srcs.sort();
+        srcs.dedup();
+
+        let done = images
+            .filter(schema::images::src.eq_any(&srcs))
+            .select(schema::images::src)
+            .load::<String>(&_connection)
+            .expect("E.");
+
+        let mut count = 0;
+        for src in srcs.iter().filter(|s| !done.contains(s)) {
+            let key = match storage.get_key(src) {
+                Some(key) => key,
+                None => continue,
+            };
+            let data = match storage.get(&key) {
+                Ok(data) => data,
+                Err(_) => continue,
+            };
+            let image = match process_image(&key, &data) {
+                Ok(image) => image,
+                Err(e) => {
+                    println!("images {}: {}", src, e);
+                    continue;
+                },
+            };
+            let saved = image.variants.iter()
+                .all(|(variant_key, variant_mime, variant_data)| storage.put(variant_key, variant_data, variant_mime).is_ok());
+            if !saved {
+                println!("images {}: variants not saved", src);
+                continue;
+            }
+            Image::create(src, image.width, image.height);
+            diesel::update(files
+                    .filter(schema::files::src.eq(src))
+                    .filter(schema::files::width.is_null())
+                )
+                .set((
+                    schema::files::width.eq(image.width),
+                    schema::files::height.eq(image.height),
+                ))
+                .execute(&_connection)
+                .expect("E.");
+            count += 1;
+        }
+        return count;
+    }
+}
+
+// файл в хранилище, общий для одинаковых загрузок (см. blobs в main.sql)
+#[derive(Debug, Serialize, Queryable, Insertable)]
+#[table_name="blobs"]
+pub struct Blob {
+    pub src:     String,
+    pub hash:    Option<String>,
+    pub user_id: i32,
+    pub mime:    String,
+    pub size:    i32,
+    pub refs:    i32,
+    pub pending: i32,
+    pub created: chrono::NaiveDateTime,
+}
+
+impl Blob {
+    pub fn get_by_hash(hash: &str, storage: &dyn Storage) -> Option<Blob> {
+        // только файлы из того же хранилища: закрытый файл не должен
+        // оказаться ссылкой на общий и наоборот
+        use crate::schema::blobs::dsl::blobs;
+
+        let _connection = establish_connection();
+        return blobs
+            .filter(schema::blobs::hash.eq(hash))
+            .filter(schema::blobs::src.like(storage.url("") + "%"))
+            .first::<Blob>(&_connection)
+            .ok();
+    }
+    pub fn pin(src: &str) -> Option<Blob> {
+        // найденный по хешу файл берется загрузкой: до сохранения ссылки
+        // release его не удалит. None - файл только что удалили,
+        // загрузку надо сохранить заново
+        let _connection = establish_connection();
+        return diesel::update(schema::blobs::table.filter(schema::blobs::src.eq(src)))
+            .set(schema::blobs::pending.eq(schema::blobs::pending + 1))
+            .get_result::<Blob>(&_connection)
+            .ok();
+    }
+    pub fn unpin(src: &str) {
+        // загрузка сохранила ссылку на взятый файл или отказалась от него
+        let _connection = establish_connection();
+        diesel::update(schema::blobs::table.filter(schema::blobs::src.eq(src)))
+            .set(schema::blobs::pending.eq(diesel::dsl::sql::<Integer>("GREATEST(pending - 1, 0)")))
+            .execute(&_connection)
+            .expect("E.");
+    }
+    pub fn create(src: &str, hash: &str, user_id: i32, mime: &str, size: i32) {
+        // ссылки появятся, когда адрес сохранят в files, items и т.д.,
+        // до этого файл отмечен как взятый загрузкой (pending): отметку
+        // снимает unpin после сохранения ссылки или при ошибке.
+        // вызывается до записи в хранилище: если release этого же адреса
+        // еще идет, вставка дождется его окончания
+        let _connection = establish_connection();
+        diesel::insert_into(schema::blobs::table)
+            .values(&Blob {
+                src:     src.to_string(),
+                hash:    Some(hash.to_string()),
+                user_id: user_id,
+                mime:    mime.to_string(),
+                size:    size,
+                refs:    0,
+                pending: 1,
+                created: chrono::Local::now().naive_utc(),
+            })
+            .on_conflict(schema::blobs::src)
+            .do_update()
+            .set(schema::blobs::pending.eq(schema::blobs::pending + 1))
+            .execute(&_connection)
+            .expect("E.");
+    }
+    pub fn release(storage: &dyn Storage, srcs: Vec<String>) {
+        // вызывается после удаления или замены ссылок на файлы:
+        // файлы, на которые больше никто не ссылается и которые не взяты
+        // загрузками (pending), удаляем из хранилища. строка blobs удаляется
+        // в той же транзакции, что и файл: pin и create того же адреса ждут ее
+        let _connection = establish_connection();
+        for src in srcs.iter().filter(|s| storage.get_key(s).is_some()) {
+            _connection.transaction::<_, diesel::result::Error, _>(|| {
+                let deleted = diesel::delete (
+                    schema::blobs::table
+                        .filter(schema::blobs::src.eq(src))
+                        .filter(schema::blobs::refs.le(0))
+                        .filter(schema::blobs::pending.le(0))
+                    )
+                    .execute(&_connection)?;
+                if deleted == 0 {
+                    return Ok(());
+                }
+                storage.delete_src(src).ok();
+                remove_image_variants(storage, src);
+                diesel::delete(schema::images::table.filter(schema::images::src.eq(src)))
+                    .execute(&_connection)?;
+                diesel::delete(schema::file_metadata::table.filter(schema::file_metadata::src.eq(src)))
+                    .execute(&_connection)?;
+                Ok(())
+            }).expect("E.");
+        }
+    }
+}
+
+// сведения о содержимом файла, общие для одинаковых загрузок (см. utils/metadata.rs)
+#[derive(Debug, Serialize, Deserialize, Clone, Queryable, Insertable)]
+#[table_name="file_metadata"]
+pub struct FileMetadata {
+    #[serde(skip)]
+    pub src:      String,
+    pub pages:    Option<i32>,
+    pub title:    Option<String>,
+    pub excerpt:  Option<String>,
+    pub duration: Option<i32>,
+}
+
+impl FileMetadata {
+    pub fn create(&self, _connection: &PgConnection) -> QueryResult<()> {
+        // сведения необязательны: ошибку вызывающий только пишет в лог
+        diesel::insert_into(schema::file_metadata::table)
+            .values(self)
+            .on_conflict_do_nothing()
+            .execute(_connection)?;
+        return Ok(());
+    }
+    pub fn get_list(_connection: &PgConnection, srcs: &Vec<String>) -> Vec<FileMetadata> {
+        use crate::schema::file_metadata::dsl::file_metadata;
+
+        if srcs.is_empty() {
+            return Vec::new();
+        }
+        return file_metadata
+            .filter(schema::file_metadata::src.eq_any(srcs))
+            .load::<FileMetadata>(_connection)
+            .expect("E.");
+    }
+    pub fn attach(_connection: &PgConnection, files: &mut Vec<SmallFile>) {
+        // одним запросом на весь список; у картинок сведений нет
+        let srcs = files.iter()
+            .filter(|f| f.variants.is_empty())
+            .map(|f| f.src.clone())
+            .collect::<Vec<String>>();
+        let list = FileMetadata::get_list(_connection, &srcs);
+        for file in files.iter_mut() {
+            file.meta = list.iter().find(|m| m.src == file.src).cloned();
+        }
+    }
+    pub fn fill_missing(storage: &dyn Storage) -> usize {
+        // файлы хранилища, загруженные до появления file_metadata.
+        // возвращает число разобранных
+        use crate::schema::blobs::dsl::blobs;
+
+        let _connection = establish_connection();
+        let _blobs = blobs
+            .filter(schema::blobs::src.like(storage.url("") + "%"))
+            .filter(schema::blobs::mime.ne(""))
+            .filter(schema::blobs::mime.not_like("image/%"))
+            .filter(schema::blobs::size.le(METADATA_MAX_SIZE as i32))
+            .load::<Blob>(&_connection)
+            .expect("E.");
+        let done = FileMetadata::get_list(&_connection, &_blobs.iter().map(|b| b.src.clone()).collect())
+            .into_iter()
+            .map(|m| m.src)
+            .collect::<Vec<String>>();
+
+        let mut count = 0;
+        for blob in _blobs.iter().filter(|b| !done.contains(&b.src)) {
+            let data = match storage.get_key(&blob.src).map(|key| storage.get(&key)) {
+                Some(Ok(data)) => data,
+                _ => continue,
+            };
+            if let Err(e) = extract_metadata(&blob.src, &blob.mime, &data).create(&_connection) {
+                println!("file_metadata {}: {}", blob.src, e);
+                continue;
+            }
+            count += 1;
+        }
+        return count;
+    }
+}
@@ -6,6 +6,9 @@ mod order;
 mod serve;
 mod media;
 mod chat;
+mod redirect;
+mod search;
+mod search_query;
 
 pub use self::{
     item::*,
@@ -16,4 +19,7 @@ pub use self::{
     order::*,
     media::*,
     chat::*,
+    redirect::*,
+    search::*,
+    search_query::*,
 };
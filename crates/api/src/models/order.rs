@@ -5,14 +5,24 @@ use crate::diesel::{
     QueryDsl,
     RunQueryDsl,
     ExpressionMethods,
+    TextExpressionMethods,
 };
 use serde::{Serialize, Deserialize};
-use crate::models::{Serve, TechCategories};
+use crate::models::{Serve, TechCategories, Blob};
 use crate::schema::{
     orders,
     order_files,
 };
-use crate::utils::establish_connection;
+use crate::utils::{
+    establish_connection,
+    extract_metadata,
+    get_hash_key,
+    sniff_mime,
+    Storage,
+    UploadedFiles,
+    METADATA_MAX_SIZE,
+};
+use sha2::{Digest, Sha256};
 
 
 #[derive(Debug, Serialize, Identifiable, Queryable, Associations)]
@@ -197,23 +207,91 @@ pub struct EditOrder {
 
 #[derive(Debug, Serialize, Queryable, Identifiable, Associations)]
 pub struct OrderFile {
-    pub id:       i32,
-    pub order_id: i32,
-    pub src:      String,
+    pub id:            i32,
+    pub order_id:      i32,
+    pub src:           String,
+    pub original_name: String,
+    pub mime:          String,
+    pub size:          i32,
+}
+
+impl OrderFile {
+    pub fn move_to_private(storage: &dyn Storage, private_storage: &dyn Storage) -> usize {
+        // вложения, загруженные до закрытого хранилища, лежат в общем по
+        // угадываемым адресам. переносим их в закрытое под хешем содержимого,
+        // старый файл удаляет Blob::release. возвращает число перенесенных
+        use crate::schema::order_files::dsl::order_files;
+
+        let _connection = establish_connection();
+        let _files = order_files
+            .filter(schema::order_files::src.like(storage.url("") + "%"))
+            .load::<OrderFile>(&_connection)
+            .expect("E.");
+
+        let mut count = 0;
+        for file in _files.iter() {
+            let data = match storage.get_key(&file.src).map(|key| storage.get(&key)) {
+                Some(Ok(data)) => data,
+                _ => {
+                    println!("order_files id={}: {} not found", file.id, file.src);
+                    continue;
+                },
+            };
+            let ext = match sniff_mime(&data, &file.original_name) {
+                Some((_, ext)) => ext,
+                None => "bin".to_string(),
+            };
+            let hash = format!("{:x}", Sha256::digest(&data));
+            let key = get_hash_key(&hash, &ext);
+            if private_storage.put(&key, &data, &file.mime).is_err() {
+                println!("order_files id={}: {} not saved", file.id, file.src);
+                continue;
+            }
+            let src = private_storage.url(&key);
+            let user_id = schema::orders::table
+                .filter(schema::orders::id.eq(file.order_id))
+                .select(schema::orders::user_id)
+                .first::<i32>(&_connection)
+                .unwrap_or(0);
+            Blob::create(&src, &hash, user_id, &file.mime, data.len() as i32);
+            if !file.mime.starts_with("image/") && data.len() <= METADATA_MAX_SIZE {
+                if let Err(e) = extract_metadata(&src, &file.mime, &data).create(&_connection) {
+                    println!("file_metadata {}: {}", src, e);
+                }
+            }
+
+            // ссылки в blobs переносит триггер
+            diesel::update(order_files.filter(schema::order_files::id.eq(file.id)))
+                .set(schema::order_files::src.eq(&src))
+                .execute(&_connection)
+                .expect("E.");
+            // ссылка сохранена, снимаем отметку Blob::create
+            Blob::unpin(&src);
+            Blob::release(storage, vec![file.src.clone()]);
+            count += 1;
+        }
+        return count;
+    }
 }
 
 #[derive(Serialize, Insertable)]
 #[table_name="order_files"]
 pub struct NewOrderFile {
-    pub order_id: i32,
-    pub src:      String,
+    pub order_id:      i32,
+    pub src:           String,
+    pub original_name: String,
+    pub mime:          String,
+    pub size:          i32,
 }
 
 impl NewOrderFile {
-    pub fn create (order_id: i32, src: String) -> Self {
+    pub fn create (order_id: i32, file: &UploadedFiles) -> Self {
         NewOrderFile {
-            order_id: order_id,
-            src:      src,
+            order_id:      order_id,
+            src:           file.get_src(),
+            original_name: file.name.clone(),
+            mime:          file.mime.clone(),
+            size:          file.size,
         }
     }
 }
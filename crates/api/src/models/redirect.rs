@@ -0,0 +1,86 @@
+use crate::schema;
+use crate::diesel::{
+    Queryable,
+    Insertable,
+    QueryDsl,
+    RunQueryDsl,
+    ExpressionMethods,
+    PgConnection,
+    QueryResult,
+};
+use serde::Serialize;
+use crate::schema::slug_redirects;
+use crate::utils::establish_connection;
+
+
+///////////
+// types:
+// 1. тег
+// 2. категория
+#[derive(Debug, Serialize, Queryable, Identifiable)]
+#[table_name="slug_redirects"]
+pub struct SlugRedirect {
+    pub id:       i32,
+    pub types:    i16,
+    pub old_slug: String,
+    pub new_id:   i32,
+    pub created:  chrono::NaiveDateTime,
+}
+
+impl SlugRedirect {
+    pub fn get_new_id(types: i16, old_slug: &str) -> Option<i32> {
+        // id тега / категории, в которую влили объект со старым slug
+        use crate::schema::slug_redirects::dsl::slug_redirects;
+
+        let _connection = establish_connection();
+        return slug_redirects
+            .filter(schema::slug_redirects::types.eq(types))
+            .filter(schema::slug_redirects::old_slug.eq(old_slug))
+            .select(schema::slug_redirects::new_id)
+            .first::<i32>(&_connection)
+            .ok();
+    }
+    pub fn create (
+        _connection: &PgConnection,
+        types:       i16,
+        old_slug:    String,
+        old_id:      i32,
+        new_id:      i32,
+    ) -> QueryResult<()> {
+        // запоминаем старый slug. прежние перенаправления на влитый
+        // объект тоже переводим на новый, чтобы не было цепочек
+        use crate::schema::slug_redirects::dsl::slug_redirects;
+
+        diesel::update(slug_redirects
+            .filter(schema::slug_redirects::types.eq(types))
+            .filter(schema::slug_redirects::new_id.eq(old_id))
+        )
+            .set(schema::slug_redirects::new_id.eq(new_id))
+            .execute(_connection)?;
+        diesel::delete(slug_redirects
+            .filter(schema::slug_redirects::types.eq(types))
+            .filter(schema::slug_redirects::old_slug.eq(&old_slug))
+        )
+            .execute(_connection)?;
+
+        let _new_redirect = NewSlugRedirect {
+            types:    types,
+            old_slug: old_slug,
+            new_id:   new_id,
+            created:  chrono::Local::now().naive_utc(),
+        };
+        diesel::insert_into(schema::slug_redirects::table)
+            .values(&_new_redirect)
+            .execute(_connection)?;
+        return Ok(());
+    }
+}
+
+#[derive(Insertable)]
+#[table_name="slug_redirects"]
+pub struct NewSlugRedirect {
+    pub types:    i16,
+    pub old_slug: String,
+    pub new_id:   i32,
+    pub created:  chrono::NaiveDateTime,
+}
@@ -0,0 +1,490 @@
+use crate::diesel::RunQueryDsl;
+use diesel::sql_types::{
+    Array,
+    BigInt,
+    Bool,
+    Float4,
+    Integer,
+    Nullable,
+    SmallInt,
+    Text,
+    Timestamp,
+    Varchar,
+};
+use serde::Serialize;
+use crate::utils::{establish_connection, xml_escape};
+
+
+// поиск идет по таблице item_search_index (см. main.sql): документ
+// объекта собирается из названия, описания, тегов и блоков контента,
+// запрос разбирается websearch_to_tsquery ("услуги сайт -магазин", "фразы в кавычках").
+// ищутся объекты типов 1-6, заказы в поиск не попадают.
+#[derive(Serialize, QueryableByName)]
+pub struct SearchItem {
+    #[sql_type = "Integer"]
+    pub id:            i32,
+    #[sql_type = "Varchar"]
+    pub slug:          String,
+    #[sql_type = "Nullable<Varchar>"]
+    pub image:         Option<String>,
+    #[sql_type = "Nullable<Varchar>"]
+    pub description:   Option<String>,
+    #[sql_type = "SmallInt"]
+    pub item_types:    i16,
+    #[sql_type = "SmallInt"]
+    pub types:         i16,
+    #[sql_type = "Varchar"]
+    pub title:         String,
+    #[sql_type = "Timestamp"]
+    pub created:       chrono::NaiveDateTime,
+    #[sql_type = "Float4"]
+    pub rank:          f32,
+    // поле, по которому объект найден: content, description, title, tags
+    #[sql_type = "Varchar"]
+    pub matched_field: String,
+    // фрагмент этого поля, найденные слова обернуты в <mark></mark>,
+    // остальной текст экранирован (см. get_snippet_html)
+    #[sql_type = "Text"]
+    pub snippet:       String,
+}
+
+// фильтры поиска и списков категорий. внутри фильтра значения
+// объединяются через ИЛИ, разные фильтры - через И.
+// пустой список / None - фильтр не задан.
+#[derive(Default, Clone)]
+pub struct SearchFilters {
+    pub scope_cat_ids: Vec<i32>,                      // категория страницы списка с подкатегориями
+    pub cat_ids:       Vec<i32>,                      // выбранные категории с подкатегориями
+    pub tag_ids:       Vec<i32>,
+    pub tech_cat_ids:  Vec<i32>,                      // тех. категории из tech_categories_items
+    pub price_min:     Option<i32>,                   // цена со скидкой (price - price_acc), только типы 2, 3, 5
+    pub price_max:     Option<i32>,
+    pub date_from:     Option<chrono::NaiveDateTime>, // created >= date_from
+    pub date_to:       Option<chrono::NaiveDateTime>, // created < date_to
+    // объекты, найденные встроенным индексом (см. SearchBackend), и их
+    // релевантность; если заданы - заменяют полнотекстовое условие Postgres
+    pub index_ids:     Option<Vec<i32>>,
+    pub index_ranks:   Vec<f32>,
+}
+impl SearchFilters {
+    pub fn is_empty(&self) -> bool {
+        return self.cat_ids.is_empty()
+            && self.tag_ids.is_empty()
+            && self.tech_cat_ids.is_empty()
+            && self.price_min.is_none()
+            && self.price_max.is_none()
+            && self.date_from.is_none()
+            && self.date_to.is_none();
+    }
+}
+
+// значение фасета: facet - category / tag / tech_category,
+// count - число найденных объектов с этим значением
+#[derive(Serialize, QueryableByName)]
+pub struct FacetValue {
+    #[sql_type = "Varchar"]
+    pub facet: String,
+    #[sql_type = "Integer"]
+    pub id:    i32,
+    #[sql_type = "Varchar"]
+    pub name:  String,
+    #[sql_type = "BigInt"]
+    pub count: i64,
+}
+
+#[derive(Serialize, QueryableByName)]
+pub struct FacetRanges {
+    #[sql_type = "Nullable<Integer>"]
+    pub price_min: Option<i32>,
+    #[sql_type = "Nullable<Integer>"]
+    pub price_max: Option<i32>,
+    #[sql_type = "Nullable<Timestamp>"]
+    pub date_min:  Option<chrono::NaiveDateTime>,
+    #[sql_type = "Nullable<Timestamp>"]
+    pub date_max:  Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Serialize)]
+pub struct SearchFacets {
+    pub categories:      Vec<FacetValue>,
+    pub tags:            Vec<FacetValue>,
+    pub tech_categories: Vec<FacetValue>,
+    pub ranges:          FacetRanges,
+}
+
+#[derive(QueryableByName)]
+pub struct SearchWord {
+    #[sql_type = "Varchar"]
+    pub word: String,
+}
+
+// подсказка строки поиска: kind - item, category или tag.
+// у тегов types = 0 и нет картинки
+#[derive(Serialize, QueryableByName)]
+pub struct SearchSuggestion {
+    #[sql_type = "Varchar"]
+    pub kind:  String,
+    #[sql_type = "Integer"]
+    pub id:    i32,
+    #[sql_type = "Varchar"]
+    pub title: String,
+    #[sql_type = "Varchar"]
+    pub slug:  String,
+    #[sql_type = "SmallInt"]
+    pub types: i16,
+    #[sql_type = "Nullable<Varchar>"]
+    pub image: Option<String>,
+}
+
+// текст объекта для внешнего индекса (см. SearchBackend)
+#[derive(QueryableByName)]
+pub struct SearchDocument {
+    #[sql_type = "Integer"]
+    pub id:          i32,
+    #[sql_type = "Varchar"]
+    pub title:       String,
+    #[sql_type = "Text"]
+    pub description: String,
+    #[sql_type = "Text"]
+    pub tags:        String,
+    #[sql_type = "Text"]
+    pub content:     String,
+}
+
+#[derive(Serialize, QueryableByName)]
+pub struct SearchTypeCount {
+    #[sql_type = "SmallInt"]
+    pub types: i16,
+    #[sql_type = "BigInt"]
+    pub count: i64,
+}
+
+// найденные объекты: $1 - q (пустой q - без текстового условия),
+// $2 - types (0 - все), $3 - is_admin, $4-$11 - фильтры,
+// $12-$14 - результаты встроенного индекса
+const MATCHED_SQL: &str = "
+    WITH query AS (
+        SELECT websearch_to_tsquery('russian', $1) AS q
+    ),
+    matched AS (
+        SELECT i.id, CASE
+            WHEN $12 THEN ($14::REAL[])[array_position($13::INT[], i.id)]
+            WHEN $1 = '' THEN 0
+            ELSE ts_rank_cd(s.document, query.q)
+        END AS rank
+        FROM items i
+        INNER JOIN item_search_index s ON s.item_id = i.id
+        CROSS JOIN query
+        WHERE (CASE WHEN $12 THEN i.id = ANY($13) ELSE ($1 = '' OR s.document @@ query.q) END)
+        AND i.types BETWEEN 1 AND 6
+        AND ($2 = 0 OR i.types = $2)
+        AND ($3 OR i.item_types < 10)
+        AND (CARDINALITY($4::INT[]) = 0 OR i.id IN (
+            SELECT l.item_id FROM category l WHERE l.categories_id = ANY($4)
+        ))
+        AND (CARDINALITY($5::INT[]) = 0 OR i.id IN (
+            SELECT l.item_id FROM category l WHERE l.categories_id = ANY($5)
+        ))
+        AND (CARDINALITY($6::INT[]) = 0 OR i.id IN (
+            SELECT l.item_id FROM tags_items l WHERE l.tag_id = ANY($6)
+        ))
+        AND (CARDINALITY($7::INT[]) = 0 OR i.id IN (
+            SELECT l.item_id FROM tech_categories_items l WHERE l.category_id = ANY($7)
+        ))
+        AND ($8::INT IS NULL OR (i.types IN (2, 3, 5) AND i.price - COALESCE(i.price_acc, 0) >= $8))
+        AND ($9::INT IS NULL OR (i.types IN (2, 3, 5) AND i.price - COALESCE(i.price_acc, 0) <= $9))
+        AND ($10::TIMESTAMP IS NULL OR i.created >= $10)
+        AND ($11::TIMESTAMP IS NULL OR i.created < $11)
+    )
+";
+
+// привязка параметров MATCHED_SQL
+macro_rules! bind_matched {
+    ($query:expr, $q:expr, $types:expr, $is_admin:expr, $filters:expr) => {
+        $query
+            .bind::<Text, _>($q)
+            .bind::<SmallInt, _>($types)
+            .bind::<Bool, _>($is_admin)
+            .bind::<Array<Integer>, _>($filters.scope_cat_ids.clone())
+            .bind::<Array<Integer>, _>($filters.cat_ids.clone())
+            .bind::<Array<Integer>, _>($filters.tag_ids.clone())
+            .bind::<Array<Integer>, _>($filters.tech_cat_ids.clone())
+            .bind::<Nullable<Integer>, _>($filters.price_min)
+            .bind::<Nullable<Integer>, _>($filters.price_max)
+            .bind::<Nullable<Timestamp>, _>($filters.date_from)
+            .bind::<Nullable<Timestamp>, _>($filters.date_to)
+            .bind::<Bool, _>($filters.index_ids.is_some())
+            .bind::<Array<Integer>, _>($filters.index_ids.clone().unwrap_or_default())
+            .bind::<Array<Float4>, _>($filters.index_ranks.clone())
+    };
+}
+
+fn get_snippet_html(snippet: &str) -> String {
+    // ts_headline отдает текст как есть: в названии, описании и тегах
+    // может быть разметка. найденные слова он отмечает символами
+    // U+E000 / U+E001, весь текст экранируем и только их меняем на <mark>.
+    // контент приходит без тегов, но с сущностями html - их раскрываем
+    // до экранирования, чтобы не показывать "&amp;nbsp;"
+    let text = snippet
+        .replace("&nbsp;", " ")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&");
+    return xml_escape(&text)
+        .replace('\u{E000}', "<mark>")
+        .replace('\u{E001}', "</mark>");
+}
+
+impl SearchItem {
+    pub fn search (
+        q:        &str,
+        types:    i16,
+        filters:  &SearchFilters,
+        limit:    i64,
+        offset:   i64,
+        is_admin: bool
+    ) -> Vec<SearchItem> {
+        // types = 0 - все типы. самые релевантные первыми, при равенстве - новые.
+        // фрагмент берется из первого подходящего блока контента, иначе
+        // из описания, названия или тегов; строится только для страницы выдачи
+        let _connection = establish_connection();
+        let _query = diesel::sql_query(MATCHED_SQL.to_string() + "
+            , hits AS (
+                SELECT i.id, i.slug, i.image, i.description, i.item_types, i.types,
+                i.title, i.created, m.rank
+                FROM matched m
+                INNER JOIN items i ON i.id = m.id
+                ORDER BY m.rank DESC, i.created DESC
+                LIMIT $15 OFFSET $16
+            )
+            SELECT h.id, h.slug, h.image, h.description, h.item_types, h.types,
+            h.title, h.created, h.rank::REAL AS rank,
+            COALESCE(f.field, 'title')::VARCHAR AS matched_field,
+            ts_headline('russian', COALESCE(f.text, h.title), query.q,
+                'StartSel=\u{E000}, StopSel=\u{E001}, MaxWords=35, MinWords=15, MaxFragments=2, FragmentDelimiter=\" … \"'
+            ) AS snippet
+            FROM hits h
+            CROSS JOIN query
+            LEFT JOIN LATERAL (
+                SELECT x.field, x.text FROM (
+                    SELECT 1 AS n, 'content' AS field, (
+                        SELECT c.title || ' ' || regexp_replace(c.content, '<[^>]*>', ' ', 'g')
+                        FROM item_contents c
+                        WHERE c.item_id = h.id
+                        AND to_tsvector('russian', c.title || ' ' || regexp_replace(c.content, '<[^>]*>', ' ', 'g')) @@ query.q
+                        ORDER BY c.position
+                        LIMIT 1
+                    ) AS text
+                    UNION ALL
+                    SELECT 2, 'description', h.description
+                    UNION ALL
+                    SELECT 3, 'title', h.title
+                    UNION ALL
+                    SELECT 4, 'tags', (
+                        SELECT string_agg(t.name, ', ') FROM tags t
+                        INNER JOIN tags_items l ON l.tag_id = t.id
+                        WHERE l.item_id = h.id
+                    )
+                ) x
+                WHERE x.text IS NOT NULL AND to_tsvector('russian', x.text) @@ query.q
+                ORDER BY x.n
+                LIMIT 1
+            ) f ON TRUE
+            ORDER BY h.rank DESC, h.created DESC
+        ");
+        let mut list = bind_matched!(_query, q, types, is_admin, filters)
+            .bind::<BigInt, _>(limit)
+            .bind::<BigInt, _>(offset)
+            .load::<SearchItem>(&_connection)
+            .expect("E.");
+        for item in list.iter_mut() {
+            item.snippet = get_snippet_html(&item.snippet);
+        }
+        return list;
+    }
+    pub fn get_counts(q: &str, filters: &SearchFilters, is_admin: bool) -> Vec<SearchTypeCount> {
+        // число найденных объектов по типам
+        let _connection = establish_connection();
+        let _query = diesel::sql_query(MATCHED_SQL.to_string() + "
+            SELECT i.types, COUNT(*) AS count
+            FROM matched m
+            INNER JOIN items i ON i.id = m.id
+            GROUP BY i.types
+            ORDER BY i.types
+        ");
+        return bind_matched!(_query, q, 0, is_admin, filters)
+            .load::<SearchTypeCount>(&_connection)
+            .expect("E.");
+    }
+    pub fn get_facets(q: &str, types: i16, filters: &SearchFilters, is_admin: bool) -> SearchFacets {
+        // значения фасетов и диапазоны цен / дат среди найденных объектов
+        let _connection = establish_connection();
+        let _query = diesel::sql_query(MATCHED_SQL.to_string() + "
+            SELECT 'category'::VARCHAR AS facet, c.id, c.name::VARCHAR AS name,
+            COUNT(DISTINCT l.item_id) AS count
+            FROM matched m
+            INNER JOIN category l ON l.item_id = m.id
+            INNER JOIN categories c ON c.id = l.categories_id
+            GROUP BY c.id
+            UNION ALL
+            SELECT 'tag'::VARCHAR, t.id, t.name::VARCHAR, COUNT(DISTINCT l.item_id)
+            FROM matched m
+            INNER JOIN tags_items l ON l.item_id = m.id
+            INNER JOIN tags t ON t.id = l.tag_id
+            GROUP BY t.id
+            UNION ALL
+            SELECT 'tech_category'::VARCHAR, tc.id, tc.name::VARCHAR, COUNT(DISTINCT l.item_id)
+            FROM matched m
+            INNER JOIN tech_categories_items l ON l.item_id = m.id
+            INNER JOIN tech_categories tc ON tc.id = l.category_id
+            GROUP BY tc.id
+            ORDER BY facet, count DESC, name
+        ");
+        let values = bind_matched!(_query, q, types, is_admin, filters)
+            .load::<FacetValue>(&_connection)
+            .expect("E.");
+
+        let _query = diesel::sql_query(MATCHED_SQL.to_string() + "
+            SELECT
+            MIN(i.price - COALESCE(i.price_acc, 0)) FILTER (WHERE i.types IN (2, 3, 5)) AS price_min,
+            MAX(i.price - COALESCE(i.price_acc, 0)) FILTER (WHERE i.types IN (2, 3, 5)) AS price_max,
+            MIN(i.created) AS date_min,
+            MAX(i.created) AS date_max
+            FROM matched m
+            INNER JOIN items i ON i.id = m.id
+        ");
+        let ranges = bind_matched!(_query, q, types, is_admin, filters)
+            .get_result::<FacetRanges>(&_connection)
+            .expect("E.");
+
+        let mut facets = SearchFacets {
+            categories:      Vec::new(),
+            tags:            Vec::new(),
+            tech_categories: Vec::new(),
+            ranges:          ranges,
+        };
+        for value in values.into_iter() {
+            match value.facet.as_str() {
+                "category" => facets.categories.push(value),
+                "tag" => facets.tags.push(value),
+                _ => facets.tech_categories.push(value),
+            };
+        }
+        return facets;
+    }
+    pub fn get_did_you_mean(q: &str) -> Option<String> {
+        // каждое слово запроса заменяется самым похожим словом словаря
+        // (триграммы, порог pg_trgm.similarity_threshold). None - если
+        // исправлять нечего
+        let _connection = establish_connection();
+        let mut words = Vec::new();
+        let mut is_changed = false;
+        for word in q.to_lowercase().split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()) {
+            let _word = diesel::sql_query("
+                SELECT w.word FROM search_words w
+                WHERE w.word % $1
+                ORDER BY similarity(w.word, $1) DESC, w.ndoc DESC
+                LIMIT 1
+            ")
+                .bind::<Text, _>(word)
+                .get_result::<SearchWord>(&_connection);
+            match _word {
+                Ok(w) if w.word != word && word.chars().count() > 2 => {
+                    words.push(w.word);
+                    is_changed = true;
+                },
+                _ => words.push(word.to_string()),
+            };
+        }
+        if !is_changed {
+            return None;
+        }
+        return Some(words.join(" "));
+    }
+    pub fn get_suggestions(q: &str, limit: i64) -> Vec<SearchSuggestion> {
+        // до limit опубликованных объектов каждого типа, категорий и тегов,
+        // у которых с q начинается название или одно из его слов.
+        // совпавшие с начала названия - первыми, дальше по популярности:
+        // объекты и категории по просмотрам, теги по числу объектов
+        let q = q.trim().to_lowercase().replace("\\", "\\\\").replace("%", "\\%").replace("_", "\\_");
+        let _connection = establish_connection();
+        return diesel::sql_query("
+            SELECT s.kind, s.id, s.title, s.slug, s.types, s.image FROM (
+                SELECT 'item'::VARCHAR AS kind, i.id, i.title, i.slug, i.types, i.image,
+                ROW_NUMBER() OVER (
+                    PARTITION BY i.types
+                    ORDER BY lower(i.title) LIKE $1 || '%' DESC, i.view DESC, i.id
+                ) AS n
+                FROM items i
+                WHERE i.types BETWEEN 1 AND 6 AND i.item_types < 10
+                AND (lower(i.title) LIKE $1 || '%' OR lower(i.title) LIKE '% ' || $1 || '%')
+                UNION ALL
+                SELECT 'category', c.id, c.name, c.slug, c.types, c.image,
+                ROW_NUMBER() OVER (
+                    ORDER BY lower(c.name) LIKE $1 || '%' DESC, c.view DESC, c.id
+                )
+                FROM categories c
+                WHERE lower(c.name) LIKE $1 || '%' OR lower(c.name) LIKE '% ' || $1 || '%'
+                UNION ALL
+                SELECT 'tag', t.id, t.name, t.slug, 0::SMALLINT, NULL::VARCHAR,
+                ROW_NUMBER() OVER (
+                    ORDER BY lower(t.name) LIKE $1 || '%' DESC, t.count DESC, t.id
+                )
+                FROM tags t
+                WHERE lower(t.name) LIKE $1 || '%' OR lower(t.name) LIKE '% ' || $1 || '%'
+            ) s
+            WHERE s.n <= $2
+            ORDER BY s.kind, s.types, s.n
+        ")
+            .bind::<Text, _>(q)
+            .bind::<BigInt, _>(limit)
+            .load::<SearchSuggestion>(&_connection)
+            .expect("E.");
+    }
+    pub fn get_documents(item_id: Option<i32>) -> Vec<SearchDocument> {
+        // None - все объекты. отбор по типу и публикации делает MATCHED_SQL,
+        // поэтому в индекс идут и скрытые объекты
+        let _connection = establish_connection();
+        return diesel::sql_query("
+            SELECT i.id, i.title,
+            COALESCE(i.description, '') AS description,
+            COALESCE((
+                SELECT string_agg(t.name, ' ') FROM tags t
+                INNER JOIN tags_items l ON l.tag_id = t.id
+                WHERE l.item_id = i.id
+            ), '') AS tags,
+            COALESCE((
+                SELECT string_agg(c.title || ' ' || regexp_replace(c.content, '<[^>]*>', ' ', 'g'), ' ')
+                FROM item_contents c WHERE c.item_id = i.id
+            ), '') AS content
+            FROM items i
+            WHERE i.types BETWEEN 1 AND 6
+            AND ($1::INT IS NULL OR i.id = $1)
+        ")
+            .bind::<Nullable<Integer>, _>(item_id)
+            .load::<SearchDocument>(&_connection)
+            .expect("E.");
+    }
+    pub fn refresh_index() -> usize {
+        // пересобрать item_search_index и словарь опечаток целиком
+        let _connection = establish_connection();
+        let count = diesel::sql_query("
+            SELECT refresh_item_search_index(i.id) FROM items i
+        ")
+            .execute(&_connection)
+            .expect("E.");
+        diesel::sql_query("SELECT refresh_search_words()")
+            .execute(&_connection)
+            .expect("E.");
+        return count;
+    }
+    pub fn refresh_words() {
+        // пересобрать словарь для исправления опечаток
+        let _connection = establish_connection();
+        diesel::sql_query("SELECT refresh_search_words()")
+            .execute(&_connection)
+            .expect("E.");
+    }
+}
@@ -0,0 +1,217 @@
+use crate::schema;
+use crate::diesel::{
+    Queryable,
+    Insertable,
+    QueryDsl,
+    RunQueryDsl,
+    ExpressionMethods,
+};
+use diesel::sql_types::{
+    BigInt,
+    Float8,
+    Integer,
+    Timestamp,
+    Varchar,
+};
+use serde::Serialize;
+use crate::schema::{
+    search_queries,
+    search_clicks,
+};
+use crate::utils::establish_connection;
+
+
+#[derive(Debug, Serialize, Queryable, Identifiable)]
+#[table_name="search_queries"]
+pub struct SearchQuery {
+    pub id:          i32,
+    pub user_id:     i32,
+    pub q:           String,
+    pub types:       i16,
+    pub page:        i32,
+    pub results:     i32,
+    pub corrected_q: Option<String>,
+    pub created:     chrono::NaiveDateTime,
+}
+
+// статистика запроса за период: searches - сколько раз искали,
+// results - сколько находилось в последний раз, clicks - переходов из выдачи,
+// ctr - доля поисков, после которых был хотя бы один переход
+#[derive(Serialize, QueryableByName)]
+pub struct SearchQueryStat {
+    #[sql_type = "Varchar"]
+    pub q:        String,
+    #[sql_type = "BigInt"]
+    pub searches: i64,
+    #[sql_type = "Integer"]
+    pub results:  i32,
+    #[sql_type = "BigInt"]
+    pub clicks:   i64,
+    #[sql_type = "Float8"]
+    pub ctr:      f64,
+}
+
+#[derive(Serialize, QueryableByName)]
+pub struct SearchCtrStat {
+    #[sql_type = "BigInt"]
+    pub searches:         i64,
+    #[sql_type = "BigInt"]
+    pub clicked_searches: i64,
+    #[sql_type = "BigInt"]
+    pub clicks:           i64,
+    #[sql_type = "Float8"]
+    pub ctr:              f64,
+}
+
+// в отчеты идут только первые страницы выдачи, чтобы листание
+// не считалось повторным поиском
+const QUERY_STATS_SQL: &str = "
+    SELECT s.q::VARCHAR AS q, COUNT(*) AS searches,
+    (ARRAY_AGG(s.results ORDER BY s.created DESC))[1] AS results,
+    COALESCE(SUM(c.clicks), 0)::BIGINT AS clicks,
+    COUNT(c.clicks)::FLOAT8 / COUNT(*) AS ctr
+    FROM search_queries s
+    LEFT JOIN (
+        SELECT search_id, COUNT(*) AS clicks FROM search_clicks GROUP BY search_id
+    ) c ON c.search_id = s.id
+    WHERE s.page = 1 AND s.created >= $1 AND s.created < $2
+    GROUP BY s.q
+";
+
+impl SearchQuery {
+    pub fn create (
+        user_id:     i32,
+        q:           &str,
+        types:       i16,
+        page:        i32,
+        results:     i32,
+        corrected_q: Option<String>,
+    ) -> i32 {
+        // запоминаем запрос, id отдается в выдаче как search_id
+        let _connection = establish_connection();
+        let _q: String = q.trim().to_lowercase().chars().take(200).collect();
+        let new_query = NewSearchQuery {
+            user_id:     user_id,
+            q:           _q,
+            types:       types,
+            page:        page,
+            results:     results,
+            corrected_q: corrected_q,
+            created:     chrono::Local::now().naive_utc() + chrono::Duration::hours(3),
+        };
+        return diesel::insert_into(schema::search_queries::table)
+            .values(&new_query)
+            .returning(schema::search_queries::id)
+            .get_result::<i32>(&_connection)
+            .expect("E.");
+    }
+    pub fn create_click(search_id: i32, item_id: i32) {
+        // переход из выдачи; неизвестный search_id пропускаем
+        use crate::schema::search_queries::dsl::search_queries;
+
+        let _connection = establish_connection();
+        let is_exists = search_queries
+            .filter(schema::search_queries::id.eq(search_id))
+            .select(schema::search_queries::id)
+            .first::<i32>(&_connection)
+            .is_ok();
+        if !is_exists {
+            return;
+        }
+        let new_click = NewSearchClick {
+            search_id: search_id,
+            item_id:   item_id,
+            created:   chrono::Local::now().naive_utc() + chrono::Duration::hours(3),
+        };
+        diesel::insert_into(schema::search_clicks::table)
+            .values(&new_click)
+            .execute(&_connection)
+            .expect("E.");
+    }
+    pub fn get_top (
+        date_from: chrono::NaiveDateTime,
+        date_to:   chrono::NaiveDateTime,
+        limit:     i64,
+    ) -> Vec<SearchQueryStat> {
+        // самые частые запросы за период
+        let _connection = establish_connection();
+        return diesel::sql_query(QUERY_STATS_SQL.to_string() + "
+            ORDER BY searches DESC, q
+            LIMIT $3
+        ")
+            .bind::<Timestamp, _>(date_from)
+            .bind::<Timestamp, _>(date_to)
+            .bind::<BigInt, _>(limit)
+            .load::<SearchQueryStat>(&_connection)
+            .expect("E.");
+    }
+    pub fn get_zero_results (
+        date_from: chrono::NaiveDateTime,
+        date_to:   chrono::NaiveDateTime,
+        limit:     i64,
+    ) -> Vec<SearchQueryStat> {
+        // частые запросы, которые сейчас ничего не находят
+        let _connection = establish_connection();
+        return diesel::sql_query(QUERY_STATS_SQL.to_string() + "
+            HAVING (ARRAY_AGG(s.results ORDER BY s.created DESC))[1] = 0
+            ORDER BY searches DESC, q
+            LIMIT $3
+        ")
+            .bind::<Timestamp, _>(date_from)
+            .bind::<Timestamp, _>(date_to)
+            .bind::<BigInt, _>(limit)
+            .load::<SearchQueryStat>(&_connection)
+            .expect("E.");
+    }
+    pub fn get_ctr (
+        date_from: chrono::NaiveDateTime,
+        date_to:   chrono::NaiveDateTime,
+    ) -> SearchCtrStat {
+        // общая доля поисков с переходом за период
+        let _connection = establish_connection();
+        return diesel::sql_query("
+            SELECT COUNT(*) AS searches,
+            COUNT(c.clicks) AS clicked_searches,
+            COALESCE(SUM(c.clicks), 0)::BIGINT AS clicks,
+            CASE WHEN COUNT(*) = 0 THEN 0 ELSE COUNT(c.clicks)::FLOAT8 / COUNT(*) END AS ctr
+            FROM search_queries s
+            LEFT JOIN (
+                SELECT search_id, COUNT(*) AS clicks FROM search_clicks GROUP BY search_id
+            ) c ON c.search_id = s.id
+            WHERE s.page = 1 AND s.created >= $1 AND s.created < $2
+        ")
+            .bind::<Timestamp, _>(date_from)
+            .bind::<Timestamp, _>(date_to)
+            .get_result::<SearchCtrStat>(&_connection)
+            .expect("E.");
+    }
+}
+
+#[derive(Insertable)]
+#[table_name="search_queries"]
+pub struct NewSearchQuery {
+    pub user_id:     i32,
+    pub q:           String,
+    pub types:       i16,
+    pub page:        i32,
+    pub results:     i32,
+    pub corrected_q: Option<String>,
+    pub created:     chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Queryable, Identifiable)]
+#[table_name="search_clicks"]
+pub struct SearchClick {
+    pub id:        i32,
+    pub search_id: i32,
+    pub item_id:   i32,
+    pub created:   chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name="search_clicks"]
+pub struct NewSearchClick {
+    pub search_id: i32,
+    pub item_id:   i32,
+    pub created:   chrono::NaiveDateTime,
+}
@@ -28,9 +28,12 @@ CREATE TABLE orders (
 );
 
 CREATE TABLE order_files (
-    id       SERIAL PRIMARY KEY,
-    order_id INT NOT NULL,
-    src      VARCHAR(100) NOT NULL,
+    id            SERIAL PRIMARY KEY,
+    order_id      INT NOT NULL,
+    src           VARCHAR(300) NOT NULL,
+    original_name VARCHAR(200) NOT NULL,
+    mime          VARCHAR(100) NOT NULL,
+    size          INT NOT NULL,
 
     CONSTRAINT fk_order_files
         FOREIGN KEY(order_id)
@@ -50,7 +53,7 @@ CREATE TABLE users (
     email      VARCHAR(100) NOT NULL,
     password   VARCHAR(1000) NOT NULL,
     bio        VARCHAR(500),
-    image      VARCHAR(100),
+    image      VARCHAR(300),
     perm       SMALLINT NOT NULL,
     view       INT NOT NULL,
     height     FLOAT NOT NULL,
@@ -131,6 +134,7 @@ CREATE TABLE cookie_stats (
 CREATE TABLE tags (
     id        SERIAL PRIMARY KEY,
     name      VARCHAR(100) NOT NULL,
+    slug      VARCHAR(100) NOT NULL, -- латиница, уникален; в старых базах заполняет `tag-slugs`
     position  SMALLINT NOT NULL,
     count     SMALLINT NOT NULL,
     user_id   INT NOT NULL,
@@ -138,6 +142,8 @@ CREATE TABLE tags (
     height    FLOAT NOT NULL,
     seconds   INT NOT NULL,
 
+    UNIQUE(slug),
+
     CONSTRAINT fk_tag_creator
         FOREIGN KEY(user_id)
             REFERENCES users(id)
@@ -163,16 +169,22 @@ CREATE TABLE categories (
     user_id     INT NOT NULL,
     description VARCHAR(500),
     position    SMALLINT NOT NULL,
-    image       VARCHAR(100),
+    image       VARCHAR(300),
     count       SMALLINT NOT NULL,
     view        INT NOT NULL,
     height      FLOAT NOT NULL,
     seconds     INT NOT NULL,
     types       SMALLINT NOT NULL, -- категория блога, категория услуги ......
     slug        VARCHAR(100) NOT NULL,
+    parent_id   INT,                -- родительская категория (NULL - корневая)
 
-    UNIQUE(slug)
+    UNIQUE(slug),
+
+    CONSTRAINT fk_categories_parent
+        FOREIGN KEY(parent_id)
+            REFERENCES categories(id)
 );
+CREATE INDEX categories_parent_id_idx ON categories (parent_id);
 
 INSERT INTO categories (id, name, user_id, description, position, image, count, view, height, seconds, types, slug)
 VALUES (1, 'Все услуги', 1, NULL, 1, NULL, 0, 0, 0.0, 0, 2, 'all-services') ON CONFLICT DO NOTHING;
@@ -204,7 +216,7 @@ CREATE TABLE items (
     id          SERIAL PRIMARY KEY,
     title       VARCHAR(100) NOT NULL,
     link        VARCHAR(500),
-    image       VARCHAR(100),
+    image       VARCHAR(300),
     description VARCHAR(1000),
     item_types  SMALLINT NOT NULL,          -- обычное / изменено / удалено
     price       INT NOT NULL,
@@ -217,6 +229,7 @@ CREATE TABLE items (
     view        INT NOT NULL,
     height      FLOAT NOT NULL,
     seconds     INT NOT NULL,
+    updated     TIMESTAMP NOT NULL DEFAULT now(), -- когда изменено (для sitemap)
 
     UNIQUE(slug),
 
@@ -225,6 +238,11 @@ CREATE TABLE items (
             REFERENCES users(id)
 );
 CREATE INDEX items_creator_idx ON items (user_id);
+-- для баз, созданных до sitemap: дата изменения старых объектов - дата создания
+ALTER TABLE items ADD COLUMN IF NOT EXISTS updated TIMESTAMP;
+UPDATE items SET updated = created WHERE updated IS NULL;
+ALTER TABLE items ALTER COLUMN updated SET DEFAULT now();
+ALTER TABLE items ALTER COLUMN updated SET NOT NULL;
  
 CREATE TABLE item_contents ( 
     id         SERIAL PRIMARY KEY,
@@ -288,20 +306,303 @@ CREATE TABLE category (
             REFERENCES items(id)
 );
 
+-- старые адреса склеенных тегов и категорий.
+-- по ним страницы находят тег / категорию, в которую они влиты.
+CREATE TABLE slug_redirects (
+    id       SERIAL PRIMARY KEY,
+    types    SMALLINT NOT NULL,     -- 1 тег, 2 категория
+    old_slug VARCHAR(100) NOT NULL, -- прежний slug (у тегов - имя)
+    new_id   INT NOT NULL,          -- id тега / категории, в которую влили
+    created  TIMESTAMP NOT NULL,
+
+    UNIQUE(types, old_slug)
+);
+
+-- полнотекстовый поиск -------
+---------------
+---------------
+-- документ объекта для поиска (конфигурация russian, со стеммингом):
+-- A - название, B - описание и имена тегов, C - блоки контента.
+-- держится в актуальном состоянии триггерами ниже; в diesel-схему
+-- не попадает (см. diesel.toml), запросы к ней - через sql_query.
+CREATE TABLE item_search_index (
+    item_id  INT PRIMARY KEY,
+    document TSVECTOR NOT NULL,
+
+    CONSTRAINT fk_item_search_index
+        FOREIGN KEY(item_id)
+            REFERENCES items(id)
+            ON DELETE CASCADE
+);
+CREATE INDEX item_search_index_document_idx ON item_search_index USING GIN (document);
+
+CREATE OR REPLACE FUNCTION refresh_item_search_index(_item_id INT) RETURNS VOID AS $$
+BEGIN
+    INSERT INTO item_search_index (item_id, document)
+    SELECT i.id,
+        setweight(to_tsvector('russian', i.title), 'A') ||
+        setweight(to_tsvector('russian', COALESCE(i.description, '')), 'B') ||
+        setweight(to_tsvector('russian', COALESCE((
+            SELECT string_agg(t.name, ' ') FROM tags t
+            INNER JOIN tags_items l ON l.tag_id = t.id
+            WHERE l.item_id = i.id
+        ), '')), 'B') ||
+        setweight(to_tsvector('russian', COALESCE((
+            SELECT string_agg(c.title || ' ' || regexp_replace(c.content, '<[^>]*>', ' ', 'g'), ' ')
+            FROM item_contents c WHERE c.item_id = i.id
+        ), '')), 'C')
+    FROM items i WHERE i.id = _item_id
+    ON CONFLICT (item_id) DO UPDATE SET document = EXCLUDED.document;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION items_search_trigger() RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM refresh_item_search_index(NEW.id);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+-- у item_contents и tags_items обновляем документ объекта,
+-- к которому относилась старая и новая строка
+CREATE OR REPLACE FUNCTION item_links_search_trigger() RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP <> 'INSERT' THEN
+        PERFORM refresh_item_search_index(OLD.item_id);
+    END IF;
+    IF TG_OP <> 'DELETE' AND (TG_OP = 'INSERT' OR NEW.item_id <> OLD.item_id) THEN
+        PERFORM refresh_item_search_index(NEW.item_id);
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION tags_search_trigger() RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM refresh_item_search_index(l.item_id)
+    FROM tags_items l WHERE l.tag_id = NEW.id;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER items_search_update
+    AFTER INSERT OR UPDATE OF title, description ON items
+    FOR EACH ROW EXECUTE PROCEDURE items_search_trigger();
+CREATE TRIGGER item_contents_search_update
+    AFTER INSERT OR UPDATE OR DELETE ON item_contents
+    FOR EACH ROW EXECUTE PROCEDURE item_links_search_trigger();
+CREATE TRIGGER tags_items_search_update
+    AFTER INSERT OR UPDATE OR DELETE ON tags_items
+    FOR EACH ROW EXECUTE PROCEDURE item_links_search_trigger();
+CREATE TRIGGER tags_search_update
+    AFTER UPDATE OF name ON tags
+    FOR EACH ROW EXECUTE PROCEDURE tags_search_trigger();
+
+-- индекс для уже существующих объектов
+SELECT refresh_item_search_index(id) FROM items;
+
+-- словарь для исправления опечаток ("возможно, вы имели в виду"):
+-- слова опубликованных объектов и тегов без стемминга.
+-- похожие слова ищутся по триграммам (pg_trgm).
+-- пересобирается refresh_search_words() командой `search-words` (по cron)
+-- и при полной пересборке индекса
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE TABLE search_words (
+    word VARCHAR(100) PRIMARY KEY,
+    ndoc INT NOT NULL              -- в скольких объектах встречается
+);
+CREATE INDEX search_words_word_trgm_idx ON search_words USING GIN (word gin_trgm_ops);
+
+CREATE OR REPLACE FUNCTION refresh_search_words() RETURNS VOID AS $$
+BEGIN
+    -- две пересборки одновременно столкнулись бы на первичном ключе
+    PERFORM pg_advisory_xact_lock(hashtext('refresh_search_words'));
+    DELETE FROM search_words;
+    INSERT INTO search_words (word, ndoc)
+    SELECT word, ndoc FROM ts_stat($q$
+        SELECT to_tsvector('simple', i.title || ' ' || COALESCE(i.description, '') || ' ' ||
+            COALESCE(string_agg(c.title || ' ' || regexp_replace(c.content, '<[^>]*>', ' ', 'g'), ' '), ''))
+        FROM items i
+        LEFT JOIN item_contents c ON c.item_id = i.id
+        WHERE i.item_types < 10
+        GROUP BY i.id
+        UNION ALL
+        SELECT to_tsvector('simple', t.name) FROM tags t
+    $q$)
+    WHERE char_length(word) BETWEEN 3 AND 100 AND word !~ '^[0-9]+$';
+END;
+$$ LANGUAGE plpgsql;
+
+SELECT refresh_search_words();
+
+-- подсказки строки поиска (/search/suggest): совпадение с началом
+-- названия или любого его слова, LIKE по триграммам
+CREATE INDEX items_title_trgm_idx ON items USING GIN (lower(title) gin_trgm_ops);
+CREATE INDEX categories_name_trgm_idx ON categories USING GIN (lower(name) gin_trgm_ops);
+CREATE INDEX tags_name_trgm_idx ON tags USING GIN (lower(name) gin_trgm_ops);
+
+-- журнал поисковых запросов (для отчетов о популярных запросах,
+-- запросах без результатов и переходах из выдачи)
+CREATE TABLE search_queries (
+    id          SERIAL PRIMARY KEY,
+    user_id     INT NOT NULL,          -- пользователь куки, 0 - без куки
+    q           VARCHAR(200) NOT NULL, -- запрос в нижнем регистре
+    types       SMALLINT NOT NULL,     -- тип объектов, 0 - все
+    page        INT NOT NULL,
+    results     INT NOT NULL,          -- сколько найдено
+    corrected_q VARCHAR(200),          -- запрос в другой раскладке, если выдача по нему
+    created     TIMESTAMP NOT NULL
+);
+CREATE INDEX search_queries_created_idx ON search_queries (created);
+
+-- переходы из выдачи на объекты (приходят через create_history)
+CREATE TABLE search_clicks (
+    id        SERIAL PRIMARY KEY,
+    search_id INT NOT NULL,
+    item_id   INT NOT NULL,
+    created   TIMESTAMP NOT NULL,
+
+    CONSTRAINT fk_search_clicks_query
+        FOREIGN KEY(search_id)
+            REFERENCES search_queries(id)
+            ON DELETE CASCADE
+);
+CREATE INDEX search_clicks_search_id_idx ON search_clicks (search_id);
+
 CREATE TABLE files (
     id          SERIAL PRIMARY KEY,
     user_id     INT NOT NULL,
     item_id     INT NOT NULL,
     item_types  SMALLINT NOT NULL,      -- блог, услуга, товар ......
     types       SMALLINT NOT NULL,      -- фото, видео, документ  ......
-    src         VARCHAR(100) NOT NULL,
+    src         VARCHAR(300) NOT NULL,  -- адрес в blobs, у одинаковых файлов общий
     description VARCHAR,
     position    SMALLINT NOT NULL,
     view        INT NOT NULL,
     seconds     INT NOT NULL,
+    original_name VARCHAR(200) NOT NULL, -- имя файла у загрузившего
+    mime          VARCHAR(100) NOT NULL, -- тип по содержимому файла
+    size          INT NOT NULL,          -- байт
+    width         INT,                   -- у картинок
+    height        INT,
+    created       TIMESTAMP NOT NULL DEFAULT now()
+);
+CREATE INDEX files_src_idx ON files (src);
+-- медиатека: файлы пользователя по дате и поиск по описанию
+CREATE INDEX files_user_created_idx ON files (user_id, created);
+-- ILIKE использует триграммный индекс по самой колонке, без lower()
+CREATE INDEX files_description_trgm_idx ON files USING GIN (description gin_trgm_ops);
+CREATE INDEX files_original_name_trgm_idx ON files USING GIN (original_name gin_trgm_ops);
+CREATE INDEX order_files_src_idx ON order_files (src);
+
+-- загруженные картинки, для которых созданы варианты
+-- {hash}_{thumb|card|full}.{ext} и .webp (см. utils/images.rs)
+CREATE TABLE images (
+    src    VARCHAR(300) PRIMARY KEY,
+    width  INT NOT NULL,
+    height INT NOT NULL
+);
 
-    UNIQUE(src)
+-- сведения о содержимом загруженных файлов, кроме картинок (их размеры
+-- в images): страницы и заголовок pdf, начало текста, длительность
+-- аудио и видео. строка есть у каждого разобранного файла, даже если
+-- ничего извлечь не удалось (см. utils/metadata.rs)
+CREATE TABLE file_metadata (
+    src      VARCHAR(300) PRIMARY KEY,
+    pages    INT,           -- страниц в pdf
+    title    VARCHAR(300),  -- заголовок из свойств документа
+    excerpt  VARCHAR(500),  -- начало текста
+    duration INT            -- секунд
+);
+
+-- загруженные файлы в хранилище. ключ - sha256 содержимого
+-- ({hash[0..2]}/{hash[2..4]}/{hash}.{ext}), одинаковые загрузки получают
+-- один и тот же файл. refs - сколько строк files, order_files, items.image и
+-- categories.image на него ссылается, считают триггеры ниже.
+-- файл удаляется из хранилища, когда refs доходит до 0 (см. Blob::release).
+-- pending - загрузки, которые получили этот файл, но еще не сохранили
+-- ссылку на него: пока он больше 0, release файл не трогает. отметку
+-- снимает сама загрузка (см. UploadPins), триггер ее не трогает: иначе
+-- чужая ссылка на тот же файл сняла бы отметку еще не сохраненной загрузки.
+-- cleanup-media файлы с отметкой не трогает; отметку, оставленную
+-- падением процесса посреди загрузки, надо сбросить вручную
+CREATE TABLE blobs (
+    src     VARCHAR(300) PRIMARY KEY,
+    hash    VARCHAR(64),             -- NULL у файлов, загруженных до дедупликации
+    user_id INT NOT NULL,            -- кто загрузил первым
+    mime    VARCHAR(100) NOT NULL,
+    size    INT NOT NULL,
+    refs    INT NOT NULL,
+    pending INT NOT NULL DEFAULT 0,
+    created TIMESTAMP NOT NULL
 );
+-- один хеш может лежать и в общем, и в закрытом хранилище (см. get_private_storage)
+CREATE INDEX blobs_hash_idx ON blobs (hash);
+
+-- адреса файлов в S3 (S3_PUBLIC_URL + ключ) длиннее 100 символов.
+-- для баз, созданных со старой схемой, где колонки адресов VARCHAR(100)
+ALTER TABLE files ALTER COLUMN src TYPE VARCHAR(300);
+ALTER TABLE order_files ALTER COLUMN src TYPE VARCHAR(300);
+ALTER TABLE items ALTER COLUMN image TYPE VARCHAR(300);
+ALTER TABLE categories ALTER COLUMN image TYPE VARCHAR(300);
+ALTER TABLE users ALTER COLUMN image TYPE VARCHAR(300);
+ALTER TABLE images ALTER COLUMN src TYPE VARCHAR(300);
+ALTER TABLE file_metadata ALTER COLUMN src TYPE VARCHAR(300);
+ALTER TABLE blobs ALTER COLUMN src TYPE VARCHAR(300);
+
+-- старый индекс по lower(description) поиском ILIKE не используется
+DROP INDEX IF EXISTS files_description_trgm_idx;
+CREATE INDEX files_description_trgm_idx ON files USING GIN (description gin_trgm_ops);
+
+-- TG_ARGV[0] - колонка с адресом файла
+CREATE OR REPLACE FUNCTION blob_refs_trigger() RETURNS TRIGGER AS $$
+DECLARE
+    _old VARCHAR;
+    _new VARCHAR;
+BEGIN
+    IF TG_OP <> 'INSERT' THEN
+        _old := to_jsonb(OLD) ->> TG_ARGV[0];
+    END IF;
+    IF TG_OP <> 'DELETE' THEN
+        _new := to_jsonb(NEW) ->> TG_ARGV[0];
+    END IF;
+    IF _old IS DISTINCT FROM _new THEN
+        UPDATE blobs SET refs = refs - 1 WHERE src = _old;
+        UPDATE blobs SET refs = refs + 1 WHERE src = _new;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER files_blob_refs
+    AFTER INSERT OR UPDATE OF src OR DELETE ON files
+    FOR EACH ROW EXECUTE PROCEDURE blob_refs_trigger('src');
+CREATE TRIGGER order_files_blob_refs
+    AFTER INSERT OR UPDATE OF src OR DELETE ON order_files
+    FOR EACH ROW EXECUTE PROCEDURE blob_refs_trigger('src');
+CREATE TRIGGER items_blob_refs
+    AFTER INSERT OR UPDATE OF image OR DELETE ON items
+    FOR EACH ROW EXECUTE PROCEDURE blob_refs_trigger('image');
+CREATE TRIGGER categories_blob_refs
+    AFTER INSERT OR UPDATE OF image OR DELETE ON categories
+    FOR EACH ROW EXECUTE PROCEDURE blob_refs_trigger('image');
+
+-- уже загруженные файлы: без хеша, со ссылками по текущим данным
+INSERT INTO blobs (src, hash, user_id, mime, size, refs, created)
+SELECT u.src, NULL, MIN(u.user_id), MIN(u.mime), MAX(u.size), COUNT(*), now()
+FROM (
+    SELECT src, user_id, mime, size FROM files
+    UNION ALL
+    SELECT f.src, o.user_id, f.mime, f.size FROM order_files f
+    INNER JOIN orders o ON o.id = f.order_id
+    UNION ALL
+    SELECT image, user_id, '', 0 FROM items WHERE image IS NOT NULL AND image <> ''
+    UNION ALL
+    SELECT image, user_id, '', 0 FROM categories WHERE image IS NOT NULL AND image <> ''
+) u
+GROUP BY u.src
+ON CONFLICT (src) DO NOTHING;
 
 -- serve -------
 ---------------
@@ -5,26 +5,105 @@ use crate::diesel::{
     QueryDsl,
     RunQueryDsl,
     ExpressionMethods,
+    TextExpressionMethods,
+    PgTextExpressionMethods,
+    Connection,
+};
+use diesel::sql_types::{
+    Array,
+    BigInt,
+    Float4,
+    Integer,
+    SmallInt,
+    Text,
+    Varchar,
 };
 use serde::{Serialize, Deserialize};
 use crate::schema::{
     tags,
     tags_items,
 };
-use crate::utils::establish_connection;
+use crate::models::SlugRedirect;
+use crate::utils::{establish_connection, update_tag_counts, slugify};
+use crate::errors::Error;
+
 
+#[derive(QueryableByName)]
+struct TagName {
+    #[sql_type = "Integer"]
+    id:   i32,
+    #[sql_type = "Varchar"]
+    name: String,
+}
 
-#[derive(Serialize, Queryable)]
+// насколько имя тега должно быть похоже на запрос с опечаткой (pg_trgm similarity)
+pub const TAG_SIMILARITY: f32 = 0.3;
+
+#[derive(Serialize, Queryable, QueryableByName)]
 pub struct SmallTag {
+    #[sql_type = "Integer"]
+    pub id:    i32,
+    #[sql_type = "Varchar"]
     pub name:  String,
+    #[sql_type = "Varchar"]
+    pub slug:  String,
+    #[sql_type = "SmallInt"]
     pub count: i16,
 }
 
+// тег облака: count - число опубликованных объектов типа / категории
+// с этим тегом, weight - класс веса от 1 до 5 (по логарифму count)
+#[derive(Serialize, QueryableByName)]
+pub struct CloudTag {
+    #[sql_type = "Integer"]
+    pub id:     i32,
+    #[sql_type = "Varchar"]
+    pub name:   String,
+    #[sql_type = "Varchar"]
+    pub slug:   String,
+    #[sql_type = "Integer"]
+    pub count:  i32,
+    #[sql_type = "SmallInt"]
+    pub weight: i16,
+}
+
+fn get_words(text: &str) -> Vec<String> {
+    // слова текста в нижнем регистре без html-разметки, вместе с парами
+    // соседних слов - под теги из двух слов
+    let mut words: Vec<String> = Vec::new();
+    let mut word = String::new();
+    let mut is_tag = false;
+    for c in text.chars().chain(std::iter::once(' ')) {
+        if c == '<' {
+            is_tag = true;
+        }
+        else if c == '>' {
+            is_tag = false;
+        }
+        else if !is_tag && c.is_alphanumeric() {
+            word.extend(c.to_lowercase());
+            continue;
+        }
+        if word.chars().count() > 2 {
+            words.push(word.clone());
+        }
+        word.clear();
+    }
+    let mut stack = words.clone();
+    for pair in words.windows(2) {
+        stack.push(pair[0].clone() + " " + &pair[1]);
+    }
+    stack.sort();
+    stack.dedup();
+    return stack;
+}
+
 #[derive(Debug, Serialize, Queryable, Identifiable, Associations)]
 #[table_name="tags"]
 pub struct Tag {
     pub id:       i32,
     pub name:     String,
+    pub slug:     String,
     pub position: i16,
     pub count:    i16,
     pub user_id:  i32,
@@ -33,6 +112,279 @@ pub struct Tag {
     pub seconds:  i32,
 }
 impl Tag {
+    pub fn get_tag_by_slug(slug: &str) -> Option<Tag> {
+        // тег по slug или id; если тег был влит в другой - отдаем тот.
+        // сначала slug (в т.ч. старый, из редиректов), id - только если
+        // такого slug нет: slug тега может состоять из цифр ("2024")
+        use crate::schema::tags::dsl::tags;
+
+        let _connection = establish_connection();
+        let _tag = tags
+            .filter(schema::tags::slug.eq(slug))
+            .first::<Tag>(&_connection);
+        if _tag.is_ok() {
+            return _tag.ok();
+        }
+        let new_id = match SlugRedirect::get_new_id(1, slug) {
+            Some(id) => id,
+            None => slug.parse::<i32>().ok()?,
+        };
+        return tags
+            .filter(schema::tags::id.eq(new_id))
+            .first::<Tag>(&_connection)
+            .ok();
+    }
+    pub fn get_unique_slug(text: &str, exclude_id: i32) -> String {
+        // slug из текста; занятый другим тегом получает суффикс "-2", "-3"...
+        use crate::schema::tags::dsl::tags;
+
+        let mut base = slugify(text);
+        if base.is_empty() {
+            base = "tag".to_string();
+        }
+        let _connection = establish_connection();
+        let _slugs = tags
+            .filter(schema::tags::slug.like(base.clone() + "%"))
+            .filter(schema::tags::id.ne(exclude_id))
+            .select(schema::tags::slug)
+            .load::<String>(&_connection)
+            .expect("E.");
+
+        let mut slug = base.clone();
+        let mut n = 2;
+        while _slugs.contains(&slug) {
+            slug = format!("{}-{}", base, n);
+            n += 1;
+        }
+        return slug;
+    }
+    pub fn fill_slugs() -> usize {
+        // базы, созданные до slug у тегов: добавляем колонку, заполняем
+        // по имени тем же get_unique_slug и только потом делаем ее
+        // обязательной и уникальной. повторный запуск ничего не меняет
+        let _connection = establish_connection();
+        diesel::sql_query("ALTER TABLE tags ADD COLUMN IF NOT EXISTS slug VARCHAR(100)")
+            .execute(&_connection)
+            .expect("E.");
+        let _tags = diesel::sql_query("SELECT id, name FROM tags WHERE slug IS NULL OR slug = '' ORDER BY id")
+            .load::<TagName>(&_connection)
+            .expect("E.");
+        for tag in _tags.iter() {
+            diesel::sql_query("UPDATE tags SET slug = $1 WHERE id = $2")
+                .bind::<Varchar, _>(Tag::get_unique_slug(&tag.name, tag.id))
+                .bind::<Integer, _>(tag.id)
+                .execute(&_connection)
+                .expect("E.");
+        }
+        diesel::sql_query("ALTER TABLE tags ALTER COLUMN slug SET NOT NULL")
+            .execute(&_connection)
+            .expect("E.");
+        diesel::sql_query("CREATE UNIQUE INDEX IF NOT EXISTS tags_slug_key ON tags (slug)")
+            .execute(&_connection)
+            .expect("E.");
+        return _tags.len();
+    }
+    pub fn get_item_ids(&self) -> Vec<i32> {
+        // объекты с этим тегом, для переиндексации поиска
+        use crate::schema::tags_items::dsl::tags_items;
+
+        let _connection = establish_connection();
+        return tags_items
+            .filter(schema::tags_items::tag_id.eq(self.id))
+            .select(schema::tags_items::item_id)
+            .distinct()
+            .load::<i32>(&_connection)
+            .expect("E.");
+    }
+    pub fn merge(&self, target: &Tag) -> Result<(), Error> {
+        // вливаем тег в target: связи с объектами переносятся без дублей,
+        // старое имя запоминается для перенаправления, сам тег удаляется
+        use crate::schema::tags_items::dsl::tags_items;
+
+        let _connection = establish_connection();
+        return _connection.transaction(|| {
+            diesel::update(tags_items.filter(schema::tags_items::tag_id.eq(self.id)))
+                .set(schema::tags_items::tag_id.eq(target.id))
+                .execute(&_connection)?;
+            diesel::sql_query("
+                DELETE FROM tags_items a USING tags_items b
+                WHERE a.tag_id = $1 AND b.tag_id = $1
+                AND a.item_id = b.item_id AND a.types = b.types AND a.id > b.id
+            ")
+                .bind::<Integer, _>(target.id)
+                .execute(&_connection)?;
+            SlugRedirect::create(&_connection, 1, self.slug.clone(), self.id, target.id)?;
+            diesel::delete(self).execute(&_connection)?;
+            update_tag_counts(&_connection, vec![target.id])?;
+            Ok(())
+        });
+    }
+    pub fn search(q: &str, limit: i64) -> Vec<SmallTag> {
+        // автодополнение: сначала теги, начинающиеся с q, потом содержащие q,
+        // потом похожие по триграммам - на случай опечатки.
+        // q, набранный кириллицей, ищется и по slug - "веб" найдет "web..."
+        use crate::schema::tags::dsl::tags;
+
+        let q_raw = q.trim().to_lowercase();
+        let q_slug = slugify(q);
+        let q = q.trim().replace("\\", "\\\\").replace("%", "\\%").replace("_", "\\_");
+        let _connection = establish_connection();
+        let mut _query = tags
+            .filter(schema::tags::name.ilike(q.clone() + "%"))
+            .select((
+                schema::tags::id,
+                schema::tags::name,
+                schema::tags::slug,
+                schema::tags::count
+            ))
+            .into_boxed();
+        if !q_slug.is_empty() {
+            _query = _query.or_filter(schema::tags::slug.like(q_slug + "%"));
+        }
+        let mut _tags = _query
+            .order(schema::tags::count.desc())
+            .limit(limit)
+            .load::<SmallTag>(&_connection)
+            .expect("E.");
+
+        if (_tags.len() as i64) < limit {
+            let ids: Vec<i32> = _tags.iter().map(|t| t.id).collect();
+            let _infix = tags
+                .filter(schema::tags::id.ne_all(ids))
+                .filter(schema::tags::name.ilike("%".to_string() + &q + "%"))
+                .order(schema::tags::count.desc())
+                .limit(limit - _tags.len() as i64)
+                .select((
+                    schema::tags::id,
+                    schema::tags::name,
+                    schema::tags::slug,
+                    schema::tags::count
+                ))
+                .load::<SmallTag>(&_connection)
+                .expect("E.");
+            _tags.extend(_infix);
+        }
+        if (_tags.len() as i64) < limit && q_raw.chars().count() >= 3 {
+            let ids: Vec<i32> = _tags.iter().map(|t| t.id).collect();
+            let _similar = diesel::sql_query("
+                SELECT id, name, slug, count FROM tags
+                WHERE id <> ALL($1) AND similarity(lower(name), $2) > $3
+                ORDER BY similarity(lower(name), $2) DESC, count DESC
+                LIMIT $4
+            ")
+                .bind::<Array<Integer>, _>(ids)
+                .bind::<Text, _>(q_raw)
+                .bind::<Float4, _>(TAG_SIMILARITY)
+                .bind::<BigInt, _>(limit - _tags.len() as i64)
+                .load::<SmallTag>(&_connection)
+                .expect("E.");
+            _tags.extend(_similar);
+        }
+        return _tags;
+    }
+    pub fn get_suggestions(item_id: i32, limit: i64) -> Vec<SmallTag> {
+        // теги для объекта: сначала те, что встречаются словами в названии
+        // и блоках контента, потом частые теги объектов тех же категорий.
+        // уже привязанные теги не предлагаются
+        use crate::schema::{
+            items::dsl::items,
+            item_contents::dsl::item_contents,
+        };
+
+        let _connection = establish_connection();
+        let _title = items
+            .filter(schema::items::id.eq(item_id))
+            .select(schema::items::title)
+            .first::<String>(&_connection);
+        if _title.is_err() {
+            return Vec::new();
+        }
+        let mut text = _title.unwrap();
+        let _contents = item_contents
+            .filter(schema::item_contents::item_id.eq(item_id))
+            .select((schema::item_contents::title, schema::item_contents::content))
+            .load::<(String, String)>(&_connection)
+            .expect("E.");
+        for c in _contents.iter() {
+            text = text + " " + &c.0 + " " + &c.1;
+        }
+        let words = get_words(&text);
+        let slugs: Vec<String> = words.iter().map(|w| slugify(w)).collect();
+
+        let mut _tags = diesel::sql_query("
+            SELECT t.id, t.name, t.slug, t.count FROM tags t
+            WHERE (LOWER(t.name) = ANY($1) OR t.slug = ANY($2))
+            AND t.id NOT IN (SELECT l.tag_id FROM tags_items l WHERE l.item_id = $3)
+            ORDER BY t.count DESC
+            LIMIT $4
+        ")
+            .bind::<Array<Text>, _>(words)
+            .bind::<Array<Text>, _>(slugs)
+            .bind::<Integer, _>(item_id)
+            .bind::<BigInt, _>(limit)
+            .load::<SmallTag>(&_connection)
+            .expect("E.");
+
+        if (_tags.len() as i64) < limit {
+            let ids: Vec<i32> = _tags.iter().map(|t| t.id).collect();
+            let _similar = diesel::sql_query("
+                SELECT t.id, t.name, t.slug, t.count FROM tags t
+                INNER JOIN tags_items l ON l.tag_id = t.id
+                WHERE l.item_id IN (
+                    SELECT c2.item_id FROM category c1
+                    INNER JOIN category c2 ON c2.categories_id = c1.categories_id
+                    WHERE c1.item_id = $1 AND c2.item_id <> $1
+                )
+                AND t.id NOT IN (SELECT l2.tag_id FROM tags_items l2 WHERE l2.item_id = $1)
+                AND t.id <> ALL($2)
+                GROUP BY t.id
+                ORDER BY COUNT(*) DESC, t.count DESC
+                LIMIT $3
+            ")
+                .bind::<Integer, _>(item_id)
+                .bind::<Array<Integer>, _>(ids)
+                .bind::<BigInt, _>(limit - _tags.len() as i64)
+                .load::<SmallTag>(&_connection)
+                .expect("E.");
+            _tags.extend(_similar);
+        }
+        return _tags;
+    }
+    pub fn get_cloud(types: i16, cat_ids: Vec<i32>, limit: i64) -> Vec<CloudTag> {
+        // облако тегов типа объектов; непустой cat_ids сужает его
+        // до объектов этих категорий. берутся limit самых частых тегов,
+        // отдаются по алфавиту
+        let _connection = establish_connection();
+        return diesel::sql_query("
+            WITH cloud AS (
+                SELECT t.id, t.name, t.slug, COUNT(DISTINCT l.item_id)::INT AS count
+                FROM tags_items l
+                INNER JOIN tags t ON t.id = l.tag_id
+                INNER JOIN items i ON i.id = l.item_id AND i.item_types < 10
+                WHERE l.types = $1
+                AND (CARDINALITY($2::INT[]) = 0 OR l.item_id IN (
+                    SELECT c.item_id FROM category c WHERE c.categories_id = ANY($2)
+                ))
+                GROUP BY t.id
+                ORDER BY count DESC, t.id
+                LIMIT $3
+            ),
+            bounds AS (
+                SELECT LN(MIN(count)) AS lo, LN(MAX(count)) AS hi FROM cloud
+            )
+            SELECT cloud.id, cloud.name, cloud.slug, cloud.count,
+            (CASE WHEN bounds.hi = bounds.lo THEN 3
+            ELSE 1 + FLOOR(4 * (LN(cloud.count) - bounds.lo) / (bounds.hi - bounds.lo))
+            END)::SMALLINT AS weight
+            FROM cloud, bounds
+            ORDER BY LOWER(cloud.name)
+        ")
+            .bind::<SmallInt, _>(types)
+            .bind::<Array<Integer>, _>(cat_ids)
+            .bind::<BigInt, _>(limit)
+            .load::<CloudTag>(&_connection)
+            .expect("E.");
+    }
     pub fn get_tags_list(page: i32, limit: i32) -> (Vec<SmallTag>, i16) {
         let mut next_page_number = 0;
         let have_next: i32;
@@ -62,7 +414,9 @@ impl Tag {
             .limit(limit)
             .offset(offset)
             .select((
+                schema::tags::id,
                 schema::tags::name,
+                schema::tags::slug,
                 schema::tags::count
             ))
             .load::<SmallTag>(&_connection)
@@ -84,6 +438,7 @@ impl Tag {
 #[table_name="tags"]
 pub struct NewTag {
     pub name:     String,
+    pub slug:     String,
     pub position: i16,
     pub count:    i16,
     pub user_id:  i32,
@@ -96,6 +451,7 @@ pub struct NewTag {
 #[table_name="tags"]
 pub struct EditTag {
     pub name:     String,
+    pub slug:     String,
     pub position: i16,
 }
 
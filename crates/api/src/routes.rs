@@ -8,6 +8,10 @@ use crate::views::{
     pages,
     progs,
     auth,
+    sitemap_progs,
+    feed_progs,
+    media_progs,
+    upload_progs,
 };
 
 pub fn routes(cfg: &mut web::ServiceConfig) {
@@ -19,5 +23,9 @@ pub fn routes(cfg: &mut web::ServiceConfig) {
     .configure(tag_progs::tag_routes)
     .configure(auth::auth_routes)
     .configure(order_progs::order_routes)
+    .configure(sitemap_progs::sitemap_routes)
+    .configure(feed_progs::feed_routes)
+    .configure(media_progs::media_routes)
+    .configure(upload_progs::upload_routes)
     ;
 }
@@ -0,0 +1,26 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+
+// простой кэш готовых ответов (sitemap, robots.txt и т.д.).
+// ключи группируются префиксами ("sitemap:", ...), чтобы
+// можно было сбросить сразу всю группу.
+#[derive(Clone, Default)]
+pub struct PageCache {
+    entries: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl PageCache {
+    pub fn get(&self, key: &str) -> Option<String> {
+        let _entries = self.entries.lock().unwrap();
+        return _entries.get(key).cloned();
+    }
+    pub fn set(&self, key: &str, value: String) {
+        let mut _entries = self.entries.lock().unwrap();
+        _entries.insert(key.to_string(), value);
+    }
+    pub fn clear(&self, prefix: &str) {
+        let mut _entries = self.entries.lock().unwrap();
+        _entries.retain(|k, _| !k.starts_with(prefix));
+    }
+}
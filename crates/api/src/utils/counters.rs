@@ -0,0 +1,154 @@
+use crate::diesel::{
+    Connection,
+    PgConnection,
+    RunQueryDsl,
+    QueryResult,
+};
+use diesel::sql_types::{
+    Array,
+    Integer,
+    Varchar,
+};
+use serde::Serialize;
+
+
+// денормализованные счетчики: таблица, поле и подзапрос,
+// который вычисляет правильное значение по таблицам связей.
+// в подзапросе строка счетчика доступна как "c".
+//
+// categories.count - опубликованные объекты категории и всех ее подкатегорий,
+// tags.count - опубликованные объекты с тегом,
+// tech_categories.count - категории опций в тех. категории,
+// serve_categories.count - опции в категории,
+// serve_categories.default_price - сумма цен опций по умолчанию.
+const CATEGORY_COUNT_SQL: &str = "(
+    WITH RECURSIVE tree AS (
+        SELECT t.id FROM categories t WHERE t.id = c.id
+        UNION
+        SELECT ch.id FROM categories ch INNER JOIN tree ON ch.parent_id = tree.id
+    )
+    SELECT COUNT(DISTINCT l.item_id) FROM category l
+    INNER JOIN items i ON i.id = l.item_id AND i.item_types < 10
+    WHERE l.categories_id IN (SELECT tree.id FROM tree)
+)";
+const TAG_COUNT_SQL: &str = "(
+    SELECT COUNT(DISTINCT l.item_id) FROM tags_items l
+    INNER JOIN items i ON i.id = l.item_id AND i.item_types < 10
+    WHERE l.tag_id = c.id
+)";
+const TECH_CATEGORY_COUNT_SQL: &str = "(
+    SELECT COUNT(*) FROM serve_categories s WHERE s.tech_categories = c.id
+)";
+const SERVE_CATEGORY_COUNT_SQL: &str = "(
+    SELECT COUNT(*) FROM serve s WHERE s.serve_categories = c.id
+)";
+const SERVE_CATEGORY_PRICE_SQL: &str = "(
+    SELECT COALESCE(SUM(s.price), 0) FROM serve s
+    WHERE s.serve_categories = c.id AND s.is_default
+)";
+
+const COUNTERS: [(&str, &str, &str); 5] = [
+    ("categories", "count", CATEGORY_COUNT_SQL),
+    ("tags", "count", TAG_COUNT_SQL),
+    ("tech_categories", "count", TECH_CATEGORY_COUNT_SQL),
+    ("serve_categories", "count", SERVE_CATEGORY_COUNT_SQL),
+    ("serve_categories", "default_price", SERVE_CATEGORY_PRICE_SQL),
+];
+
+#[derive(Serialize, QueryableByName)]
+pub struct CounterDiff {
+    #[sql_type = "Varchar"]
+    pub table:  String,
+    #[sql_type = "Varchar"]
+    pub field:  String,
+    #[sql_type = "Integer"]
+    pub id:     i32,
+    #[sql_type = "Varchar"]
+    pub name:   String,
+    #[sql_type = "Integer"]
+    pub stored: i32,
+    #[sql_type = "Integer"]
+    pub actual: i32,
+}
+
+fn update_counter(
+    _connection: &PgConnection,
+    table:       &str,
+    field:       &str,
+    actual_sql:  &str,
+    ids:         Vec<i32>,
+) -> QueryResult<usize> {
+    return diesel::sql_query(format!(
+        "UPDATE {} c SET {} = {} WHERE c.id = ANY($1)",
+        table, field, actual_sql,
+    ))
+        .bind::<Array<Integer>, _>(ids)
+        .execute(_connection);
+}
+
+pub fn update_category_counts(_connection: &PgConnection, ids: Vec<i32>) -> QueryResult<usize> {
+    // счетчики категорий вместе со всеми их родителями
+    return diesel::sql_query(format!("
+        UPDATE categories c SET count = {} WHERE c.id IN (
+            WITH RECURSIVE up AS (
+                SELECT p.id, p.parent_id FROM categories p WHERE p.id = ANY($1)
+                UNION
+                SELECT p.id, p.parent_id FROM categories p INNER JOIN up ON p.id = up.parent_id
+            )
+            SELECT up.id FROM up
+        )",
+        CATEGORY_COUNT_SQL,
+    ))
+        .bind::<Array<Integer>, _>(ids)
+        .execute(_connection);
+}
+pub fn update_tag_counts(_connection: &PgConnection, ids: Vec<i32>) -> QueryResult<usize> {
+    return update_counter(_connection, "tags", "count", TAG_COUNT_SQL, ids);
+}
+pub fn update_tech_category_counts(_connection: &PgConnection, ids: Vec<i32>) -> QueryResult<usize> {
+    return update_counter(_connection, "tech_categories", "count", TECH_CATEGORY_COUNT_SQL, ids);
+}
+pub fn update_serve_category_counts(_connection: &PgConnection, ids: Vec<i32>) -> QueryResult<usize> {
+    // число опций и цена по умолчанию считаются вместе
+    update_counter(_connection, "serve_categories", "count", SERVE_CATEGORY_COUNT_SQL, ids.clone())?;
+    return update_counter(_connection, "serve_categories", "default_price", SERVE_CATEGORY_PRICE_SQL, ids);
+}
+
+pub fn get_counter_diffs(_connection: &PgConnection) -> QueryResult<Vec<CounterDiff>> {
+    // счетчики, которые разошлись с таблицами связей
+    let mut list = Vec::new();
+    for (table, field, actual_sql) in COUNTERS.iter() {
+        let diffs = diesel::sql_query(format!("
+            SELECT '{table}'::VARCHAR AS \"table\", '{field}'::VARCHAR AS field,
+            c.id, c.name::VARCHAR AS name,
+            c.{field}::INT AS stored, {actual}::INT AS actual
+            FROM {table} c
+            WHERE c.{field} <> {actual}
+            ORDER BY c.id",
+            table = table,
+            field = field,
+            actual = actual_sql,
+        ))
+            .load::<CounterDiff>(_connection)?;
+        list.extend(diffs);
+    }
+    return Ok(list);
+}
+
+pub fn recount_counters(_connection: &PgConnection, is_fix: bool) -> QueryResult<Vec<CounterDiff>> {
+    // отчет о расхождениях; при is_fix все счетчики пересчитываются
+    // в одной транзакции с отчетом
+    return _connection.transaction(|| {
+        let diffs = get_counter_diffs(_connection)?;
+        if is_fix {
+            for (table, field, actual_sql) in COUNTERS.iter() {
+                diesel::sql_query(format!(
+                    "UPDATE {} c SET {} = {}",
+                    table, field, actual_sql,
+                ))
+                    .execute(_connection)?;
+            }
+        }
+        Ok(diffs)
+    });
+}
@@ -62,4 +62,31 @@ pub async fn verify_jwt(_token: String, _secret: &String)-> Result<Claims, u16>{
     }
 
     Ok(claims)
-}
\ No newline at end of file
+}
+
+// подписанные ссылки на закрытые файлы: HMAC-SHA256 от "{path}:{expires}"
+// ключом приложения (KEY), expires - unix-время окончания действия
+pub fn sign_url(path: &str, expires: i64, secret: &str) -> String {
+    use hmac::{Hmac, Mac};
+
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes()).expect("E.");
+    mac.update(format!("{}:{}", path, expires).as_bytes());
+    return mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+}
+
+pub fn verify_url_sign(path: &str, expires: i64, sign: &str, secret: &str) -> bool {
+    if expires < Utc::now().timestamp() {
+        return false;
+    }
+    let expected = sign_url(path, expires, secret);
+    // сравнение за постоянное время
+    return expected.len() == sign.len() && expected
+        .bytes()
+        .zip(sign.bytes())
+        .fold(0, |acc, (a, b)| acc | (a ^ b)) == 0;
+}
@@ -0,0 +1,379 @@
+use crate::schema;
+use crate::models::{Categories, Tag};
+use crate::utils::{
+    establish_connection,
+    get_site_url,
+    get_item_path,
+    get_category_path,
+    xml_escape,
+};
+use crate::diesel::{
+    RunQueryDsl,
+    ExpressionMethods,
+    QueryDsl,
+    NullableExpressionMethods,
+};
+use diesel::sql_types::{
+    Array,
+    Integer,
+    SmallInt,
+    Varchar,
+};
+use std::collections::HashMap;
+use chrono::TimeZone;
+use sha2::{Digest, Sha256};
+
+
+// сколько последних объектов отдаем в ленту
+pub static FEED_LIMIT: i64 = 30;
+
+#[derive(Queryable)]
+pub struct FeedItem {
+    pub id:          i32,
+    pub slug:        String,
+    pub title:       String,
+    pub description: Option<String>,
+    pub image:       Option<String>,
+    pub created:     chrono::NaiveDateTime,
+    pub updated:     chrono::NaiveDateTime,
+}
+
+pub struct Feed {
+    pub types:     i16,
+    pub title:     String,
+    pub link:      String,
+    pub self_url:  String,
+    pub cat_slug:  Option<String>,
+    pub items:     Vec<FeedItem>,
+    pub cat_slugs: HashMap<i32, String>, // item_id -> первая категория, если cat_slug не задан
+    pub image_sizes: HashMap<String, i32>, // картинка -> размер, байт (для enclosure)
+}
+
+#[derive(QueryableByName)]
+struct ItemCatSlug {
+    #[sql_type = "Integer"]
+    item_id: i32,
+    #[sql_type = "Varchar"]
+    slug:    String,
+}
+
+fn get_cat_slugs(_connection: &diesel::PgConnection, types: i16, ids: Vec<i32>) -> HashMap<i32, String> {
+    // первая по position категория каждого объекта, одним запросом на всю ленту
+    return diesel::sql_query("
+        SELECT DISTINCT ON (c.item_id) c.item_id, cs.slug
+        FROM category c
+        INNER JOIN categories cs ON cs.id = c.categories_id
+        WHERE c.item_id = ANY($1) AND c.types = $2
+        ORDER BY c.item_id, cs.position
+    ")
+        .bind::<Array<Integer>, _>(ids)
+        .bind::<SmallInt, _>(types)
+        .load::<ItemCatSlug>(_connection)
+        .expect("E.")
+        .into_iter()
+        .map(|c| (c.item_id, c.slug))
+        .collect();
+}
+
+// даты в базе хранятся по московскому времени (+3), см. NewItem::create
+fn to_moscow(date: &chrono::NaiveDateTime) -> chrono::DateTime<chrono::FixedOffset> {
+    return chrono::FixedOffset::east(3 * 3600)
+        .from_local_datetime(date)
+        .unwrap();
+}
+pub fn get_http_date(date: &chrono::NaiveDateTime) -> String {
+    return to_moscow(date)
+        .with_timezone(&chrono::Utc)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string();
+}
+
+fn get_feed_types_title(types: i16) -> String {
+    return match types {
+        1 => "Блог".to_string(),
+        4 => "Обучающие статьи".to_string(),
+        5 => "Работы".to_string(),
+        _ => "Непонятно".to_string(),
+    };
+}
+
+fn get_image_mime(src: &str) -> String {
+    let _src = src.to_lowercase();
+    if _src.ends_with(".png") {
+        return "image/png".to_string();
+    }
+    else if _src.ends_with(".webp") {
+        return "image/webp".to_string();
+    }
+    else if _src.ends_with(".gif") {
+        return "image/gif".to_string();
+    }
+    return "image/jpeg".to_string();
+}
+
+fn get_absolute_url(src: &str) -> String {
+    if src.starts_with("http") {
+        return src.to_string();
+    }
+    return get_site_url() + src;
+}
+
+impl Feed {
+    pub fn get_last_modified(&self) -> Option<chrono::NaiveDateTime> {
+        return self.items.iter().map(|i| i.updated).max();
+    }
+    pub fn get_etag(&self) -> String {
+        // меняется, если поменялся состав ленты или хоть один объект в ней.
+        // sha256, а не DefaultHasher: тот не обязан совпадать между сборками
+        let mut hasher = Sha256::new();
+        hasher.update(self.title.as_bytes());
+        for item in self.items.iter() {
+            hasher.update(item.id.to_be_bytes());
+            hasher.update(item.updated.timestamp().to_be_bytes());
+        }
+        let hash = format!("{:x}", hasher.finalize());
+        return format!("\"{}-{}\"", self.types, &hash[..32]);
+    }
+    fn get_item_url(&self, item: &FeedItem) -> Option<String> {
+        // None - у объекта нет категории, адреса страницы тоже нет
+        let cat_slug = match self.cat_slug.as_deref() {
+            Some(slug) => slug,
+            None => self.cat_slugs.get(&item.id)?.as_str(),
+        };
+        return Some(format!(
+            "{}/{}/{}/{}/",
+            get_site_url(),
+            get_item_path(self.types),
+            cat_slug,
+            item.slug,
+        ));
+    }
+
+    pub fn get_rss(&self) -> String {
+        let mut xml = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n".to_string();
+        xml += "<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n<channel>\n";
+        xml += &format!("<title>{}</title>\n", xml_escape(&self.title));
+        xml += &format!("<link>{}</link>\n", xml_escape(&self.link));
+        xml += &format!("<description>{}</description>\n", xml_escape(&self.title));
+        xml += &format!(
+            "<atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>\n",
+            xml_escape(&self.self_url),
+        );
+        xml += "<language>ru</language>\n";
+        if let Some(date) = self.get_last_modified() {
+            xml += &format!("<lastBuildDate>{}</lastBuildDate>\n", to_moscow(&date).to_rfc2822());
+        }
+        for item in self.items.iter() {
+            let url = match self.get_item_url(item) {
+                Some(url) => url,
+                None => continue,
+            };
+            xml += "<item>\n";
+            xml += &format!("<title>{}</title>\n", xml_escape(&item.title));
+            xml += &format!("<link>{}</link>\n", xml_escape(&url));
+            xml += &format!("<guid isPermaLink=\"true\">{}</guid>\n", xml_escape(&url));
+            if item.description.is_some() {
+                xml += &format!(
+                    "<description>{}</description>\n",
+                    xml_escape(item.description.as_deref().unwrap()),
+                );
+            }
+            // length в enclosure обязателен: картинки без известного размера не отдаем
+            let size = item.image.as_ref().and_then(|s| self.image_sizes.get(s));
+            if item.image.is_some() && size.is_some() {
+                let src = item.image.as_deref().unwrap();
+                xml += &format!(
+                    "<enclosure url=\"{}\" length=\"{}\" type=\"{}\"/>\n",
+                    xml_escape(&get_absolute_url(src)),
+                    size.unwrap(),
+                    get_image_mime(src),
+                );
+            }
+            xml += &format!("<pubDate>{}</pubDate>\n", to_moscow(&item.created).to_rfc2822());
+            xml += "</item>\n";
+        }
+        xml += "</channel>\n</rss>\n";
+        return xml;
+    }
+
+    pub fn get_atom(&self) -> String {
+        let updated = match self.get_last_modified() {
+            Some(date) => to_moscow(&date).to_rfc3339(),
+            None => to_moscow(&chrono::Local::now().naive_utc()).to_rfc3339(),
+        };
+        let mut xml = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n".to_string();
+        xml += "<feed xmlns=\"http://www.w3.org/2005/Atom\" xml:lang=\"ru\">\n";
+        xml += &format!("<title>{}</title>\n", xml_escape(&self.title));
+        xml += &format!("<id>{}</id>\n", xml_escape(&self.self_url));
+        xml += &format!("<link href=\"{}\"/>\n", xml_escape(&self.link));
+        xml += &format!("<link rel=\"self\" href=\"{}\"/>\n", xml_escape(&self.self_url));
+        xml += &format!("<updated>{}</updated>\n", updated);
+        for item in self.items.iter() {
+            let url = match self.get_item_url(item) {
+                Some(url) => url,
+                None => continue,
+            };
+            xml += "<entry>\n";
+            xml += &format!("<title>{}</title>\n", xml_escape(&item.title));
+            xml += &format!("<id>{}</id>\n", xml_escape(&url));
+            xml += &format!("<link href=\"{}\"/>\n", xml_escape(&url));
+            xml += &format!("<published>{}</published>\n", to_moscow(&item.created).to_rfc3339());
+            xml += &format!("<updated>{}</updated>\n", to_moscow(&item.updated).to_rfc3339());
+            if item.description.is_some() {
+                xml += &format!(
+                    "<summary>{}</summary>\n",
+                    xml_escape(item.description.as_deref().unwrap()),
+                );
+            }
+            if item.image.is_some() {
+                let src = item.image.as_deref().unwrap();
+                let length = match self.image_sizes.get(src) {
+                    Some(size) => format!(" length=\"{}\"", size),
+                    None => "".to_string(),
+                };
+                xml += &format!(
+                    "<link rel=\"enclosure\" href=\"{}\" type=\"{}\"{}/>\n",
+                    xml_escape(&get_absolute_url(src)),
+                    get_image_mime(src),
+                    length,
+                );
+            }
+            xml += "<author><name>вебсервисы.рф</name></author>\n";
+            xml += "</entry>\n";
+        }
+        xml += "</feed>\n";
+        return xml;
+    }
+}
+
+pub fn get_feed (
+    types:    i16,
+    cat_slug: Option<String>,
+    tag:      Option<String>,
+    self_url: String,
+) -> Option<Feed> {
+    // лента опубликованных объектов типа types,
+    // при необходимости ограниченная категорией или тегом.
+    // None - если категория или тег не найдены.
+    use crate::schema::{
+        items::dsl::items,
+        categories::dsl::categories,
+        category::dsl::category,
+        tags_items::dsl::tags_items,
+        blobs::dsl::blobs,
+    };
+
+    let _connection = establish_connection();
+    let mut title = get_feed_types_title(types);
+    let mut link = format!("{}/{}/", get_site_url(), get_category_path(types));
+    let mut ids: Option<Vec<i32>> = None;
+
+    // старый slug влитой категории ведет на ленту новой
+    let cat_slug = cat_slug.map(|s| Categories::get_actual_slug(&s, types));
+    if cat_slug.is_some() {
+        let _category = categories
+            .filter(schema::categories::slug.eq(cat_slug.as_deref().unwrap()))
+            .filter(schema::categories::types.eq(types))
+            .select((schema::categories::id, schema::categories::name))
+            .first::<(i32, String)>(&_connection);
+        if _category.is_err() {
+            return None;
+        }
+        let (cat_id, cat_name) = _category.unwrap();
+        title = title + " - " + &cat_name;
+        link = format!(
+            "{}/{}/{}/",
+            get_site_url(),
+            get_category_path(types),
+            cat_slug.as_deref().unwrap(),
+        );
+        ids = Some(category
+            .filter(schema::category::categories_id.eq(cat_id))
+            .filter(schema::category::types.eq(types))
+            .select(schema::category::item_id)
+            .load::<i32>(&_connection)
+            .expect("E"));
+    }
+    if tag.is_some() {
+        let _tag = Tag::get_tag_by_slug(tag.as_deref().unwrap())?;
+        let (tag_id, tag_name) = (_tag.id, _tag.name);
+        title = title + " - #" + &tag_name;
+        let tag_ids = tags_items
+            .filter(schema::tags_items::tag_id.eq(tag_id))
+            .filter(schema::tags_items::types.eq(types))
+            .select(schema::tags_items::item_id)
+            .load::<i32>(&_connection)
+            .expect("E");
+        ids = match ids {
+            Some(cat_ids) => Some(cat_ids.into_iter().filter(|i| tag_ids.contains(i)).collect()),
+            None => Some(tag_ids),
+        };
+    }
+
+    let _items: Vec<FeedItem>;
+    if ids.is_some() {
+        _items = items
+            .filter(schema::items::id.eq_any(ids.unwrap()))
+            .filter(schema::items::types.eq(types))
+            .filter(schema::items::item_types.lt(10))
+            .order(schema::items::created.desc())
+            .limit(FEED_LIMIT)
+            .select((
+                schema::items::id,
+                schema::items::slug,
+                schema::items::title,
+                schema::items::description.nullable(),
+                schema::items::image.nullable(),
+                schema::items::created,
+                schema::items::updated,
+            ))
+            .load::<FeedItem>(&_connection)
+            .expect("E.");
+    }
+    else {
+        _items = items
+            .filter(schema::items::types.eq(types))
+            .filter(schema::items::item_types.lt(10))
+            .order(schema::items::created.desc())
+            .limit(FEED_LIMIT)
+            .select((
+                schema::items::id,
+                schema::items::slug,
+                schema::items::title,
+                schema::items::description.nullable(),
+                schema::items::image.nullable(),
+                schema::items::created,
+                schema::items::updated,
+            ))
+            .load::<FeedItem>(&_connection)
+            .expect("E.");
+    }
+
+    let cat_slugs = match cat_slug {
+        Some(_) => HashMap::new(),
+        None => get_cat_slugs(&_connection, types, _items.iter().map(|i| i.id).collect()),
+    };
+    // объекты без категории в ленту не попадают (нет адреса страницы),
+    // в т.ч. в ETag и Last-Modified
+    let _items = _items
+        .into_iter()
+        .filter(|i| cat_slug.is_some() || cat_slugs.contains_key(&i.id))
+        .collect::<Vec<FeedItem>>();
+    let image_sizes = blobs
+        .filter(schema::blobs::src.eq_any(_items.iter().filter_map(|i| i.image.clone()).collect::<Vec<String>>()))
+        .select((schema::blobs::src, schema::blobs::size))
+        .load::<(String, i32)>(&_connection)
+        .expect("E.")
+        .into_iter()
+        .collect();
+    return Some(Feed {
+        types:       types,
+        title:       title,
+        link:        link,
+        self_url:    self_url,
+        cat_slug:    cat_slug,
+        items:       _items,
+        cat_slugs:   cat_slugs,
+        image_sizes: image_sizes,
+    });
+}
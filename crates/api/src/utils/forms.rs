@@ -1,44 +1,12 @@
 use actix_multipart::{Field, Multipart};
-use actix_web::web;
 use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 use std::{
-    io::Write,
-    fs::create_dir_all,
     str,
+    sync::Arc,
 };
-
-#[derive(Debug, Clone)]
-pub struct UploadedFiles {
-    pub name: String,
-    pub path: String,
-}
-
-impl UploadedFiles {
-    fn new(filename: String, owner_id: i32) -> UploadedFiles {
-        use chrono::Datelike;
-
-        let now = chrono::Local::now().naive_utc();
-        let format_folder = format!(
-            "./media/{}/{}/{}/{}/",
-            owner_id.to_string(),
-            now.year().to_string(),
-            now.month().to_string(),
-            now.day().to_string(),
-        );
-        let format_path = format_folder.clone() + &filename.to_string();
-        // вариант для https
-        let create_path = format_folder.replace("./", "/my/");
-        // вариант для debug
-        //let create_path = format_folder.replace("./", "/");
-        create_dir_all(create_path).unwrap();
-
-        UploadedFiles {
-            name: filename.to_string(),
-            path: format_path.to_string(),
-        }
-    }
-}
+use crate::utils::{check_form_token, save_upload, Storage, UploadKind, UploadedFiles, UploadPins, ErrorParams};
+use crate::errors::Error;
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct FeedbackForm {
@@ -58,9 +26,13 @@ pub struct CategoriesForm {
     pub level:       i16,
     pub types:       i16,
     pub slug:        String,
+    pub parent_id:   Option<i32>, // None - поле не передано, 0 - корневая
+    #[serde(skip)]
+    pub pins:        UploadPins, // загруженные файлы, см. UploadPins
 }
 
-pub async fn category_form(payload: &mut Multipart, owner_id: i32) -> CategoriesForm {
+pub async fn category_form(payload: &mut Multipart, owner_id: i32, storage: &Arc<dyn Storage>) -> Result<CategoriesForm, Error> {
+    let mut uploaded = 0;
     let mut form: CategoriesForm = CategoriesForm {
         token:       "".to_string(),
         id:          0,
@@ -71,6 +43,8 @@ pub async fn category_form(payload: &mut Multipart, owner_id: i32) -> Categories
         level:       0,
         types:       0,
         slug:        "".to_string(),
+        parent_id:   None,
+        pins:        UploadPins::new(storage),
     };
 
     while let Some(item) = payload.next().await {
@@ -87,21 +61,10 @@ pub async fn category_form(payload: &mut Multipart, owner_id: i32) -> Categories
             }
         }
         else if name == "image" {
-            let _new_path = field.content_disposition().get_filename().unwrap();
-            if _new_path != "" {
-                let file = UploadedFiles::new(_new_path.to_string(), owner_id);
-                let file_path = file.path.clone();
-                let mut f = web::block(move || std::fs::File::create(&file_path).expect("Failed to open hello.txt"))
-                    .await
-                    .unwrap();
-                while let Some(chunk) = field.next().await {
-                    let data = chunk.unwrap();
-                    f = web::block(move || f.write_all(&data).map(|_| f))
-                        .await
-                        .unwrap()
-                        .expect("Failed to open hello.txt");
-                }
-                form.image = file.path.clone().replace("./","/");
+            check_form_token(&form.token)?;
+            if let Some(file) = save_upload(&mut field, owner_id, UploadKind::Image, &mut uploaded, storage).await? {
+                form.pins.push(&file);
+                form.image = file.get_src();
             }
         }
         else if name == "position" {
@@ -131,6 +94,22 @@ pub async fn category_form(payload: &mut Multipart, owner_id: i32) -> Categories
                 }
             }
         }
+        else if name == "parent_id" {
+            while let Some(chunk) = field.next().await {
+                let data = chunk.expect("split_payload err chunk");
+                if let Ok(s) = str::from_utf8(&data) {
+                    match s.parse::<i32>() {
+                        Ok(_int) => form.parent_id = Some(_int),
+                        Err(_) => {
+                            let body = serde_json::to_string(&ErrorParams {
+                                error: "parametr 'parent_id' is not a number!".to_string(),
+                            }).unwrap();
+                            return Err(Error::BadRequest(body));
+                        },
+                    }
+                }
+            }
+        }
 
         else {
             while let Some(chunk) = field.next().await {
@@ -152,7 +131,7 @@ pub async fn category_form(payload: &mut Multipart, owner_id: i32) -> Categories
             }
         }
     }
-    form
+    Ok(form)
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -214,10 +193,13 @@ pub struct ItemForms {
     pub position:      i16,
     pub types:         i16,
     pub slug:          String,
+    #[serde(skip)]
+    pub pins:          UploadPins,
 }
 
 // форма для элементов с опциями / тех категориями
-pub async fn item_form(payload: &mut Multipart, owner_id: i32) -> ItemForms {
+pub async fn item_form(payload: &mut Multipart, owner_id: i32, storage: &Arc<dyn Storage>) -> Result<ItemForms, Error> {
+    let mut uploaded = 0;
     let mut form: ItemForms = ItemForms {
         token:                "".to_string(),
         id:                   0,
@@ -231,6 +213,7 @@ pub async fn item_form(payload: &mut Multipart, owner_id: i32) -> ItemForms {
         position:             0,
         types:                0,
         slug:                 "".to_string(),
+        pins:                 UploadPins::new(storage),
     };
 
     while let Some(item) = payload.next().await {
@@ -326,25 +309,14 @@ pub async fn item_form(payload: &mut Multipart, owner_id: i32) -> ItemForms {
             }
         }
         else if name == "main_image" {
-            let _new_path = field.content_disposition().get_filename().unwrap();
-            if _new_path != "" {
-                let file = UploadedFiles::new(_new_path.to_string(), owner_id);
-                let file_path = file.path.clone();
-                let mut f = web::block(move || std::fs::File::create(&file_path).expect("E"))
-                    .await
-                    .unwrap();
-                while let Some(chunk) = field.next().await {
-                    let data = chunk.unwrap();
-                    f = web::block(move || f.write_all(&data).map(|_| f))
-                        .await
-                        .unwrap()
-                        .expect("E");
-                }
-                form.main_image = Some(file.path.clone().replace("./","/"));
+            check_form_token(&form.token)?;
+            if let Some(file) = save_upload(&mut field, owner_id, UploadKind::Image, &mut uploaded, storage).await? {
+                form.pins.push(&file);
+                form.main_image = Some(file.get_src());
             }
         }
     }
-    form
+    Ok(form)
 }
 
 pub async fn feedback_form(payload: &mut Multipart) -> FeedbackForm {
@@ -389,13 +361,15 @@ pub struct OrderForms {
     pub username:    String,
     pub description: Option<String>,
     pub email:       String,
-    pub files:       Vec<String>,
+    pub files:       Vec<UploadedFiles>,
     pub serve_list:  Vec<i32>,
+    #[serde(skip)]
+    pub pins:        UploadPins,
 }
 
 // форма для заказов
-pub async fn order_form(payload: &mut Multipart, owner_id: i32) -> OrderForms {
-    let mut files: Vec<UploadedFiles> = Vec::new();
+pub async fn order_form(payload: &mut Multipart, owner_id: i32, storage: &Arc<dyn Storage>) -> Result<OrderForms, Error> {
+    let mut uploaded = 0;
 
     let mut form: OrderForms = OrderForms {
         token:       "".to_string(),
@@ -408,12 +382,13 @@ pub async fn order_form(payload: &mut Multipart, owner_id: i32) -> OrderForms {
         email:       "".to_string(),
         files:       Vec::new(),
         serve_list:  Vec::new(),
+        pins:        UploadPins::new(storage),
     };
 
     while let Some(item) = payload.next().await {
         let mut field: Field = item.expect("split_payload err");
         let name = field.name();
-        let string_list = ["title", "email", "description", "username"];
+        let string_list = ["title", "email", "description", "username", "token"];
 
         if string_list.contains(&name) {
             while let Some(chunk) = field.next().await {
@@ -476,26 +451,14 @@ pub async fn order_form(payload: &mut Multipart, owner_id: i32) -> OrderForms {
             }
         }
         else if name == "files[]" {
-            let _new_path = field.content_disposition().get_filename().unwrap();
-            if _new_path != "" {
-                let file = UploadedFiles::new(_new_path.to_string(), owner_id);
-                let file_path = file.path.clone();
-                let mut f = web::block(move || std::fs::File::create(&file_path).expect("E"))
-                    .await
-                    .unwrap();
-                while let Some(chunk) = field.next().await {
-                    let data = chunk.unwrap();
-                    f = web::block(move || f.write_all(&data).map(|_| f))
-                        .await
-                        .unwrap()
-                        .expect("E");
-                };
-                files.push(file.clone());
-                form.files.push(file.path.clone().replace("./","/"));
+            check_form_token(&form.token)?;
+            if let Some(file) = save_upload(&mut field, owner_id, UploadKind::Order, &mut uploaded, storage).await? {
+                form.pins.push(&file);
+                form.files.push(file);
             }
         }
     }
-    form
+    Ok(form)
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -504,10 +467,12 @@ pub struct FileForm {
     pub id:         i32,
     pub item_types: i16,      // блог, услуга ......
     pub types:      i16,      // фото, видео, аудио ......
-    pub files:      Vec<String>,
+    pub files:      Vec<UploadedFiles>,
+    #[serde(skip)]
+    pub pins:       UploadPins,
 }
-pub async fn files_form(payload: &mut Multipart, owner_id: i32) -> FileForm {
-    let mut _files: Vec<UploadedFiles> = Vec::new();
+pub async fn files_form(payload: &mut Multipart, owner_id: i32, storage: &Arc<dyn Storage>) -> Result<FileForm, Error> {
+    let mut uploaded = 0;
 
     let mut form: FileForm = FileForm {
         token:      "".to_string(),
@@ -515,28 +480,17 @@ pub async fn files_form(payload: &mut Multipart, owner_id: i32) -> FileForm {
         item_types: 0,
         types:      0,
         files:      Vec::new(),
+        pins:       UploadPins::new(storage),
     };
 
     while let Some(item) = payload.next().await {
         let mut field: Field = item.expect("split_payload err");
 
         if field.name() == "files[]" {
-            let _new_path = field.content_disposition().get_filename().unwrap();
-            if _new_path != "" {
-                let file = UploadedFiles::new(_new_path.to_string(), owner_id);
-                let file_path = file.path.clone();
-                let mut f = web::block(move || std::fs::File::create(&file_path).expect("E"))
-                    .await
-                    .unwrap();
-                while let Some(chunk) = field.next().await {
-                    let data = chunk.unwrap();
-                    f = web::block(move || f.write_all(&data).map(|_| f))
-                        .await
-                        .unwrap()
-                        .expect("E");
-                };
-                _files.push(file.clone());
-                form.files.push(file.path.clone().replace("./","/"));
+            check_form_token(&form.token)?;
+            if let Some(file) = save_upload(&mut field, owner_id, UploadKind::Media, &mut uploaded, storage).await? {
+                form.pins.push(&file);
+                form.files.push(file);
             }
         }
         else if field.name() == "item_types" {
@@ -576,7 +530,7 @@ pub async fn files_form(payload: &mut Multipart, owner_id: i32) -> FileForm {
             }
         }
     }
-    form
+    Ok(form)
 }
 
 #[derive(Deserialize, Serialize, Debug)]
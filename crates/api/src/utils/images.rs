@@ -0,0 +1,171 @@
+use image::{
+    imageops::FilterType,
+    DynamicImage,
+    ImageFormat,
+    GenericImageView,
+};
+use serde::Serialize;
+use crate::utils::Storage;
+
+
+// размеры вариантов картинки: название и наибольшая сторона, px.
+// варианты хранятся рядом с исходником: {hash}_{название}.{ext} и {hash}_{название}.webp
+pub const IMAGE_VARIANTS: &[(&str, u32)] = &[
+    ("thumb", 200),
+    ("card",  600),
+    ("full",  1600),
+];
+const JPEG_QUALITY: u8 = 85;
+const WEBP_QUALITY: f32 = 80.0;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ImageVariant {
+    pub name:   String,
+    pub src:    String, // в формате исходника
+    pub webp:   String,
+    pub width:  i32,
+    pub height: i32,
+}
+
+fn get_variant_path(path: &str, name: &str, ext: &str) -> String {
+    // /media/ab/cd/{hash}.jpg -> /media/ab/cd/{hash}_thumb.{ext}
+    let stem = match path.rsplit_once('.') {
+        Some((stem, _)) => stem,
+        None => path,
+    };
+    return format!("{}_{}.{}", stem, name, ext);
+}
+
+pub fn get_image_variants(src: &str, width: i32, height: i32) -> Vec<ImageVariant> {
+    // адреса и размеры вариантов обработанной картинки (см. process_image)
+    let ext = match src.rsplit_once('.') {
+        Some((_, ext)) => ext,
+        None => return Vec::new(),
+    };
+    let mut variants = Vec::new();
+    for (name, size) in IMAGE_VARIANTS.iter() {
+        let scale = (*size as f64 / width.max(height).max(1) as f64).min(1.0);
+        variants.push(ImageVariant {
+            name:   name.to_string(),
+            src:    get_variant_path(src, name, ext),
+            webp:   get_variant_path(src, name, "webp"),
+            width:  (width as f64 * scale).round() as i32,
+            height: (height as f64 * scale).round() as i32,
+        });
+    }
+    return variants;
+}
+
+fn apply_orientation(img: DynamicImage, data: &[u8]) -> DynamicImage {
+    // EXIF удаляется, поэтому поворот из него применяем к самим пикселям
+    let orientation = exif::Reader::new()
+        .read_from_container(&mut std::io::Cursor::new(data))
+        .ok()
+        .and_then(|e| e.get_field(exif::Tag::Orientation, exif::In::PRIMARY)
+            .and_then(|f| f.value.get_uint(0)));
+    return match orientation {
+        Some(2) => img.fliph(),
+        Some(3) => img.rotate180(),
+        Some(4) => img.flipv(),
+        Some(5) => img.rotate90().fliph(),
+        Some(6) => img.rotate90(),
+        Some(7) => img.rotate270().fliph(),
+        Some(8) => img.rotate270(),
+        _ => img,
+    };
+}
+
+fn get_image_mime(ext: &str) -> &'static str {
+    match ext {
+        "jpg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        _ => "image/webp",
+    }
+}
+
+fn encode_image(img: &DynamicImage, ext: &str) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    match ext {
+        "jpg" => {
+            let rgb = DynamicImage::ImageRgb8(img.to_rgb8());
+            image::codecs::jpeg::JpegEncoder::new_with_quality(&mut data, JPEG_QUALITY)
+                .encode_image(&rgb)
+                .map_err(|e| e.to_string())?;
+        },
+        "webp" => {
+            let rgba = DynamicImage::ImageRgba8(img.to_rgba8());
+            let encoder = webp::Encoder::from_image(&rgba).map_err(|e| e.to_string())?;
+            data = encoder.encode(WEBP_QUALITY).to_vec();
+        },
+        _ => {
+            let format = ImageFormat::from_extension(ext).ok_or("unknown format".to_string())?;
+            img.write_to(&mut std::io::Cursor::new(&mut data), format)
+                .map_err(|e| e.to_string())?;
+        },
+    };
+    return Ok(data);
+}
+
+pub struct ProcessedImage {
+    pub width:    i32,
+    pub height:   i32,
+    pub original: Option<Vec<u8>>,                  // исходник без EXIF; у gif - None
+    pub variants: Vec<(String, &'static str, Vec<u8>)>, // ключ, mime, содержимое
+}
+
+pub fn process_image(key: &str, data: &[u8]) -> Result<ProcessedImage, String> {
+    // загруженная картинка: пересохраняем исходник без EXIF (gif не трогаем,
+    // чтобы не потерять анимацию), готовим варианты IMAGE_VARIANTS
+    // в формате исходника и в webp. записывает их в хранилище вызывающий
+    let ext = match key.rsplit_once('.') {
+        Some((_, ext)) => ext.to_string(),
+        None => return Err("unknown format".to_string()),
+    };
+    let img = image::load_from_memory(data).map_err(|e| e.to_string())?;
+    let img = apply_orientation(img, data);
+    let original = match ext.as_str() {
+        "gif" => None,
+        _ => Some(encode_image(&img, &ext)?),
+    };
+
+    let (width, height) = img.dimensions();
+    let mut variants = Vec::new();
+    for (name, size) in IMAGE_VARIANTS.iter() {
+        let variant = if width > *size || height > *size {
+            img.resize(*size, *size, FilterType::Lanczos3)
+        }
+        else {
+            img.clone()
+        };
+        variants.push((get_variant_path(key, name, &ext), get_image_mime(&ext), encode_image(&variant, &ext)?));
+        variants.push((get_variant_path(key, name, "webp"), "image/webp", encode_image(&variant, "webp")?));
+    }
+    return Ok(ProcessedImage {
+        width:    width as i32,
+        height:   height as i32,
+        original: original,
+        variants: variants,
+    });
+}
+
+pub fn get_variant_srcs(src: &str) -> Vec<String> {
+    // адреса всех вариантов картинки, в т.ч. webp
+    let ext = match src.rsplit_once('.') {
+        Some((_, ext)) => ext,
+        None => return Vec::new(),
+    };
+    let mut list = Vec::new();
+    for (name, _) in IMAGE_VARIANTS.iter() {
+        list.push(get_variant_path(src, name, ext));
+        list.push(get_variant_path(src, name, "webp"));
+    }
+    return list;
+}
+
+pub fn remove_image_variants(storage: &dyn Storage, src: &str) {
+    // удалить варианты картинки; отсутствующие пропускаем
+    for variant in get_variant_srcs(src).iter() {
+        storage.delete_src(variant).ok();
+    }
+}
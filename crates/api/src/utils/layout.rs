@@ -0,0 +1,22 @@
+// одни и те же клавиши в английской и русской раскладке (ЙЦУКЕН)
+const EN_KEYS: &str = "qwertyuiop[]asdfghjkl;'zxcvbnm,.`";
+const RU_KEYS: &str = "йцукенгшщзхъфывапролджэячсмитьбюё";
+
+pub fn switch_keyboard_layout(text: &str) -> String {
+    // текст, набранный не в той раскладке: "ecke;b" -> "услужи", "ыущ" -> "seo".
+    // направление выбирается по тому, каких букв в тексте больше
+    let text = text.to_lowercase();
+    let en: Vec<char> = EN_KEYS.chars().collect();
+    let ru: Vec<char> = RU_KEYS.chars().collect();
+    let ru_count = text.chars().filter(|c| ru.contains(c)).count();
+    let en_count = text.chars().filter(|c| c.is_ascii_alphabetic()).count();
+    let (from, to) = if en_count >= ru_count { (&en, &ru) } else { (&ru, &en) };
+
+    return text
+        .chars()
+        .map(|c| match from.iter().position(|k| *k == c) {
+            Some(i) => to[i],
+            None => c,
+        })
+        .collect();
+}
@@ -0,0 +1,181 @@
+use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use chrono::NaiveDateTime;
+use crate::schema;
+use crate::diesel::{
+    Connection,
+    PgConnection,
+    RunQueryDsl,
+    ExpressionMethods,
+    OptionalExtension,
+    QueryDsl,
+};
+use diesel::sql_types::Text;
+use crate::models::Blob;
+use crate::utils::{
+    get_variant_srcs,
+    Storage,
+};
+
+
+// файлы моложе этого не трогаем: их форма могла еще не сохраниться
+pub const CLEANUP_GRACE_HOURS: i64 = 24;
+// куда переносятся файлы в режиме quarantine. скрытые каталоги
+// actix-files не раздает, при s3 лучше закрыть префикс политикой бакета
+pub const QUARANTINE_PREFIX: &str = ".quarantine/";
+
+// все адреса файлов, на которые ссылается база, и файлы, взятые
+// незавершенными загрузками (blobs.pending)
+const REFERENCED_SQL: &str = "
+    SELECT src FROM files
+    UNION SELECT src FROM order_files
+    UNION SELECT image AS src FROM items WHERE image IS NOT NULL
+    UNION SELECT image AS src FROM categories WHERE image IS NOT NULL
+    UNION SELECT image AS src FROM users WHERE image IS NOT NULL
+    UNION SELECT src FROM blobs WHERE refs > 0 OR pending > 0";
+
+#[derive(QueryableByName)]
+struct MediaSrc {
+    #[sql_type = "Text"]
+    src: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CleanupMode {
+    DryRun,     // только отчет
+    Quarantine, // перенести в QUARANTINE_PREFIX
+    Delete,
+}
+
+impl CleanupMode {
+    pub fn from_args(args: &Vec<String>) -> CleanupMode {
+        if args.iter().any(|a| a == "--delete") {
+            return CleanupMode::Delete;
+        }
+        if args.iter().any(|a| a == "--quarantine") {
+            return CleanupMode::Quarantine;
+        }
+        return CleanupMode::DryRun;
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct OrphanFile {
+    pub key:      String,
+    pub size:     i64,
+    pub modified: NaiveDateTime,
+    pub done:     bool, // перенесен или удален; в dry_run всегда false
+}
+
+#[derive(Serialize, Debug)]
+pub struct CleanupReport {
+    pub storage: &'static str,
+    pub url:     String,     // адреса файлов хранилища, /media/ или /private/
+    pub mode:    CleanupMode,
+    pub scanned: usize,      // файлов в хранилище (без карантина)
+    pub skipped: usize,      // свежие файлы без ссылок, см. CLEANUP_GRACE_HOURS
+    pub orphans: Vec<OrphanFile>,
+    pub size:    i64,        // байт в orphans
+}
+
+fn get_referenced_keys(_connection: &PgConnection, storage: &dyn Storage) -> Result<HashSet<String>, String> {
+    // ключи файлов с ссылками и вариантов их картинок
+    let srcs = diesel::sql_query(REFERENCED_SQL)
+        .load::<MediaSrc>(_connection)
+        .map_err(|e| e.to_string())?;
+    let mut keys = HashSet::new();
+    for s in srcs.iter() {
+        if let Some(key) = storage.get_key(&s.src) {
+            keys.insert(key);
+        }
+        for variant in get_variant_srcs(&s.src).iter() {
+            if let Some(key) = storage.get_key(variant) {
+                keys.insert(key);
+            }
+        }
+    }
+    return Ok(keys);
+}
+
+fn remove_orphan(_connection: &PgConnection, storage: &dyn Storage, key: &str, mode: CleanupMode) -> Result<Option<bool>, String> {
+    // список ссылок устарел, пока шел обход хранилища: строку blobs
+    // блокируем и проверяем заново. загрузка, которая возьмет этот файл
+    // (Blob::pin, Blob::create), дождется конца транзакции.
+    // файлы без строки blobs - варианты картинок и старые загрузки.
+    // None - у файла появилась ссылка, Some(перенесен или удален)
+    let src = storage.url(key);
+    return _connection.transaction::<_, diesel::result::Error, _>(|| {
+        let blob = schema::blobs::table
+            .filter(schema::blobs::src.eq(&src))
+            .for_update()
+            .first::<Blob>(_connection)
+            .optional()?;
+        if let Some(blob) = blob {
+            if blob.refs > 0 || blob.pending > 0 {
+                return Ok(None);
+            }
+        }
+        let done = match mode {
+            CleanupMode::Quarantine => storage.rename(key, &(QUARANTINE_PREFIX.to_string() + key)).is_ok(),
+            _ => storage.delete(key).is_ok(),
+        };
+        if done {
+            diesel::delete(schema::blobs::table.filter(schema::blobs::src.eq(&src)))
+                .execute(_connection)?;
+            diesel::delete(schema::images::table.filter(schema::images::src.eq(&src)))
+                .execute(_connection)?;
+            diesel::delete(schema::file_metadata::table.filter(schema::file_metadata::src.eq(&src)))
+                .execute(_connection)?;
+        }
+        Ok(Some(done))
+    }).map_err(|e| e.to_string());
+}
+
+pub fn cleanup_storage(_connection: &PgConnection, storage: &dyn Storage, mode: CleanupMode) -> Result<CleanupReport, String> {
+    // сверяем хранилище со ссылками из базы (files, order_files,
+    // items.image, categories.image, users.image). файлы без ссылок - это
+    // удаленные объекты, файлы и заказы, а также брошенные загрузки
+    let referenced = get_referenced_keys(_connection, storage)?;
+    let files = storage.list()?;
+    let border = chrono::Utc::now().naive_utc() - chrono::Duration::hours(CLEANUP_GRACE_HOURS);
+
+    let mut report = CleanupReport {
+        storage: storage.name(),
+        url:     storage.url(""),
+        mode:    mode,
+        scanned: 0,
+        skipped: 0,
+        orphans: Vec::new(),
+        size:    0,
+    };
+    for file in files.into_iter() {
+        if file.key.starts_with(QUARANTINE_PREFIX) {
+            continue;
+        }
+        report.scanned += 1;
+        if referenced.contains(&file.key) {
+            continue;
+        }
+        if file.modified > border {
+            report.skipped += 1;
+            continue;
+        }
+        let done = match mode {
+            CleanupMode::DryRun => false,
+            _ => match remove_orphan(_connection, storage, &file.key, mode)? {
+                Some(done) => done,
+                None => continue,
+            },
+        };
+        report.size += file.size;
+        report.orphans.push(OrphanFile {
+            key:      file.key,
+            size:     file.size,
+            modified: file.modified,
+            done:     done,
+        });
+    }
+
+    return Ok(report);
+}
@@ -0,0 +1,262 @@
+use std::convert::TryFrom;
+use std::io::Cursor;
+use std::sync::{
+    mpsc::{sync_channel, SyncSender},
+    Arc, Mutex, OnceLock,
+};
+use crate::models::FileMetadata;
+use crate::utils::{establish_connection, Storage};
+
+
+// сведения о содержимом загруженных файлов (см. file_metadata в main.sql).
+// разбираются только форматы, которые читаются без внешних программ;
+// у картинок размеры сохраняет process_image
+pub const EXCERPT_LEN: usize = 300;          // символов в excerpt
+pub const METADATA_MAX_SIZE: usize = 20 * 1024 * 1024; // файлы больше не разбираются
+const METADATA_QUEUE_LEN: usize = 100;       // файлов в очереди на разбор
+const TITLE_LEN:       usize = 300;
+const TEXT_READ_LEN:   usize = 64 * 1024;    // байт текстового файла для excerpt
+const PDF_TEXT_PAGES:  u32 = 2;              // страниц pdf для excerpt
+
+pub fn extract_metadata(src: &str, mime: &str, data: &[u8]) -> FileMetadata {
+    // поля, которые не удалось извлечь, остаются None.
+    // разборщики форматов могут паниковать на испорченных файлах
+    let empty = FileMetadata {
+        src:      src.to_string(),
+        pages:    None,
+        title:    None,
+        excerpt:  None,
+        duration: None,
+    };
+    let mut meta = empty.clone();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| read_metadata(&mut meta, mime, data)));
+    if result.is_err() {
+        return empty;
+    }
+    return meta;
+}
+
+fn read_metadata(meta: &mut FileMetadata, mime: &str, data: &[u8]) {
+    match mime {
+        "application/pdf" => {
+            let (pages, title, text) = read_pdf(data);
+            meta.pages = pages;
+            meta.title = title.and_then(|t| clean_text(&t, TITLE_LEN));
+            meta.excerpt = text.and_then(|t| clean_text(&t, EXCERPT_LEN));
+        },
+        "text/plain" => {
+            let text = String::from_utf8_lossy(&data[..data.len().min(TEXT_READ_LEN)]);
+            meta.excerpt = clean_text(&text, EXCERPT_LEN);
+        },
+        "audio/mpeg" => {
+            meta.duration = mp3_duration::from_read(&mut Cursor::new(data))
+                .ok()
+                .and_then(|d| i32::try_from(d.as_secs()).ok());
+        },
+        "audio/wav" => {
+            meta.duration = hound::WavReader::new(Cursor::new(data))
+                .ok()
+                .filter(|r| r.spec().sample_rate > 0)
+                .and_then(|r| i32::try_from(r.duration() / r.spec().sample_rate).ok());
+        },
+        "audio/ogg" => {
+            meta.duration = get_ogg_duration(data);
+        },
+        "video/mp4" => {
+            meta.duration = mp4::Mp4Reader::read_header(Cursor::new(data), data.len() as u64)
+                .ok()
+                .and_then(|r| i32::try_from(r.duration().as_secs()).ok());
+        },
+        _ => (),
+    };
+}
+
+struct MetadataJob {
+    storage: Arc<dyn Storage>,
+    src:     String,
+    mime:    String,
+}
+
+static METADATA_QUEUE: OnceLock<Mutex<SyncSender<MetadataJob>>> = OnceLock::new();
+
+pub fn queue_metadata(storage: Arc<dyn Storage>, src: String, mime: String, size: usize) {
+    // разбор идет в отдельном потоке, загрузка его не ждет. файлы больше
+    // METADATA_MAX_SIZE не разбираются; если очередь полна, файл
+    // разберет команда `file-metadata`
+    if size > METADATA_MAX_SIZE {
+        return;
+    }
+    let queue = METADATA_QUEUE.get_or_init(|| {
+        let (sender, receiver) = sync_channel::<MetadataJob>(METADATA_QUEUE_LEN);
+        std::thread::spawn(move || {
+            for job in receiver.iter() {
+                let data = match job.storage.get_key(&job.src).map(|key| job.storage.get(&key)) {
+                    Some(Ok(data)) => data,
+                    _ => continue,
+                };
+                let meta = extract_metadata(&job.src, &job.mime, &data);
+                if let Err(e) = meta.create(&establish_connection()) {
+                    println!("file_metadata {}: {}", job.src, e);
+                }
+            }
+        });
+        Mutex::new(sender)
+    });
+    queue.lock().unwrap().try_send(MetadataJob {
+        storage: storage,
+        src:     src,
+        mime:    mime,
+    }).ok();
+}
+
+fn clean_text(text: &str, len: usize) -> Option<String> {
+    // пробелы и переводы строк схлопываются, управляющие символы убираются
+    let text = text
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(len)
+        .collect::<String>();
+    if text.is_empty() {
+        return None;
+    }
+    return Some(text);
+}
+
+fn read_pdf(data: &[u8]) -> (Option<i32>, Option<String>, Option<String>) {
+    // (страниц, заголовок, текст первых страниц)
+    let doc = match lopdf::Document::load_mem(data) {
+        Ok(doc) => doc,
+        Err(_) => return (None, None, None),
+    };
+    let pages = doc.get_pages();
+    let title = doc.trailer.get(b"Info")
+        .and_then(|info| info.as_reference())
+        .and_then(|id| doc.get_dictionary(id))
+        .and_then(|info| info.get(b"Title"))
+        .and_then(|title| title.as_str())
+        .ok()
+        .map(decode_pdf_string);
+    let numbers = pages.keys().cloned().take(PDF_TEXT_PAGES as usize).collect::<Vec<u32>>();
+    let text = doc.extract_text(&numbers).ok();
+    return (Some(pages.len() as i32), title, text);
+}
+
+fn decode_pdf_string(bytes: &[u8]) -> String {
+    // строки pdf - UTF-16BE с BOM или однобайтовая кодировка
+    if bytes.starts_with(&[0xFE, 0xFF]) {
+        let units = bytes[2..]
+            .chunks_exact(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .collect::<Vec<u16>>();
+        return String::from_utf16_lossy(&units);
+    }
+    return bytes.iter().map(|&b| b as char).collect();
+}
+
+fn get_ogg_duration(data: &[u8]) -> Option<i32> {
+    // granule position последней страницы ogg - число сэмплов,
+    // частота берется из заголовка vorbis; у opus всегда 48 кГц минус pre-skip
+    let find = |magic: &[u8]| data.windows(magic.len()).take(1024).position(|w| w == magic);
+    let (rate, skip) = if let Some(pos) = find(b"\x01vorbis") {
+        let bytes = data.get(pos + 12..pos + 16)?;
+        (u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as u64, 0)
+    } else if let Some(pos) = find(b"OpusHead") {
+        let bytes = data.get(pos + 10..pos + 12)?;
+        (48000, u16::from_le_bytes([bytes[0], bytes[1]]) as u64)
+    } else {
+        return None;
+    };
+    if rate == 0 {
+        return None;
+    }
+
+    let last = data.windows(4).rposition(|w| w == b"OggS")?;
+    let bytes = data.get(last + 6..last + 14)?;
+    let mut granule = [0u8; 8];
+    granule.copy_from_slice(bytes);
+    let samples = u64::from_le_bytes(granule).checked_sub(skip)?;
+    return i32::try_from(samples / rate).ok();
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_ogg(header: &[u8], granule: u64) -> Vec<u8> {
+        // первая страница с заголовком кодека и последняя с granule position
+        let mut data = b"OggS\x00\x02".to_vec();
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(header);
+        data.extend_from_slice(b"OggS\x00\x04");
+        data.extend_from_slice(&granule.to_le_bytes());
+        data.extend_from_slice(&[0; 8]);
+        return data;
+    }
+
+    #[test]
+    fn ogg_vorbis_duration() {
+        let mut header = b"\x01vorbis".to_vec();
+        header.extend_from_slice(&[0, 0, 0, 0, 2]);      // версия, каналы
+        header.extend_from_slice(&44100u32.to_le_bytes());
+        assert_eq!(get_ogg_duration(&get_ogg(&header, 44100 * 90)), Some(90));
+    }
+
+    #[test]
+    fn ogg_opus_duration() {
+        let mut header = b"OpusHead\x01\x02".to_vec();
+        header.extend_from_slice(&312u16.to_le_bytes()); // pre-skip
+        assert_eq!(get_ogg_duration(&get_ogg(&header, 48000 * 5 + 312)), Some(5));
+        // granule меньше pre-skip
+        assert_eq!(get_ogg_duration(&get_ogg(&header, 100)), None);
+    }
+
+    #[test]
+    fn ogg_bad_duration() {
+        let mut header = b"\x01vorbis".to_vec();
+        header.extend_from_slice(&[0, 0, 0, 0, 2]);
+        header.extend_from_slice(&0u32.to_le_bytes());
+        assert_eq!(get_ogg_duration(&get_ogg(&header, 1000)), None);
+        // длительность не помещается в i32
+        let mut header = b"\x01vorbis".to_vec();
+        header.extend_from_slice(&[0, 0, 0, 0, 2]);
+        header.extend_from_slice(&1u32.to_le_bytes());
+        assert_eq!(get_ogg_duration(&get_ogg(&header, u64::MAX)), None);
+        assert_eq!(get_ogg_duration(b"OggS"), None);
+    }
+
+    #[test]
+    fn pdf_string_utf16() {
+        let mut bytes = vec![0xFE, 0xFF];
+        for unit in "Отчет 1".encode_utf16() {
+            bytes.extend_from_slice(&unit.to_be_bytes());
+        }
+        assert_eq!(decode_pdf_string(&bytes), "Отчет 1");
+        // нечетный хвост отбрасывается
+        bytes.push(0x04);
+        assert_eq!(decode_pdf_string(&bytes), "Отчет 1");
+    }
+
+    #[test]
+    fn pdf_string_single_byte() {
+        assert_eq!(decode_pdf_string(b"Report"), "Report");
+        assert_eq!(decode_pdf_string(&[0xE9]), "\u{e9}");
+    }
+
+    #[test]
+    fn clean_text_collapses_whitespace() {
+        assert_eq!(clean_text("  a\n\n b\tc \u{7}d ", 100), Some("a b c d".to_string()));
+        assert_eq!(clean_text("абвгд", 3), Some("абв".to_string()));
+        assert_eq!(clean_text(" \n\t", 100), None);
+    }
+
+    #[test]
+    fn extract_metadata_of_broken_pdf() {
+        let meta = extract_metadata("/media/a.pdf", "application/pdf", b"%PDF-1.4 broken");
+        assert_eq!(meta.pages, None);
+        assert_eq!(meta.excerpt, None);
+    }
+}
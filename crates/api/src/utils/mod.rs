@@ -1,11 +1,37 @@
 mod forms;
+mod upload;
+mod resumable;
+mod images;
+mod metadata;
+mod storage;
+mod media_cleanup;
 mod crypto;
 mod stat;
+mod cache;
+mod sitemap;
+mod feeds;
+mod counters;
+mod slug;
+mod layout;
+mod search_backend;
 
 pub use self::{
     forms::*,
+    upload::*,
+    resumable::*,
+    images::*,
+    metadata::*,
+    storage::*,
+    media_cleanup::*,
     crypto::*,
     stat::*,
+    cache::*,
+    sitemap::*,
+    feeds::*,
+    counters::*,
+    slug::*,
+    layout::*,
+    search_backend::*,
 };
 use actix_web::{
     HttpRequest,
@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::{Arc, Mutex, OnceLock};
+use serde::{Deserialize, Serialize};
+use chrono::NaiveDateTime;
+use crate::utils::{upload_error, sanitize_filename, TempUpload, UploadKind, MAX_REQUEST_SIZE};
+use crate::errors::Error;
+
+
+// докачиваемые загрузки: init -> куски с offset -> finalize (по образцу tus).
+// состояние лежит на диске в UPLOAD_TMP_ROOT (по умолчанию uploads_tmp/):
+// {id}.part - полученные байты, {id}.json - UploadSession
+pub const UPLOAD_CHUNK_SIZE:   usize = 5 * 1024 * 1024; // наибольший кусок, байт
+pub const UPLOAD_EXPIRE_HOURS: i64 = 24;                // брошенные загрузки удаляются
+// незавершенных загрузок одного владельца: штук и заявленных байт вместе.
+// заказы грузит cookie-пользователь, без ограничения он занял бы весь диск
+pub const UPLOAD_MAX_SESSIONS: usize = 5;
+pub const UPLOAD_MAX_RESERVED: usize = MAX_REQUEST_SIZE;
+
+// подсчет загрузок владельца и создание новой - без параллельных create
+static CREATE_LOCK: Mutex<()> = Mutex::new(());
+// проверка offset и дозапись одной загрузки - без параллельных кусков,
+// разные загрузки друг друга не ждут
+static SESSION_LOCKS: OnceLock<Mutex<HashMap<String, Arc<Mutex<()>>>>> = OnceLock::new();
+
+fn get_session_lock(id: &str) -> Arc<Mutex<()>> {
+    let mut locks = SESSION_LOCKS.get_or_init(|| Mutex::new(HashMap::new())).lock().unwrap();
+    return locks.entry(id.to_string()).or_default().clone();
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UploadSession {
+    pub id:       String,
+    pub owner_id: i32,    // пользователь; для заказов - cookie-пользователь
+    pub kind:     UploadKind,
+    pub filename: String,
+    pub size:     usize,  // заявленный размер файла
+    pub sha256:   String, // заявленная контрольная сумма всего файла
+    pub created:  NaiveDateTime,
+}
+
+pub fn get_upload_tmp_root() -> String {
+    let mut root = std::env::var("UPLOAD_TMP_ROOT").unwrap_or("uploads_tmp/".to_string());
+    if !root.ends_with('/') {
+        root.push('/');
+    }
+    return root;
+}
+
+fn get_path(id: &str, ext: &str) -> Result<String, Error> {
+    // id приходит из адреса запроса - пропускаем только uuid
+    if uuid::Uuid::parse_str(id).is_err() {
+        return Err(Error::NotFound("upload not found".to_string()));
+    }
+    return Ok(format!("{}{}.{}", get_upload_tmp_root(), id, ext));
+}
+
+fn io_error(e: std::io::Error) -> Error {
+    return Error::InternalServerError(e.to_string());
+}
+
+impl UploadSession {
+    pub fn create(owner_id: i32, kind: UploadKind, filename: &str, size: usize, sha256: &str) -> Result<UploadSession, Error> {
+        if size == 0 || size > kind.max_size() {
+            return Err(upload_error(format!(
+                "file size must be from 1 byte to {} MB!", kind.max_size() / 1024 / 1024,
+            )));
+        }
+        if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(upload_error("parametr 'sha256' must be a hex sha256!".to_string()));
+        }
+        remove_stale_uploads();
+
+        let _lock = CREATE_LOCK.lock().unwrap();
+        let (count, reserved) = get_owner_uploads(owner_id);
+        if count >= UPLOAD_MAX_SESSIONS {
+            return Err(upload_error(format!(
+                "too many unfinished uploads, finish or delete one of {}!", count,
+            )));
+        }
+        if reserved + size > UPLOAD_MAX_RESERVED {
+            return Err(upload_error(format!(
+                "unfinished uploads are larger than {} MB in total!", UPLOAD_MAX_RESERVED / 1024 / 1024,
+            )));
+        }
+        let session = UploadSession {
+            id:       uuid::Uuid::new_v4().to_string(),
+            owner_id: owner_id,
+            kind:     kind,
+            filename: sanitize_filename(filename),
+            size:     size,
+            sha256:   sha256.to_lowercase(),
+            created:  chrono::Utc::now().naive_utc(),
+        };
+        std::fs::create_dir_all(get_upload_tmp_root()).map_err(io_error)?;
+        std::fs::write(get_path(&session.id, "part")?, b"").map_err(io_error)?;
+        std::fs::write(get_path(&session.id, "json")?, serde_json::to_vec(&session).unwrap()).map_err(io_error)?;
+        return Ok(session);
+    }
+    pub fn open(id: &str) -> Result<UploadSession, Error> {
+        let data = match std::fs::read(get_path(id, "json")?) {
+            Ok(data) => data,
+            Err(_) => return Err(Error::NotFound("upload not found".to_string())),
+        };
+        return serde_json::from_slice(&data)
+            .map_err(|e| Error::InternalServerError(e.to_string()));
+    }
+    pub fn get_offset(&self) -> Result<usize, Error> {
+        // сколько байт уже получено
+        let meta = std::fs::metadata(get_path(&self.id, "part")?).map_err(io_error)?;
+        return Ok(meta.len() as usize);
+    }
+    pub fn append(&self, offset: usize, data: &[u8]) -> Result<usize, Error> {
+        // кусок принимается только с текущего конца файла: повторно
+        // отправленный или пропущенный кусок - Conflict с текущим offset,
+        // клиент продолжает с него. возвращает новый offset
+        if data.len() > UPLOAD_CHUNK_SIZE {
+            return Err(upload_error(format!("chunk is larger than {} MB!", UPLOAD_CHUNK_SIZE / 1024 / 1024)));
+        }
+        if offset.checked_add(data.len()).map_or(true, |end| end > self.size) {
+            return Err(upload_error("chunk is past the declared file size!".to_string()));
+        }
+        let lock = get_session_lock(&self.id);
+        let _lock = lock.lock().unwrap();
+        let current = self.get_offset()?;
+        if offset != current {
+            return Err(Error::Conflict(format!("offset must be {}", current)));
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(get_path(&self.id, "part")?)
+            .map_err(io_error)?;
+        file.write_all(data).map_err(io_error)?;
+        return Ok(current + data.len());
+    }
+    pub fn read(&self) -> Result<TempUpload, Error> {
+        // принятый файл; только когда получены все байты.
+        // файл остается на месте до remove
+        let offset = self.get_offset()?;
+        if offset != self.size {
+            return Err(Error::Conflict(format!("upload is incomplete: {} of {} bytes", offset, self.size)));
+        }
+        return TempUpload::from_file(&get_path(&self.id, "part")?);
+    }
+    pub fn remove(&self) {
+        if let Ok(path) = get_path(&self.id, "part") {
+            std::fs::remove_file(path).ok();
+        }
+        if let Ok(path) = get_path(&self.id, "json") {
+            std::fs::remove_file(path).ok();
+        }
+        if let Some(locks) = SESSION_LOCKS.get() {
+            locks.lock().unwrap().remove(&self.id);
+        }
+    }
+}
+
+fn get_owner_uploads(owner_id: i32) -> (usize, usize) {
+    // (незавершенных загрузок, заявленных байт в них)
+    let entries = match std::fs::read_dir(get_upload_tmp_root()) {
+        Ok(entries) => entries,
+        Err(_) => return (0, 0),
+    };
+    let (mut count, mut reserved) = (0, 0);
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if let Some(id) = name.strip_suffix(".json") {
+            if let Ok(session) = UploadSession::open(id) {
+                if session.owner_id == owner_id {
+                    count += 1;
+                    reserved += session.size;
+                }
+            }
+        }
+    }
+    return (count, reserved);
+}
+
+pub fn remove_stale_uploads() -> usize {
+    // загрузки, которые не завершили за UPLOAD_EXPIRE_HOURS
+    let border = chrono::Utc::now().naive_utc() - chrono::Duration::hours(UPLOAD_EXPIRE_HOURS);
+    let entries = match std::fs::read_dir(get_upload_tmp_root()) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+    let mut count = 0;
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        // файлы форм (см. save_upload), оставшиеся после падения процесса
+        if name.ends_with(".form") {
+            let modified = entry.metadata()
+                .and_then(|m| m.modified())
+                .map(|t| chrono::DateTime::<chrono::Utc>::from(t).naive_utc());
+            if modified.map(|t| t < border).unwrap_or(false) {
+                std::fs::remove_file(entry.path()).ok();
+                count += 1;
+            }
+            continue;
+        }
+        let id = match name.strip_suffix(".json") {
+            Some(id) => id.to_string(),
+            None => continue,
+        };
+        if let Ok(session) = UploadSession::open(&id) {
+            if session.created < border {
+                session.remove();
+                count += 1;
+            }
+        }
+    }
+    return count;
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_session(owner_id: i32, size: usize) -> UploadSession {
+        // все тесты пишут в один временный каталог
+        let root = std::env::temp_dir().join("api_upload_tests/");
+        std::env::set_var("UPLOAD_TMP_ROOT", root.to_str().unwrap());
+        return UploadSession::create(owner_id, UploadKind::Media, "test.txt", size, &"a".repeat(64)).unwrap();
+    }
+
+    #[test]
+    fn append_moves_offset() {
+        let session = create_session(-1, 10);
+        assert_eq!(session.append(0, b"hello").unwrap(), 5);
+        assert_eq!(session.append(5, b"world").unwrap(), 10);
+        assert_eq!(session.get_offset().unwrap(), 10);
+        session.remove();
+    }
+
+    #[test]
+    fn append_rejects_wrong_offset() {
+        let session = create_session(-2, 10);
+        session.append(0, b"hello").unwrap();
+        // повтор уже принятого куска и пропуск
+        assert!(matches!(session.append(0, b"hello"), Err(Error::Conflict(_))));
+        assert!(matches!(session.append(7, b"abc"), Err(Error::Conflict(_))));
+        assert_eq!(session.get_offset().unwrap(), 5);
+        session.remove();
+    }
+
+    #[test]
+    fn append_rejects_bytes_past_size() {
+        let session = create_session(-3, 4);
+        assert!(matches!(session.append(0, b"hello"), Err(Error::BadRequest(_))));
+        assert!(matches!(session.append(usize::MAX, b"a"), Err(Error::BadRequest(_))));
+        assert_eq!(session.get_offset().unwrap(), 0);
+        session.remove();
+    }
+
+    #[test]
+    fn read_requires_all_bytes() {
+        let session = create_session(-4, 10);
+        session.append(0, b"hello").unwrap();
+        assert!(matches!(session.read(), Err(Error::Conflict(_))));
+        session.remove();
+    }
+
+    #[test]
+    fn create_limits_sessions_per_owner() {
+        let sessions = (0..UPLOAD_MAX_SESSIONS)
+            .map(|_| create_session(-5, 1))
+            .collect::<Vec<UploadSession>>();
+        assert!(UploadSession::create(-5, UploadKind::Media, "test.txt", 1, &"a".repeat(64)).is_err());
+        for session in sessions.iter() {
+            session.remove();
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use std::sync::Arc;
+use crate::models::SearchItem;
+
+
+// где ищется текст запроса. фильтры, права и фрагменты выдачи всегда
+// считает Postgres (см. MATCHED_SQL), бэкенд только отдает найденные
+// объекты по убыванию релевантности
+pub trait SearchBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+    // (id, релевантность) найденных объектов; None - текст ищет сам Postgres
+    fn find_ids(&self, q: &str) -> Option<(Vec<i32>, Vec<f32>)>;
+    // объект создан, изменен, опубликован или скрыт
+    fn index_item(&self, item_id: i32);
+    fn delete_item(&self, item_id: i32);
+    // пересобрать индекс по базе, возвращает число объектов
+    fn rebuild(&self) -> Result<usize, String>;
+}
+
+pub fn get_search_backend() -> Arc<dyn SearchBackend> {
+    // SEARCH_BACKEND=postgres (по умолчанию) или tantivy
+    let backend = std::env::var("SEARCH_BACKEND").unwrap_or("postgres".to_string());
+    return match backend.as_str() {
+        #[cfg(feature = "tantivy")]
+        "tantivy" => Arc::new(TantivySearch::open()),
+        #[cfg(not(feature = "tantivy"))]
+        "tantivy" => panic!("SEARCH_BACKEND=tantivy requires build with --features tantivy"),
+        _ => Arc::new(PostgresSearch),
+    };
+}
+
+
+// полнотекстовый поиск Postgres по item_search_index
+pub struct PostgresSearch;
+
+impl SearchBackend for PostgresSearch {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+    fn find_ids(&self, _q: &str) -> Option<(Vec<i32>, Vec<f32>)> {
+        None
+    }
+    fn index_item(&self, _item_id: i32) {
+        // item_search_index обновляют триггеры
+    }
+    fn delete_item(&self, _item_id: i32) {
+        // строка индекса удаляется каскадом вместе с объектом
+    }
+    fn rebuild(&self) -> Result<usize, String> {
+        return Ok(SearchItem::refresh_index());
+    }
+}
+
+
+#[cfg(feature = "tantivy")]
+pub use self::tantivy_backend::TantivySearch;
+
+#[cfg(feature = "tantivy")]
+mod tantivy_backend {
+    use std::sync::Mutex;
+    use tantivy::{
+        doc,
+        Index,
+        IndexReader,
+        IndexWriter,
+        ReloadPolicy,
+        TantivyError,
+        Term,
+    };
+    use tantivy::collector::{Count, TopDocs};
+    use tantivy::directory::MmapDirectory;
+    use tantivy::query::QueryParser;
+    use tantivy::schema::{
+        Field,
+        IndexRecordOption,
+        Schema,
+        TextFieldIndexing,
+        TextOptions,
+        INDEXED,
+        STORED,
+    };
+    use tantivy::tokenizer::{
+        Language,
+        LowerCaser,
+        RemoveLongFilter,
+        SimpleTokenizer,
+        Stemmer,
+        TextAnalyzer,
+    };
+    use crate::models::{SearchDocument, SearchItem};
+    use super::SearchBackend;
+
+    // индекс на диске в SEARCH_INDEX_PATH (по умолчанию search_index/).
+    // веса полей те же, что в item_search_index: название > описание, теги > текст.
+    // писать в индекс может только один IndexWriter на все процессы,
+    // поэтому он открывается на время записи: сервер и `search-rebuild`
+    // мешают друг другу, только если пишут одновременно
+    pub struct TantivySearch {
+        index:       Index,
+        reader:      IndexReader,
+        writing:     Mutex<()>,
+        id:          Field,
+        title:       Field,
+        description: Field,
+        tags:        Field,
+        content:     Field,
+    }
+
+    impl TantivySearch {
+        pub fn open() -> TantivySearch {
+            let path = std::env::var("SEARCH_INDEX_PATH").unwrap_or("search_index/".to_string());
+            std::fs::create_dir_all(&path).expect("E.");
+
+            let text_options = TextOptions::default().set_indexing_options(
+                TextFieldIndexing::default()
+                    .set_tokenizer("ru_stem")
+                    .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+            );
+            let mut schema_builder = Schema::builder();
+            let id = schema_builder.add_i64_field("id", INDEXED | STORED);
+            let title = schema_builder.add_text_field("title", text_options.clone());
+            let description = schema_builder.add_text_field("description", text_options.clone());
+            let tags = schema_builder.add_text_field("tags", text_options.clone());
+            let content = schema_builder.add_text_field("content", text_options);
+
+            let directory = MmapDirectory::open(&path).expect("E.");
+            let index = Index::open_or_create(directory, schema_builder.build()).expect("E.");
+            index.tokenizers().register(
+                "ru_stem",
+                TextAnalyzer::from(SimpleTokenizer)
+                    .filter(RemoveLongFilter::limit(40))
+                    .filter(LowerCaser)
+                    .filter(Stemmer::new(Language::Russian)),
+            );
+            let reader = index
+                .reader_builder()
+                .reload_policy(ReloadPolicy::OnCommit)
+                .try_into()
+                .expect("E.");
+
+            return TantivySearch {
+                index:       index,
+                reader:      reader,
+                writing:     Mutex::new(()),
+                id:          id,
+                title:       title,
+                description: description,
+                tags:        tags,
+                content:     content,
+            };
+        }
+        fn write<F: FnOnce(&mut IndexWriter) -> Result<(), TantivyError>>(&self, f: F) -> Result<(), String> {
+            let _lock = self.writing.lock().unwrap();
+            let mut writer = self.index.writer(50_000_000).map_err(|e| e.to_string())?;
+            f(&mut writer).map_err(|e| e.to_string())?;
+            writer.commit().map_err(|e| e.to_string())?;
+            return Ok(());
+        }
+        fn add_documents(&self, writer: &mut IndexWriter, documents: &Vec<SearchDocument>) -> Result<(), TantivyError> {
+            for d in documents.iter() {
+                writer.delete_term(Term::from_field_i64(self.id, d.id as i64));
+                writer.add_document(doc!(
+                    self.id          => d.id as i64,
+                    self.title       => d.title.clone(),
+                    self.description => d.description.clone(),
+                    self.tags        => d.tags.clone(),
+                    self.content     => d.content.clone(),
+                ))?;
+            }
+            return Ok(());
+        }
+    }
+
+    impl SearchBackend for TantivySearch {
+        fn name(&self) -> &'static str {
+            "tantivy"
+        }
+        fn find_ids(&self, q: &str) -> Option<(Vec<i32>, Vec<f32>)> {
+            // синтаксис запросов tantivy пользователю не нужен:
+            // оставляем только слова, все они должны встретиться
+            let words: Vec<String> = q
+                .split(|c: char| !c.is_alphanumeric())
+                .filter(|w| !w.is_empty())
+                .map(|w| w.to_lowercase())
+                .collect();
+            if words.is_empty() {
+                return Some((Vec::new(), Vec::new()));
+            }
+            let mut parser = QueryParser::for_index (
+                &self.index,
+                vec![self.title, self.description, self.tags, self.content],
+            );
+            parser.set_conjunction_by_default();
+            parser.set_field_boost(self.title, 4.0);
+            parser.set_field_boost(self.description, 2.0);
+            parser.set_field_boost(self.tags, 2.0);
+            let query = match parser.parse_query(&words.join(" ")) {
+                Ok(query) => query,
+                Err(_) => return Some((Vec::new(), Vec::new())),
+            };
+
+            // берем все найденное: фильтры и счетчики по типам считает
+            // Postgres, обрезанный список исказил бы их
+            let searcher = self.reader.searcher();
+            let count = searcher.search(&query, &Count).expect("E.");
+            if count == 0 {
+                return Some((Vec::new(), Vec::new()));
+            }
+            let hits = searcher
+                .search(&query, &TopDocs::with_limit(count))
+                .expect("E.");
+            let (mut ids, mut ranks) = (Vec::new(), Vec::new());
+            for (score, address) in hits.into_iter() {
+                let document = searcher.doc(address).expect("E.");
+                if let Some(id) = document.get_first(self.id).and_then(|v| v.as_i64()) {
+                    ids.push(id as i32);
+                    ranks.push(score);
+                }
+            }
+            return Some((ids, ranks));
+        }
+        fn index_item(&self, item_id: i32) {
+            let documents = SearchItem::get_documents(Some(item_id));
+            let result = self.write(|writer| {
+                writer.delete_term(Term::from_field_i64(self.id, item_id as i64));
+                self.add_documents(writer, &documents)
+            });
+            if let Err(e) = result {
+                println!("search index item {}: {}", item_id, e);
+            }
+        }
+        fn delete_item(&self, item_id: i32) {
+            let result = self.write(|writer| {
+                writer.delete_term(Term::from_field_i64(self.id, item_id as i64));
+                Ok(())
+            });
+            if let Err(e) = result {
+                println!("search index item {}: {}", item_id, e);
+            }
+        }
+        fn rebuild(&self) -> Result<usize, String> {
+            // выдача все равно соединяется с item_search_index
+            // (см. MATCHED_SQL), поэтому пересобираем и его
+            SearchItem::refresh_index();
+            let documents = SearchItem::get_documents(None);
+            self.write(|writer| {
+                writer.delete_all_documents()?;
+                self.add_documents(writer, &documents)
+            })?;
+            return Ok(documents.len());
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use crate::utils::establish_connection;
+use crate::diesel::RunQueryDsl;
+use diesel::sql_types::{
+    BigInt,
+    Integer,
+    SmallInt,
+    Varchar,
+    Timestamp,
+    Nullable,
+};
+
+
+// сколько ссылок на объекты кладем в одну страницу sitemap.
+// протокол разрешает до 50000, но так страницы быстрее отдаются.
+pub static SITEMAP_PAGE_LIMIT: i64 = 5000;
+
+pub fn get_site_url() -> String {
+    // адрес сайта, на который ведут ссылки карты сайта
+    return std::env::var("SITE_URL")
+        .unwrap_or("https://вебсервисы.рф".to_string())
+        .trim_end_matches("/")
+        .to_string();
+}
+
+pub fn xml_escape(value: &str) -> String {
+    return value
+        .replace("&", "&amp;")
+        .replace("<", "&lt;")
+        .replace(">", "&gt;")
+        .replace("\"", "&quot;")
+        .replace("'", "&apos;");
+}
+
+pub fn encode_path_segment(value: &str) -> String {
+    // slug бывают кириллические, в <loc> адрес должен быть в ASCII
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            _ => encoded += &format!("%{:02X}", byte),
+        }
+    }
+    return encoded;
+}
+
+pub fn get_item_path(types: i16) -> String {
+    return match types {
+        1 => "blog".to_string(),
+        2 => "service".to_string(),
+        3 => "store".to_string(),
+        4 => "wiki".to_string(),
+        5 => "work".to_string(),
+        6 => "help".to_string(),
+        _ => "blog".to_string(),
+    };
+}
+pub fn get_category_path(types: i16) -> String {
+    return get_item_path(types) + "s";
+}
+
+#[derive(QueryableByName)]
+pub struct SitemapItem {
+    #[sql_type = "Integer"]
+    pub id:       i32,
+    #[sql_type = "SmallInt"]
+    pub types:    i16,
+    #[sql_type = "Varchar"]
+    pub slug:     String,
+    #[sql_type = "Timestamp"]
+    pub updated:  chrono::NaiveDateTime,
+    #[sql_type = "Nullable<Varchar>"]
+    pub cat_slug: Option<String>,
+}
+impl SitemapItem {
+    pub fn get_url(&self) -> Option<String> {
+        // у объекта без категории нет страницы на сайте
+        if self.cat_slug.is_none() {
+            return None;
+        }
+        return Some(format!(
+            "{}/{}/{}/{}/",
+            get_site_url(),
+            get_item_path(self.types),
+            encode_path_segment(self.cat_slug.as_deref().unwrap()),
+            encode_path_segment(&self.slug),
+        ));
+    }
+}
+
+#[derive(QueryableByName)]
+pub struct SitemapCategory {
+    #[sql_type = "Integer"]
+    pub id:      i32,
+    #[sql_type = "SmallInt"]
+    pub types:   i16,
+    #[sql_type = "Varchar"]
+    pub slug:    String,
+    #[sql_type = "Nullable<Timestamp>"]
+    pub updated: Option<chrono::NaiveDateTime>,
+}
+impl SitemapCategory {
+    pub fn get_url(&self) -> String {
+        return format!(
+            "{}/{}/{}/",
+            get_site_url(),
+            get_category_path(self.types),
+            encode_path_segment(&self.slug),
+        );
+    }
+}
+
+#[derive(QueryableByName)]
+struct SitemapCount {
+    #[sql_type = "BigInt"]
+    count: i64,
+}
+
+pub fn get_sitemap_pages_count() -> i64 {
+    // число страниц sitemap с объектами (опубликованными)
+    let _connection = establish_connection();
+    let _count = diesel::sql_query(
+        "SELECT COUNT(*) AS count FROM items WHERE item_types < 10 AND types < 7"
+    )
+        .get_result::<SitemapCount>(&_connection)
+        .expect("E.")
+        .count;
+    if _count == 0 {
+        return 1;
+    }
+    return (_count + SITEMAP_PAGE_LIMIT - 1) / SITEMAP_PAGE_LIMIT;
+}
+
+pub fn get_sitemap_items(page: i64) -> Vec<SitemapItem> {
+    let _connection = establish_connection();
+    return diesel::sql_query("
+        SELECT i.id, i.types, i.slug, i.updated,
+        (
+            SELECT c.slug FROM category l
+            INNER JOIN categories c ON c.id = l.categories_id
+            WHERE l.item_id = i.id AND l.types = i.types
+            ORDER BY c.position LIMIT 1
+        ) AS cat_slug
+        FROM items i
+        WHERE i.item_types < 10 AND i.types < 7
+        ORDER BY i.id
+        LIMIT $1 OFFSET $2
+    ")
+        .bind::<BigInt, _>(SITEMAP_PAGE_LIMIT)
+        .bind::<BigInt, _>((page - 1) * SITEMAP_PAGE_LIMIT)
+        .load::<SitemapItem>(&_connection)
+        .expect("E.");
+}
+
+pub fn get_sitemap_categories() -> Vec<SitemapCategory> {
+    // дата изменения категории - последнее изменение ее опубликованных объектов
+    let _connection = establish_connection();
+    return diesel::sql_query("
+        SELECT c.id, c.types, c.slug, MAX(i.updated) AS updated
+        FROM categories c
+        LEFT JOIN category l ON l.categories_id = c.id
+        LEFT JOIN items i ON i.id = l.item_id AND i.item_types < 10
+        GROUP BY c.id, c.types, c.slug
+        ORDER BY c.id
+    ")
+        .load::<SitemapCategory>(&_connection)
+        .expect("E.");
+}
+
+fn get_url_tag(loc: &str, lastmod: Option<chrono::NaiveDateTime>) -> String {
+    if lastmod.is_some() {
+        return format!(
+            "<url><loc>{}</loc><lastmod>{}</lastmod></url>\n",
+            xml_escape(loc),
+            lastmod.unwrap().format("%Y-%m-%d"),
+        );
+    }
+    return format!("<url><loc>{}</loc></url>\n", xml_escape(loc));
+}
+
+pub fn get_sitemap_index() -> String {
+    let site_url = get_site_url();
+    let mut xml = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n".to_string();
+    xml += "<sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n";
+    xml += &format!(
+        "<sitemap><loc>{}</loc></sitemap>\n",
+        xml_escape(&(site_url.clone() + "/sitemap_categories.xml")),
+    );
+    for page in 1..get_sitemap_pages_count() + 1 {
+        xml += &format!(
+            "<sitemap><loc>{}</loc></sitemap>\n",
+            xml_escape(&format!("{}/sitemap_items.xml?page={}", site_url, page)),
+        );
+    }
+    xml += "</sitemapindex>\n";
+    return xml;
+}
+
+pub fn get_items_sitemap(page: i64) -> String {
+    let mut xml = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n".to_string();
+    xml += "<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n";
+    for item in get_sitemap_items(page).iter() {
+        let url = item.get_url();
+        if url.is_some() {
+            xml += &get_url_tag(&url.unwrap(), Some(item.updated));
+        }
+    }
+    xml += "</urlset>\n";
+    return xml;
+}
+
+pub fn get_categories_sitemap() -> String {
+    let mut xml = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n".to_string();
+    xml += "<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n";
+    for cat in get_sitemap_categories().iter() {
+        xml += &get_url_tag(&cat.get_url(), cat.updated);
+    }
+    xml += "</urlset>\n";
+    return xml;
+}
+
+pub fn get_robots_txt() -> String {
+    // ROBOTS_DISALLOW - пути через запятую, закрытые от индексации.
+    // по умолчанию закрываем формы и страницы для администраторов.
+    let disallow = std::env::var("ROBOTS_DISALLOW").unwrap_or(
+        "/create_item,/edit_item,/create_category,/edit_category,/edit_file,/create_tag,/edit_tag,/orders,/history,/search".to_string()
+    );
+    let mut txt = "User-agent: *\n".to_string();
+    for path in disallow.split(",") {
+        let path = path.trim();
+        if !path.is_empty() {
+            txt += &format!("Disallow: {}\n", path);
+        }
+    }
+    txt += &format!("\nSitemap: {}/sitemap.xml\n", get_site_url());
+    return txt;
+}
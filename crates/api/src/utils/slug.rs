@@ -0,0 +1,34 @@
+// транслитерация кириллицы для адресов
+fn translit(c: char) -> Option<&'static str> {
+    let s = match c {
+        'а' => "a",  'б' => "b",  'в' => "v",    'г' => "g",  'д' => "d",
+        'е' => "e",  'ё' => "e",  'ж' => "zh",   'з' => "z",  'и' => "i",
+        'й' => "y",  'к' => "k",  'л' => "l",    'м' => "m",  'н' => "n",
+        'о' => "o",  'п' => "p",  'р' => "r",    'с' => "s",  'т' => "t",
+        'у' => "u",  'ф' => "f",  'х' => "h",    'ц' => "ts", 'ч' => "ch",
+        'ш' => "sh", 'щ' => "sch", 'ъ' => "",    'ы' => "y",  'ь' => "",
+        'э' => "e",  'ю' => "yu", 'я' => "ya",
+        _ => return None,
+    };
+    return Some(s);
+}
+
+pub fn slugify(text: &str) -> String {
+    // "Веб-разработка на Rust" -> "veb-razrabotka-na-rust".
+    // все, кроме латиницы и цифр, превращается в одинарный дефис
+    let mut slug = String::new();
+    for c in text.trim().to_lowercase().chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c);
+        }
+        else if let Some(s) = translit(c) {
+            slug.push_str(s);
+        }
+        else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    // slug ограничен 100 символами, с запасом под суффикс "-N"
+    slug.truncate(90);
+    return slug.trim_end_matches('-').to_string();
+}
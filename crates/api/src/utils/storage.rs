@@ -0,0 +1,394 @@
+use std::io::Write;
+use std::sync::Arc;
+use actix_web::web::Bytes;
+use chrono::NaiveDateTime;
+use futures::Stream;
+use tokio::sync::mpsc;
+
+
+// где лежат загруженные файлы. ключ - путь файла внутри хранилища
+// ({hash[0..2]}/{hash[2..4]}/{hash}.{ext}), в базе хранится адрес url(ключ)
+pub trait Storage: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn put(&self, key: &str, data: &[u8], mime: &str) -> Result<(), String>;
+    // файл с диска, без чтения целиком в память
+    fn put_file(&self, key: &str, path: &str, mime: &str) -> Result<(), String>;
+    fn get(&self, key: &str) -> Result<Vec<u8>, String>;
+    // отсутствующий файл - не ошибка
+    fn delete(&self, key: &str) -> Result<(), String>;
+    fn exists(&self, key: &str) -> bool;
+    fn url(&self, key: &str) -> String;
+    // все файлы хранилища
+    fn list(&self) -> Result<Vec<StoredFile>, String>;
+
+    // ключ по адресу из базы; None - файл не из этого хранилища
+    fn get_key(&self, src: &str) -> Option<String> {
+        return src.strip_prefix(&self.url("")).map(|k| k.to_string());
+    }
+    fn delete_src(&self, src: &str) -> Result<(), String> {
+        return match self.get_key(src) {
+            Some(key) => self.delete(&key),
+            None => Ok(()),
+        };
+    }
+    // перенести файл в to_key (например, в карантин)
+    fn rename(&self, key: &str, to_key: &str) -> Result<(), String> {
+        let data = self.get(key)?;
+        self.put(to_key, &data, "application/octet-stream")?;
+        return self.delete(key);
+    }
+    // путь файла на диске, если хранилище локальное
+    fn get_local_path(&self, _key: &str) -> Option<String> {
+        return None;
+    }
+    // записать файл в writer частями, без чтения целиком в память
+    fn get_to(&self, key: &str, writer: &mut dyn Write) -> Result<(), String> {
+        let data = self.get(key)?;
+        return writer.write_all(&data).map_err(|e| e.to_string());
+    }
+}
+
+// writer, отдающий записанное частями в канал (тело ответа, см. get_stream)
+struct ChannelWriter {
+    sender: mpsc::Sender<Result<Bytes, std::io::Error>>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // клиент закрыл соединение - прекращаем чтение из хранилища
+        self.sender
+            .blocking_send(Ok(Bytes::copy_from_slice(buf)))
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "closed"))?;
+        return Ok(buf.len());
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        return Ok(());
+    }
+}
+
+pub fn get_stream(storage: Arc<dyn Storage>, key: String)
+    -> impl Stream<Item = Result<Bytes, std::io::Error>> {
+    // файл хранилища как поток для HttpResponse::streaming.
+    // читается в отдельном потоке, ошибка чтения обрывает ответ
+    let (sender, receiver) = mpsc::channel(8);
+    std::thread::spawn(move || {
+        let mut writer = ChannelWriter { sender: sender.clone() };
+        if let Err(e) = storage.get_to(&key, &mut writer) {
+            sender.blocking_send(Err(std::io::Error::new(std::io::ErrorKind::Other, e))).ok();
+        }
+    });
+    return futures::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+}
+
+#[derive(Debug, Clone)]
+pub struct StoredFile {
+    pub key:      String,
+    pub size:     i64,
+    pub modified: NaiveDateTime, // UTC
+}
+
+pub fn get_storage() -> Arc<dyn Storage> {
+    // STORAGE_BACKEND=local (по умолчанию) или s3
+    let backend = std::env::var("STORAGE_BACKEND").unwrap_or("local".to_string());
+    return match backend.as_str() {
+        "s3" => Arc::new(S3Storage::open()),
+        _ => Arc::new(LocalStorage::open()),
+    };
+}
+
+// закрытые файлы (вложения заказов): напрямую не раздаются,
+// только через /order_file с проверкой прав или подписью
+pub fn get_private_storage() -> Arc<dyn Storage> {
+    let backend = std::env::var("STORAGE_BACKEND").unwrap_or("local".to_string());
+    return match backend.as_str() {
+        "s3" => match S3Storage::open_private() {
+            Some(storage) => Arc::new(storage),
+            // без S3_PRIVATE_BUCKET сайт работает, не работают только вложения заказов
+            None => {
+                println!("S3_PRIVATE_BUCKET is not set: order files are unavailable");
+                Arc::new(NoStorage { reason: "S3_PRIVATE_BUCKET is not set" })
+            },
+        },
+        _ => Arc::new(LocalStorage::open_private()),
+    };
+}
+// адреса закрытых файлов в базе: /private/{ключ}
+const PRIVATE_URL: &str = "/private/";
+
+
+// файлы на диске в MEDIA_ROOT (по умолчанию media/), раздаются по /media/.
+// закрытые - в PRIVATE_MEDIA_ROOT (по умолчанию private_media/), вне раздачи
+pub struct LocalStorage {
+    root: String,
+    url:  &'static str,
+}
+
+impl LocalStorage {
+    fn open_at(root: String, url: &'static str) -> LocalStorage {
+        let mut root = root;
+        if !root.ends_with('/') {
+            root.push('/');
+        }
+        return LocalStorage {
+            root: root,
+            url:  url,
+        };
+    }
+    pub fn open() -> LocalStorage {
+        return LocalStorage::open_at(std::env::var("MEDIA_ROOT").unwrap_or("media/".to_string()), "/media/");
+    }
+    pub fn open_private() -> LocalStorage {
+        return LocalStorage::open_at(std::env::var("PRIVATE_MEDIA_ROOT").unwrap_or("private_media/".to_string()), PRIVATE_URL);
+    }
+    pub fn get_root(&self) -> String {
+        return self.root.clone();
+    }
+    fn get_path(&self, key: &str) -> Result<String, String> {
+        // ключи генерируем сами, но "../" не пропускаем на всякий случай
+        if key.split('/').any(|p| p == ".." || p == ".") || key.starts_with('/') {
+            return Err(format!("bad key {}", key));
+        }
+        return Ok(self.root.clone() + key);
+    }
+}
+
+impl Storage for LocalStorage {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+    fn put(&self, key: &str, data: &[u8], _mime: &str) -> Result<(), String> {
+        let path = self.get_path(key)?;
+        if let Some(dir) = std::path::Path::new(&path).parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        return std::fs::write(&path, data).map_err(|e| e.to_string());
+    }
+    fn put_file(&self, key: &str, path: &str, _mime: &str) -> Result<(), String> {
+        let to = self.get_path(key)?;
+        if let Some(dir) = std::path::Path::new(&to).parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        return std::fs::copy(path, &to).map(|_| ()).map_err(|e| e.to_string());
+    }
+    fn get(&self, key: &str) -> Result<Vec<u8>, String> {
+        return std::fs::read(self.get_path(key)?).map_err(|e| e.to_string());
+    }
+    fn delete(&self, key: &str) -> Result<(), String> {
+        return match std::fs::remove_file(self.get_path(key)?) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.to_string()),
+        };
+    }
+    fn exists(&self, key: &str) -> bool {
+        return match self.get_path(key) {
+            Ok(path) => std::path::Path::new(&path).is_file(),
+            Err(_) => false,
+        };
+    }
+    fn url(&self, key: &str) -> String {
+        return self.url.to_string() + key;
+    }
+    fn list(&self) -> Result<Vec<StoredFile>, String> {
+        // обходим MEDIA_ROOT без рекурсии, стеком каталогов
+        let mut list = Vec::new();
+        let mut dirs = vec![std::path::PathBuf::from(&self.root)];
+        while let Some(dir) = dirs.pop() {
+            let entries = match std::fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.to_string()),
+            };
+            for entry in entries {
+                let entry = entry.map_err(|e| e.to_string())?;
+                let meta = entry.metadata().map_err(|e| e.to_string())?;
+                if meta.is_dir() {
+                    dirs.push(entry.path());
+                    continue;
+                }
+                let path = entry.path().to_string_lossy().to_string();
+                let key = match path.strip_prefix(&self.root) {
+                    Some(key) => key.to_string(),
+                    None => continue,
+                };
+                let modified = meta.modified().map_err(|e| e.to_string())?;
+                list.push(StoredFile {
+                    key:      key,
+                    size:     meta.len() as i64,
+                    modified: chrono::DateTime::<chrono::Utc>::from(modified).naive_utc(),
+                });
+            }
+        }
+        return Ok(list);
+    }
+    fn rename(&self, key: &str, to_key: &str) -> Result<(), String> {
+        let (path, to_path) = (self.get_path(key)?, self.get_path(to_key)?);
+        if let Some(dir) = std::path::Path::new(&to_path).parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        return std::fs::rename(&path, &to_path).map_err(|e| e.to_string());
+    }
+    fn get_local_path(&self, key: &str) -> Option<String> {
+        return self.get_path(key).ok();
+    }
+}
+
+
+// S3-совместимое хранилище (AWS, MinIO и т.д.):
+// S3_BUCKET, S3_REGION, S3_ENDPOINT, S3_ACCESS_KEY, S3_SECRET_KEY,
+// S3_PUBLIC_URL - откуда файлы бакета раздаются клиентам
+// (по умолчанию {S3_ENDPOINT}/{S3_BUCKET}).
+// для локальной проверки подходит MinIO: S3_ENDPOINT=http://localhost:9000.
+// закрытые файлы - в отдельном бакете S3_PRIVATE_BUCKET без публичного доступа
+pub struct S3Storage {
+    bucket:     s3::Bucket,
+    public_url: String,
+}
+
+impl S3Storage {
+    pub fn open() -> S3Storage {
+        let name = std::env::var("S3_BUCKET").expect("S3_BUCKET must be set");
+        let endpoint = std::env::var("S3_ENDPOINT").expect("S3_ENDPOINT must be set");
+        let public_url = std::env::var("S3_PUBLIC_URL")
+            .unwrap_or(format!("{}/{}", endpoint.trim_end_matches('/'), name));
+        return S3Storage::open_bucket(&name, public_url);
+    }
+    pub fn open_private() -> Option<S3Storage> {
+        // общий бакет раздается публично, закрытые файлы в нем не храним
+        let name = std::env::var("S3_PRIVATE_BUCKET").ok()?;
+        return Some(S3Storage::open_bucket(&name, PRIVATE_URL.to_string()));
+    }
+    fn open_bucket(name: &str, public_url: String) -> S3Storage {
+        let endpoint = std::env::var("S3_ENDPOINT").expect("S3_ENDPOINT must be set");
+        let region = s3::Region::Custom {
+            region:   std::env::var("S3_REGION").unwrap_or("us-east-1".to_string()),
+            endpoint: endpoint,
+        };
+        let credentials = s3::creds::Credentials::new(
+            Some(&std::env::var("S3_ACCESS_KEY").expect("S3_ACCESS_KEY must be set")),
+            Some(&std::env::var("S3_SECRET_KEY").expect("S3_SECRET_KEY must be set")),
+            None,
+            None,
+            None,
+        ).expect("E.");
+        // MinIO и большинство совместимых хранилищ работают с адресами вида
+        // {endpoint}/{bucket}/{key}
+        let bucket = s3::Bucket::new_with_path_style(name, region, credentials).expect("E.");
+
+        return S3Storage {
+            bucket:     bucket,
+            public_url: public_url.trim_end_matches('/').to_string(),
+        };
+    }
+}
+
+impl Storage for S3Storage {
+    fn name(&self) -> &'static str {
+        "s3"
+    }
+    fn put(&self, key: &str, data: &[u8], mime: &str) -> Result<(), String> {
+        let response = self.bucket
+            .put_object_with_content_type(key, data, mime)
+            .map_err(|e| e.to_string())?;
+        return match response.status_code() {
+            200..=299 => Ok(()),
+            code => Err(format!("s3 put {}: {}", key, code)),
+        };
+    }
+    fn put_file(&self, key: &str, path: &str, mime: &str) -> Result<(), String> {
+        let mut file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+        let code = self.bucket
+            .put_object_stream_with_content_type(&mut file, key, mime)
+            .map_err(|e| e.to_string())?;
+        return match code {
+            200..=299 => Ok(()),
+            code => Err(format!("s3 put {}: {}", key, code)),
+        };
+    }
+    fn get(&self, key: &str) -> Result<Vec<u8>, String> {
+        let response = self.bucket.get_object(key).map_err(|e| e.to_string())?;
+        return match response.status_code() {
+            200..=299 => Ok(response.bytes().to_vec()),
+            code => Err(format!("s3 get {}: {}", key, code)),
+        };
+    }
+    fn get_to(&self, key: &str, mut writer: &mut dyn Write) -> Result<(), String> {
+        let code = self.bucket
+            .get_object_stream(key, &mut writer)
+            .map_err(|e| e.to_string())?;
+        return match code {
+            200..=299 => Ok(()),
+            code => Err(format!("s3 get {}: {}", key, code)),
+        };
+    }
+    fn delete(&self, key: &str) -> Result<(), String> {
+        let response = self.bucket.delete_object(key).map_err(|e| e.to_string())?;
+        return match response.status_code() {
+            200..=299 | 404 => Ok(()),
+            code => Err(format!("s3 delete {}: {}", key, code)),
+        };
+    }
+    fn exists(&self, key: &str) -> bool {
+        return match self.bucket.head_object(key) {
+            Ok((_, code)) => code == 200,
+            Err(_) => false,
+        };
+    }
+    fn url(&self, key: &str) -> String {
+        return format!("{}/{}", self.public_url, key);
+    }
+    fn list(&self) -> Result<Vec<StoredFile>, String> {
+        let pages = self.bucket
+            .list("".to_string(), None)
+            .map_err(|e| e.to_string())?;
+        let mut list = Vec::new();
+        for page in pages.into_iter() {
+            for object in page.contents.into_iter() {
+                let modified = chrono::DateTime::parse_from_rfc3339(&object.last_modified)
+                    .map_err(|e| e.to_string())?;
+                list.push(StoredFile {
+                    key:      object.key,
+                    size:     object.size as i64,
+                    modified: modified.naive_utc(),
+                });
+            }
+        }
+        return Ok(list);
+    }
+}
+
+
+// ненастроенное хранилище: адреса те же, что у закрытого, любое обращение -
+// ошибка с причиной. запуск сервера не прерывает
+pub struct NoStorage {
+    reason: &'static str,
+}
+
+impl Storage for NoStorage {
+    fn name(&self) -> &'static str {
+        "none"
+    }
+    fn put(&self, _key: &str, _data: &[u8], _mime: &str) -> Result<(), String> {
+        return Err(self.reason.to_string());
+    }
+    fn put_file(&self, _key: &str, _path: &str, _mime: &str) -> Result<(), String> {
+        return Err(self.reason.to_string());
+    }
+    fn get(&self, _key: &str) -> Result<Vec<u8>, String> {
+        return Err(self.reason.to_string());
+    }
+    fn delete(&self, _key: &str) -> Result<(), String> {
+        return Err(self.reason.to_string());
+    }
+    fn exists(&self, _key: &str) -> bool {
+        return false;
+    }
+    fn url(&self, key: &str) -> String {
+        return PRIVATE_URL.to_string() + key;
+    }
+    fn list(&self) -> Result<Vec<StoredFile>, String> {
+        return Err(self.reason.to_string());
+    }
+}
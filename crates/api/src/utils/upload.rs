@@ -0,0 +1,521 @@
+use actix_multipart::Field;
+use actix_web::web;
+use futures::StreamExt;
+use sha2::{Digest, Sha256};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::Read,
+    str,
+    sync::Arc,
+};
+use tokio::io::AsyncWriteExt;
+use crate::utils::{
+    get_upload_tmp_root,
+    process_image,
+    queue_metadata,
+    ErrorParams, Storage,
+    TOKEN,
+};
+use crate::models::{Blob, Image};
+use crate::errors::Error;
+
+
+// ограничения на размер загрузок, байт
+pub const MAX_IMAGE_SIZE:   usize = 10 * 1024 * 1024;  // картинка категории, тега, объекта
+pub const MAX_FILE_SIZE:    usize = 100 * 1024 * 1024; // файл объекта или заказа
+pub const MAX_REQUEST_SIZE: usize = 200 * 1024 * 1024; // все файлы одной формы вместе
+
+// что можно загружать в поле формы
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadKind {
+    Image, // image, main_image
+    Media, // files[] объектов: фото, видео, аудио, документы
+    Order, // files[] заказа: фото и документы
+}
+
+impl UploadKind {
+    fn allowed_mimes(&self) -> &'static [&'static str] {
+        match self {
+            UploadKind::Image => &["image/jpeg", "image/png", "image/gif", "image/webp"],
+            UploadKind::Media => &[
+                "image/jpeg", "image/png", "image/gif", "image/webp",
+                "video/mp4", "video/webm", "audio/mpeg", "audio/ogg", "audio/wav",
+                "application/pdf",
+            ],
+            UploadKind::Order => &[
+                "image/jpeg", "image/png", "image/gif", "image/webp",
+                "application/pdf", "application/zip", "text/plain",
+            ],
+        }
+    }
+    pub fn max_size(&self) -> usize {
+        match self {
+            UploadKind::Image => MAX_IMAGE_SIZE,
+            _ => MAX_FILE_SIZE,
+        }
+    }
+}
+
+pub fn get_file_types(mime: &str) -> i16 {
+    // files.types по типу содержимого: 1 фото, 2 видео, 3 аудио, 4 документ
+    match mime.split('/').next() {
+        Some("image") => 1,
+        Some("video") => 2,
+        Some("audio") => 3,
+        _ => 4,
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadedFiles {
+    pub name: String, // имя файла у клиента, только для показа
+    pub key:  String, // {hash[0..2]}/{hash[2..4]}/{hash}.{ext} в хранилище
+    pub src:  String, // адрес файла, сохраняется в базе
+    pub mime: String,
+    pub size: i32,
+    pub width:  Option<i32>, // только у картинок
+    pub height: Option<i32>,
+}
+
+impl UploadedFiles {
+    fn new(filename: String, ext: &str, mime: String, size: usize, hash: &str, storage: &dyn Storage) -> UploadedFiles {
+        let key = get_hash_key(hash, ext);
+        UploadedFiles {
+            name: filename,
+            src:  storage.url(&key),
+            key:  key,
+            mime: mime,
+            size: size as i32,
+            width:  None,
+            height: None,
+        }
+    }
+    pub fn get_src(&self) -> String {
+        return self.src.clone();
+    }
+}
+
+pub fn get_hash_key(hash: &str, ext: &str) -> String {
+    // имя в хранилище - хеш содержимого: имя клиента может содержать
+    // "../", а одинаковые файлы должны попасть в одно место
+    return format!("{}/{}/{}.{}", &hash[0..2], &hash[2..4], hash, ext);
+}
+
+pub fn sanitize_filename(filename: &str) -> String {
+    // оставляем только последний компонент пути и убираем управляющие символы
+    let name = filename
+        .rsplit(|c| c == '/' || c == '\\')
+        .next()
+        .unwrap_or("")
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect::<String>();
+    let name = name.trim().trim_start_matches('.').to_string();
+    if name.is_empty() {
+        return "file".to_string();
+    }
+    return name.chars().take(200).collect();
+}
+
+fn get_ext(filename: &str) -> String {
+    match filename.rsplit_once('.') {
+        Some((_, ext)) => ext.to_lowercase(),
+        None => "".to_string(),
+    }
+}
+
+pub fn sniff_mime(data: &[u8], filename: &str) -> Option<(&'static str, String)> {
+    // тип файла по первым байтам (mime, расширение). заявленный клиентом
+    // Content-Type и расширение имени не учитываются, кроме выбора
+    // расширения для офисных документов внутри zip
+    let starts = |magic: &[u8]| data.len() >= magic.len() && &data[..magic.len()] == magic;
+    let at = |offset: usize, magic: &[u8]| {
+        data.len() >= offset + magic.len() && &data[offset..offset + magic.len()] == magic
+    };
+
+    if starts(&[0xFF, 0xD8, 0xFF]) {
+        return Some(("image/jpeg", "jpg".to_string()));
+    }
+    if starts(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]) {
+        return Some(("image/png", "png".to_string()));
+    }
+    if starts(b"GIF87a") || starts(b"GIF89a") {
+        return Some(("image/gif", "gif".to_string()));
+    }
+    if starts(b"RIFF") && at(8, b"WEBP") {
+        return Some(("image/webp", "webp".to_string()));
+    }
+    if starts(b"RIFF") && at(8, b"WAVE") {
+        return Some(("audio/wav", "wav".to_string()));
+    }
+    if starts(b"%PDF-") {
+        return Some(("application/pdf", "pdf".to_string()));
+    }
+    if at(4, b"ftyp") {
+        return Some(("video/mp4", "mp4".to_string()));
+    }
+    if starts(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        return Some(("video/webm", "webm".to_string()));
+    }
+    if starts(b"ID3") || starts(&[0xFF, 0xFB]) || starts(&[0xFF, 0xF3]) || starts(&[0xFF, 0xF2]) {
+        return Some(("audio/mpeg", "mp3".to_string()));
+    }
+    if starts(b"OggS") {
+        return Some(("audio/ogg", "ogg".to_string()));
+    }
+    if starts(&[b'P', b'K', 0x03, 0x04]) {
+        let ext = get_ext(filename);
+        if ["docx", "xlsx", "pptx", "odt", "ods"].contains(&ext.as_str()) {
+            return Some(("application/zip", ext));
+        }
+        return Some(("application/zip", "zip".to_string()));
+    }
+    // текст: utf-8 без нулевых байтов. data может быть началом файла,
+    // оборванный последний символ ошибкой не считается
+    let is_utf8 = match str::from_utf8(data) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    };
+    if !data.is_empty() && !data.contains(&0) && is_utf8 {
+        return Some(("text/plain", "txt".to_string()));
+    }
+    return None;
+}
+
+pub fn upload_error(text: String) -> Error {
+    let body = serde_json::to_string(&ErrorParams {
+        error: text,
+    }).unwrap();
+    return Error::BadRequest(body);
+}
+
+pub fn check_form_token(token: &str) -> Result<(), Error> {
+    // файлы формы принимаются только после поля token: запрос без него
+    // не должен занимать ни память, ни диск
+    if token != TOKEN {
+        return Err(upload_error("Permission Denied: 'token' must be sent before files".to_string()));
+    }
+    return Ok(());
+}
+
+// сколько первых байтов файла хранится для sniff_mime
+const SNIFF_LEN: usize = 8 * 1024;
+
+// принятый файл во временном каталоге (UPLOAD_TMP_ROOT): в память целиком
+// не читается, sha256 считается по мере записи
+pub struct TempUpload {
+    pub path: String,
+    pub size: usize,
+    pub hash: String,  // sha256, hex
+    pub head: Vec<u8>, // первые SNIFF_LEN байт
+    owned:    bool,    // файл формы удаляется вместе с TempUpload
+}
+
+impl TempUpload {
+    fn push_head(&mut self, chunk: &[u8]) {
+        if self.head.len() < SNIFF_LEN {
+            let n = chunk.len().min(SNIFF_LEN - self.head.len());
+            self.head.extend_from_slice(&chunk[..n]);
+        }
+    }
+    pub fn from_file(path: &str) -> Result<TempUpload, Error> {
+        // уже записанный файл (докачка, см. resumable.rs): только считаем
+        // хеш, удаляет файл его владелец
+        let io_error = |e: std::io::Error| Error::InternalServerError(e.to_string());
+        let mut file = std::fs::File::open(path).map_err(io_error)?;
+        let mut tmp = TempUpload {
+            path:  path.to_string(),
+            size:  0,
+            hash:  String::new(),
+            head:  Vec::new(),
+            owned: false,
+        };
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = file.read(&mut buf).map_err(io_error)?;
+            if n == 0 {
+                break;
+            }
+            tmp.push_head(&buf[..n]);
+            hasher.update(&buf[..n]);
+            tmp.size += n;
+        }
+        tmp.hash = format!("{:x}", hasher.finalize());
+        return Ok(tmp);
+    }
+    pub fn read(&self) -> Result<Vec<u8>, String> {
+        // весь файл - только для картинок, они не больше MAX_IMAGE_SIZE
+        return std::fs::read(&self.path).map_err(|e| e.to_string());
+    }
+}
+
+impl Drop for TempUpload {
+    fn drop(&mut self) {
+        if self.owned {
+            std::fs::remove_file(&self.path).ok();
+        }
+    }
+}
+
+// файлы, которые форма положила в хранилище (Blob::create и Blob::pin
+// отмечают их как взятые, см. blobs.pending). после сохранения ссылок
+// обработчик вызывает saved(); если он вернул ошибку раньше, отметки
+// снимаются при удалении формы и ненужные файлы удаляются
+#[derive(Default)]
+pub struct UploadPins {
+    storage: Option<Arc<dyn Storage>>,
+    srcs:    Vec<String>,
+}
+
+impl UploadPins {
+    pub fn new(storage: &Arc<dyn Storage>) -> UploadPins {
+        UploadPins {
+            storage: Some(storage.clone()),
+            srcs:    Vec::new(),
+        }
+    }
+    pub fn push(&mut self, file: &UploadedFiles) {
+        self.srcs.push(file.get_src());
+    }
+    pub fn saved(&mut self) {
+        // ссылки на файлы сохранены, refs их держит
+        for src in self.srcs.drain(..) {
+            Blob::unpin(&src);
+        }
+    }
+}
+
+impl std::fmt::Debug for UploadPins {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("UploadPins").field("srcs", &self.srcs).finish()
+    }
+}
+
+impl Drop for UploadPins {
+    fn drop(&mut self) {
+        if self.srcs.is_empty() {
+            return;
+        }
+        for src in self.srcs.iter() {
+            Blob::unpin(src);
+        }
+        if let Some(storage) = self.storage.as_ref() {
+            Blob::release(&**storage, self.srcs.drain(..).collect());
+        }
+    }
+}
+
+pub async fn save_upload (
+    field:    &mut Field,
+    owner_id: i32,
+    kind:     UploadKind,
+    uploaded: &mut usize,
+    storage:  &Arc<dyn Storage>,
+) -> Result<Option<UploadedFiles>, Error> {
+    // общий путь для всех файловых полей форм: пишем файл во временный
+    // каталог с проверкой размеров и подсчетом хеша, определяем тип по
+    // содержимому, сверяем со списком разрешенных для поля и кладем
+    // в хранилище под хешем содержимого, если такого файла там еще нет.
+    // uploaded - сколько уже загружено этой формой. Ok(None) - файл не выбран
+    let filename = match field.content_disposition().get_filename() {
+        Some(name) if name != "" => sanitize_filename(name),
+        _ => return Ok(None),
+    };
+
+    let io_error = |e: std::io::Error| Error::InternalServerError(e.to_string());
+    let root = get_upload_tmp_root();
+    tokio::fs::create_dir_all(&root).await.map_err(io_error)?;
+    // при любом выходе из функции временный файл удаляет Drop
+    let mut tmp = TempUpload {
+        path:  format!("{}{}.form", root, uuid::Uuid::new_v4()),
+        size:  0,
+        hash:  String::new(),
+        head:  Vec::new(),
+        owned: true,
+    };
+    let mut file = tokio::fs::File::create(&tmp.path).await.map_err(io_error)?;
+    let mut hasher = Sha256::new();
+    while let Some(chunk) = field.next().await {
+        let chunk = chunk.map_err(|_| upload_error("upload interrupted".to_string()))?;
+        tmp.size += chunk.len();
+        if tmp.size > kind.max_size() {
+            return Err(upload_error(format!(
+                "file '{}' is larger than {} MB!", filename, kind.max_size() / 1024 / 1024,
+            )));
+        }
+        if *uploaded + tmp.size > MAX_REQUEST_SIZE {
+            return Err(upload_error(format!(
+                "files are larger than {} MB in total!", MAX_REQUEST_SIZE / 1024 / 1024,
+            )));
+        }
+        tmp.push_head(&chunk);
+        hasher.update(&chunk);
+        file.write_all(&chunk).await.map_err(io_error)?;
+    }
+    file.flush().await.map_err(io_error)?;
+    drop(file);
+    if tmp.size == 0 {
+        return Ok(None);
+    }
+    *uploaded += tmp.size;
+    tmp.hash = format!("{:x}", hasher.finalize());
+    return store_upload(tmp, filename, owner_id, kind, storage, None).await.map(Some);
+}
+
+pub fn check_upload(tmp: &TempUpload, filename: &str, kind: UploadKind, checksum: Option<&str>) -> Result<(&'static str, String), Error> {
+    // (mime, расширение) принятого файла или ошибка для клиента:
+    // тип по содержимому, сверка с разрешенными, sha256, размер картинок
+    let (mime, ext) = match sniff_mime(&tmp.head, filename) {
+        Some((mime, ext)) if kind.allowed_mimes().contains(&mime) => (mime, ext),
+        _ => return Err(upload_error(format!("file type of '{}' is not allowed!", filename))),
+    };
+    if checksum.is_some() && checksum.unwrap().to_lowercase() != tmp.hash {
+        return Err(upload_error(format!("checksum of '{}' does not match!", filename)));
+    }
+    // картинки обрабатываются в памяти
+    if mime.starts_with("image/") && tmp.size > MAX_IMAGE_SIZE {
+        return Err(upload_error(format!(
+            "image '{}' is larger than {} MB!", filename, MAX_IMAGE_SIZE / 1024 / 1024,
+        )));
+    }
+    return Ok((mime, ext));
+}
+
+pub async fn store_upload (
+    tmp:      TempUpload,
+    filename: String,
+    owner_id: i32,
+    kind:     UploadKind,
+    storage:  &Arc<dyn Storage>,
+    checksum: Option<&str>,
+) -> Result<UploadedFiles, Error> {
+    // принятый файл (из формы или докачки): проверка (см. check_upload),
+    // запись в хранилище. checksum - sha256, заявленный клиентом;
+    // при расхождении файл не сохраняется
+    let (mime, ext) = check_upload(&tmp, &filename, kind, checksum)?;
+    let hash = tmp.hash.clone();
+    let is_image = mime.starts_with("image/");
+
+    // такой файл уже загружали - ссылаемся на него же. pin не даст
+    // удалить его, пока вызывающий не сохранит ссылку
+    let (_storage, _hash) = (storage.clone(), hash.clone());
+    let found = web::block(move || {
+        Blob::get_by_hash(&_hash, &*_storage)
+            .and_then(|blob| Blob::pin(&blob.src))
+            .map(|blob| {
+                let image = Image::get(&blob.src);
+                (blob, image)
+            })
+    }).await?;
+    if let Some((blob, image)) = found {
+        return Ok(UploadedFiles {
+            name:   filename,
+            key:    storage.get_key(&blob.src).unwrap_or_default(),
+            src:    blob.src,
+            mime:   blob.mime,
+            size:   blob.size,
+            width:  image.as_ref().map(|i| i.width),
+            height: image.as_ref().map(|i| i.height),
+        });
+    }
+
+    let mut file = UploadedFiles::new(filename, &ext, mime.to_string(), tmp.size, &hash, &**storage);
+    let (_storage, key, src) = (storage.clone(), file.key.clone(), file.get_src());
+    // Err(Some) - картинка не читается, Err(None) - хранилище не приняло файл
+    let result = web::block(move || -> Result<Option<(i32, i32, i32)>, Option<String>> {
+        let tmp = tmp;
+        if !is_image {
+            // строка blobs - до записи файла, см. Blob::create
+            Blob::create(&src, &hash, owner_id, mime, tmp.size as i32);
+            if _storage.put_file(&key, &tmp.path, mime).is_err() {
+                Blob::unpin(&src);
+                Blob::release(&*_storage, vec![src]);
+                return Err(None);
+            }
+            // страницы, текст, длительность
+            queue_metadata(_storage, src, mime.to_string(), tmp.size);
+            return Ok(None);
+        }
+
+        // картинки пересохраняются без EXIF, размер файла меняется
+        let data = tmp.read().map_err(|_| None)?;
+        let image = process_image(&key, &data).map_err(Some)?;
+        let data = image.original.unwrap_or(data);
+        Blob::create(&src, &hash, owner_id, mime, data.len() as i32);
+        let saved = image.variants.iter()
+            .all(|(variant_key, variant_mime, variant_data)| _storage.put(variant_key, variant_data, variant_mime).is_ok())
+            && _storage.put(&key, &data, mime).is_ok();
+        if !saved {
+            Blob::unpin(&src);
+            Blob::release(&*_storage, vec![src]);
+            return Err(None);
+        }
+        Ok(Some((image.width, image.height, data.len() as i32)))
+    }).await?;
+
+    match result {
+        Ok(Some((width, height, size))) => {
+            file.width = Some(width);
+            file.height = Some(height);
+            file.size = size;
+        },
+        Ok(None) => (),
+        Err(Some(_)) => return Err(upload_error(format!("image '{}' is damaged!", file.name))),
+        Err(None) => return Err(Error::InternalServerError("file not saved".to_string())),
+    };
+    let (_src, _sizes) = (file.get_src(), file.width.zip(file.height));
+    if let Some((width, height)) = _sizes {
+        web::block(move || Image::create(&_src, width, height)).await?;
+    }
+    return Ok(file);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_tmp(data: &[u8]) -> TempUpload {
+        // в check_upload файл на диске не нужен
+        TempUpload {
+            path:  String::new(),
+            size:  data.len(),
+            hash:  format!("{:x}", Sha256::digest(data)),
+            head:  data.to_vec(),
+            owned: false,
+        }
+    }
+
+    #[test]
+    fn check_upload_accepts_matching_checksum() {
+        let tmp = get_tmp(b"%PDF-1.4 test");
+        let hash = tmp.hash.to_uppercase();
+        let (mime, ext) = check_upload(&tmp, "a.pdf", UploadKind::Media, Some(&hash)).unwrap();
+        assert_eq!((mime, ext.as_str()), ("application/pdf", "pdf"));
+    }
+
+    #[test]
+    fn check_upload_rejects_wrong_checksum() {
+        let tmp = get_tmp(b"%PDF-1.4 test");
+        let result = check_upload(&tmp, "a.pdf", UploadKind::Media, Some(&"0".repeat(64)));
+        assert!(matches!(result, Err(Error::BadRequest(_))));
+    }
+
+    #[test]
+    fn check_upload_rejects_type_not_allowed() {
+        // текст можно приложить к заказу, но не к объекту
+        let tmp = get_tmp(b"plain text");
+        assert!(check_upload(&tmp, "a.pdf", UploadKind::Media, None).is_err());
+        assert!(check_upload(&tmp, "a.txt", UploadKind::Order, None).is_ok());
+    }
+
+    #[test]
+    fn sniff_mime_accepts_cut_utf8() {
+        // начало файла может оборваться посреди символа
+        let text = "привет".as_bytes();
+        assert_eq!(sniff_mime(&text[..3], "a.txt").map(|m| m.0), Some("text/plain"));
+        assert_eq!(sniff_mime(&[0xFF, 0x00, 0x01], "a.txt"), None);
+    }
+}
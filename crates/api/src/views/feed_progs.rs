@@ -0,0 +1,114 @@
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    web,
+    web::block,
+};
+use serde::Deserialize;
+use crate::utils::{
+    get_feed,
+    get_http_date,
+    get_site_url,
+    ErrorParams,
+};
+use crate::errors::Error;
+
+
+pub fn feed_routes(config: &mut web::ServiceConfig) {
+    config.route("/rss", web::get().to(rss_page));
+    config.route("/atom", web::get().to(atom_page));
+}
+
+#[derive(Deserialize)]
+pub struct FeedData {
+    pub types:    Option<i16>,
+    pub cat_slug: Option<String>,
+    pub tag:      Option<String>,
+}
+
+fn get_header<'a>(req: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    return req.headers().get(name)?.to_str().ok();
+}
+
+async fn feed_page(req: HttpRequest, is_atom: bool) -> Result<HttpResponse, Error> {
+    // лента блога (1), обучающих статей (4) или работ (5).
+    // cat_slug и tag сужают ленту до категории / тега.
+    let params_some = web::Query::<FeedData>::from_query(&req.query_string());
+    if params_some.is_err() {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "parametrs not found!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+    let params = params_some.unwrap();
+    let types = params.types.unwrap_or(1);
+    if ![1, 4, 5].contains(&types) {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "parametr 'types' must be 1, 4 or 5!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+
+    let cat_slug = params.cat_slug.clone().filter(|s| !s.is_empty());
+    let tag = params.tag.clone().filter(|s| !s.is_empty());
+    let self_url = get_site_url() + &req.uri().to_string();
+    let feed_some = block(move || get_feed(types, cat_slug, tag, self_url)).await?;
+    if feed_some.is_none() {
+        return Err(Error::NotFound("feed not found".to_string()));
+    }
+    let feed = feed_some.unwrap();
+
+    // условный GET: отдаем 304, если у клиента актуальная версия
+    let etag = feed.get_etag();
+    let last_modified = feed.get_last_modified().map(|d| get_http_date(&d));
+    let if_none_match = get_header(&req, "if-none-match");
+    let if_modified_since = get_header(&req, "if-modified-since");
+
+    let is_not_modified: bool;
+    if if_none_match.is_some() {
+        is_not_modified = if_none_match
+            .unwrap()
+            .split(",")
+            .map(|t| t.trim())
+            // для If-None-Match сравнение слабое: W/"x" совпадает с "x"
+            .any(|t| t.trim_start_matches("W/") == etag || t == "*");
+    }
+    else if if_modified_since.is_some() && last_modified.is_some() {
+        let since = chrono::DateTime::parse_from_rfc2822(if_modified_since.unwrap());
+        let modified = chrono::DateTime::parse_from_rfc2822(last_modified.as_deref().unwrap());
+        is_not_modified = since.is_ok() && modified.is_ok() && modified.unwrap() <= since.unwrap();
+    }
+    else {
+        is_not_modified = false;
+    }
+
+    let mut response = if is_not_modified {
+        HttpResponse::NotModified()
+    }
+    else {
+        HttpResponse::Ok()
+    };
+    response.insert_header(("ETag", etag));
+    if last_modified.is_some() {
+        response.insert_header(("Last-Modified", last_modified.unwrap()));
+    }
+    if is_not_modified {
+        return Ok(response.finish());
+    }
+
+    if is_atom {
+        return Ok(response
+            .content_type("application/atom+xml; charset=utf-8")
+            .body(feed.get_atom()));
+    }
+    return Ok(response
+        .content_type("application/rss+xml; charset=utf-8")
+        .body(feed.get_rss()));
+}
+
+pub async fn rss_page(req: HttpRequest) -> Result<HttpResponse, Error> {
+    return feed_page(req, false).await;
+}
+pub async fn atom_page(req: HttpRequest) -> Result<HttpResponse, Error> {
+    return feed_page(req, true).await;
+}
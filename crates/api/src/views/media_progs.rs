@@ -0,0 +1,198 @@
+use actix_web::{
+    HttpRequest,
+    web,
+    web::{block, Json},
+};
+use crate::schema;
+use crate::models::{
+    File,
+    Item,
+    MediaFile,
+    MediaFilters,
+};
+use serde::{Deserialize, Serialize};
+use crate::utils::{
+    establish_connection,
+    get_request_user,
+    ErrorParams, UserResp, TOKEN,
+};
+use crate::diesel::{
+    RunQueryDsl,
+    ExpressionMethods,
+    QueryDsl,
+};
+use crate::views::{get_date, get_next_day};
+use crate::errors::Error;
+
+
+pub fn media_routes(config: &mut web::ServiceConfig) {
+    config.route("/media_library", web::get().to(media_library_page));
+    config.route("/reuse_file", web::post().to(reuse_file));
+    config.route("/reorder_files", web::post().to(reorder_files));
+}
+
+fn permission_denied() -> Error {
+    let body = serde_json::to_string(&ErrorParams {
+        error: "Permission Denied".to_string(),
+    }).unwrap();
+    return Error::BadRequest(body);
+}
+
+fn get_own_item(item_id: Option<i32>, request_user: &UserResp) -> Result<Item, Error> {
+    // объект, галерею которого меняет пользователь: свой или любой для админа
+    if item_id.is_none() || item_id.unwrap() < 1 {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "parametr 'item_id' not found!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+    let _connection = establish_connection();
+    let _item = match schema::items::table
+        .filter(schema::items::id.eq(item_id.unwrap()))
+        .first::<Item>(&_connection) {
+            Ok(item) => item,
+            Err(_) => return Err(Error::NotFound("item not found".to_string())),
+        };
+    if _item.user_id != request_user.id && request_user.perm != 60 {
+        return Err(permission_denied());
+    }
+    return Ok(_item);
+}
+
+
+// page=2 user_id=1 types=1 item_types=3 q=логотип date_from=2024-01-01 date_to=2024-01-31
+#[derive(Deserialize)]
+pub struct MediaLibraryData {
+    pub page:       Option<i32>,
+    pub user_id:    Option<i32>, // только для админа, остальные видят свои файлы
+    pub types:      Option<i16>,
+    pub item_types: Option<i16>,
+    pub q:          Option<String>,
+    pub date_from:  Option<String>,
+    pub date_to:    Option<String>,
+}
+#[derive(Serialize)]
+pub struct MediaLibraryResp {
+    pub object_list:      Vec<MediaFile>,
+    pub next_page_number: i16,
+}
+pub async fn media_library_page(req: HttpRequest) -> Result<Json<MediaLibraryResp>, Error> {
+    let _request_user = get_request_user(&req, 0).await;
+    if _request_user.id < 1 {
+        return Err(permission_denied());
+    }
+    let params_some = web::Query::<MediaLibraryData>::from_query(&req.query_string());
+    if params_some.is_err() {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "parametrs not found!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+    let params = params_some.unwrap();
+
+    let filters = MediaFilters {
+        user_id:    match _request_user.perm {
+            60 => params.user_id,
+            _ => Some(_request_user.id),
+        },
+        types:      params.types,
+        item_types: params.item_types,
+        q:          params.q.clone(),
+        date_from:  get_date(&params.date_from, "date_from")?.map(|d| d.and_hms(0, 0, 0)),
+        date_to:    match get_date(&params.date_to, "date_to")? {
+            Some(d) => Some(get_next_day(d, "date_to")?.and_hms(0, 0, 0)),
+            None => None,
+        },
+        // вложения переписки видит только админ
+        hide_messages: _request_user.perm != 60,
+    };
+    let page = params.page.unwrap_or(1);
+    let (object_list, next_page_number) = block(move || File::get_library(&filters, page, 40)).await?;
+    return Ok(Json(MediaLibraryResp {
+        object_list:      object_list,
+        next_page_number: next_page_number,
+    }));
+}
+
+#[derive(Deserialize)]
+pub struct ReuseFileData {
+    pub token:   Option<String>,
+    pub id:      Option<i32>, // файл из медиатеки
+    pub item_id: Option<i32>, // объект, в галерею которого он добавляется
+}
+pub async fn reuse_file(req: HttpRequest, data: Json<ReuseFileData>) -> Result<Json<MediaFile>, Error> {
+    let _request_user = get_request_user(&req, 0).await;
+    if _request_user.id < 1 {
+        return Err(permission_denied());
+    }
+    if data.token.is_none() || data.token.as_deref().unwrap() != TOKEN {
+        return Err(permission_denied());
+    }
+    if data.id.is_none() || data.id.unwrap() < 1 {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "parametr 'id' not found!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+
+    let _item = get_own_item(data.item_id, &_request_user)?;
+    let _connection = establish_connection();
+    let _file = match schema::files::table
+        .filter(schema::files::id.eq(data.id.unwrap()))
+        .first::<File>(&_connection) {
+            Ok(file) => file,
+            Err(_) => return Err(Error::NotFound("file not found".to_string())),
+        };
+    // брать можно свои файлы, админ - любые
+    if _file.user_id != _request_user.id && _request_user.perm != 60 {
+        return Err(permission_denied());
+    }
+    // в галерее объекта бывают только фото, видео, аудио и документы (1-4),
+    // вложения сообщений туда не переносятся
+    if _file.types < 1 || _file.types > 4 || _file.item_types == 11 {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "this file can't be added to a gallery!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+
+    let user_id = _request_user.id;
+    let _new_file = block(move || _file.reuse(user_id, _item.id, _item.types)).await??;
+    return Ok(Json(_new_file.get_media_file()));
+}
+
+#[derive(Deserialize)]
+pub struct ReorderFilesData {
+    pub token:   Option<String>,
+    pub item_id: Option<i32>,
+    pub ids:     Option<Vec<i32>>, // файлы объекта в новом порядке
+}
+pub async fn reorder_files(req: HttpRequest, data: Json<ReorderFilesData>) -> Result<Json<usize>, Error> {
+    // порядок всей галереи одним запросом, в ответе - число переставленных файлов
+    let _request_user = get_request_user(&req, 0).await;
+    if _request_user.id < 1 {
+        return Err(permission_denied());
+    }
+    if data.token.is_none() || data.token.as_deref().unwrap() != TOKEN {
+        return Err(permission_denied());
+    }
+    if data.ids.is_none() || data.ids.as_ref().unwrap().is_empty() {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "parametr 'ids' not found!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+
+    // position - SMALLINT
+    if data.ids.as_ref().unwrap().len() > i16::MAX as usize {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "too many files!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+
+    let _item = get_own_item(data.item_id, &_request_user)?;
+    let ids = data.ids.clone().unwrap();
+    let count = block(move || File::reorder(_item.id, _item.types, &ids)).await??;
+    return Ok(Json(count));
+}
@@ -5,6 +5,10 @@ pub mod auth;
 pub mod tag_progs;
 pub mod search_progs;
 pub mod serve_progs;
+pub mod sitemap_progs;
+pub mod feed_progs;
+pub mod media_progs;
+pub mod upload_progs;
 
 pub use self::{
     order_progs::*,
@@ -13,5 +17,9 @@ pub use self::{
     tag_progs::*,
     search_progs::*,
     serve_progs::*,
+    sitemap_progs::*,
+    feed_progs::*,
+    media_progs::*,
+    upload_progs::*,
     auth::*,
 };
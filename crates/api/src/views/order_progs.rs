@@ -1,5 +1,6 @@
 use actix_web::{
     HttpRequest,
+    HttpResponse,
     web,
     web::Json,
 };
@@ -14,6 +15,8 @@ use crate::utils::{
     get_request_user,
     get_or_create_cookie_user_id,
     get_cookie_user_id, get_is_ajax,
+    sign_url, verify_url_sign,
+    get_stream,
     ErrorParams, TOKEN, UserResp, 
 };
 use crate::schema;
@@ -22,11 +25,26 @@ use crate::models::{
     NewOrder,
     OrderFile,
     NewOrderFile,
+    Blob,
+    FileMetadata,
 };
 use serde::{Deserialize, Serialize};
 use actix_multipart::Multipart;
 use crate::errors::Error;
+use crate::AppState;
 use actix_web::dev::ConnectionInfo;
+use actix_web::http::header::{
+    Charset,
+    ContentDisposition,
+    DispositionParam,
+    DispositionType,
+    ExtendedValue,
+    HeaderName,
+    HeaderValue,
+    CACHE_CONTROL,
+    CONTENT_TYPE,
+};
+use actix_files::NamedFile;
 
 
 pub fn order_routes(config: &mut web::ServiceConfig) {
@@ -35,8 +53,12 @@ pub fn order_routes(config: &mut web::ServiceConfig) {
     config.route("/order", web::get().to(get_order_page));
     config.route("/order", web::post().to(create_order));
     config.route("/delete_order", web::post().to(delete_order));
+    config.route("/order_file", web::get().to(get_order_file));
 }
 
+// сколько действует подписанная ссылка на файл заказа, секунд
+const ORDER_FILE_URL_TTL: i64 = 60 * 60;
+
 #[derive(Serialize)]
 pub struct OrdersPageResp {
     pub request_user:     UserResp,
@@ -84,18 +106,43 @@ pub async fn get_user_orders_page(req: HttpRequest) -> Result<Json<OrdersPageRes
     }));
 }
 
+#[derive(Serialize)]
+pub struct OrderFileResp {
+    pub id:            i32,
+    pub original_name: String,
+    pub mime:          String,
+    pub size:          i32,
+    pub url:           String, // подписанная ссылка, см. ORDER_FILE_URL_TTL
+    pub meta:          Option<FileMetadata>,
+}
+impl OrderFileResp {
+    fn new(file: OrderFile, meta: Option<FileMetadata>, secret: &str) -> OrderFileResp {
+        let expires = chrono::Utc::now().timestamp() + ORDER_FILE_URL_TTL;
+        let path = format!("/order_file?id={}", file.id);
+        let sign = sign_url(&path, expires, secret);
+        OrderFileResp {
+            id:            file.id,
+            original_name: file.original_name,
+            mime:          file.mime,
+            size:          file.size,
+            url:           format!("{}&expires={}&sign={}", path, expires, sign),
+            meta:          meta,
+        }
+    }
+}
+
 #[derive(Serialize)]
 pub struct OrderPageResp {
     pub request_user: UserResp,
     pub object:       Order,
-    pub files:        Vec<OrderFile>,
+    pub files:        Vec<OrderFileResp>,
 }
 #[derive(Deserialize)]
 pub struct OrderPageData {
     pub is_ajax: Option<i16>,
     pub id:      Option<i32>,
 }
-pub async fn get_order_page(req: HttpRequest) -> Result<Json<OrderPageResp>, Error> {
+pub async fn get_order_page(req: HttpRequest, state: web::Data<AppState>) -> Result<Json<OrderPageResp>, Error> {
     let params_some = web::Query::<OrderPageData>::from_query(&req.query_string());
     if params_some.is_err() {
         let body = serde_json::to_string(&ErrorParams {
@@ -125,10 +172,18 @@ pub async fn get_order_page(req: HttpRequest) -> Result<Json<OrderPageResp>, Err
         return Err(Error::BadRequest(body));
     }
 
-    let _files = schema::order_files::table
+    let _order_files = schema::order_files::table
         .filter(schema::order_files::order_id.eq(&_order.id))
         .load::<OrderFile>(&_connection)
         .expect("E");
+    let _metadata = FileMetadata::get_list(&_connection, &_order_files.iter().map(|f| f.src.clone()).collect());
+    let _files = _order_files
+        .into_iter()
+        .map(|f| {
+            let meta = _metadata.iter().find(|m| m.src == f.src).cloned();
+            OrderFileResp::new(f, meta, state.key.as_ref())
+        })
+        .collect();
     
     return Ok(Json(OrderPageResp {
         request_user: get_request_user(&req, get_is_ajax(&req)).await,
@@ -142,7 +197,7 @@ pub async fn get_order_page(req: HttpRequest) -> Result<Json<OrderPageResp>, Err
 pub struct NewOrderResp {
     pub order_id: i32,
 }
-pub async fn create_order(conn: ConnectionInfo,req: HttpRequest, mut payload: Multipart) -> Result<Json<NewOrderResp>, Error> {
+pub async fn create_order(conn: ConnectionInfo, req: HttpRequest, state: web::Data<AppState>, mut payload: Multipart) -> Result<Json<NewOrderResp>, Error> {
     use crate::schema::serve::dsl::serve;
     use crate::models::{
         NewTechCategoriesItem,
@@ -156,17 +211,19 @@ pub async fn create_order(conn: ConnectionInfo,req: HttpRequest, mut payload: Mu
 
     let _connection = establish_connection();
     let user_id = get_or_create_cookie_user_id(conn, &req).await;
-    let form = order_form(payload.borrow_mut(), user_id).await;
-    
-    if form.token != TOKEN.to_string() {
+    // заказчик проверяется до приема файлов
+    if user_id == 0 {
         let body = serde_json::to_string(&ErrorParams {
-            error: "Permission Denied".to_string(),
+            error: "Информация о заказчике не найдена".to_string(),
         }).unwrap();
         return Err(Error::BadRequest(body));
     }
-    else if user_id == 0 {
+    // вложения заказа - в закрытом хранилище, отдаются через /order_file
+    let mut form = order_form(payload.borrow_mut(), user_id, &state.private_storage).await?;
+    
+    if form.token != TOKEN.to_string() {
         let body = serde_json::to_string(&ErrorParams {
-            error: "Информация о заказчике не найдена".to_string(),
+            error: "Permission Denied".to_string(),
         }).unwrap();
         return Err(Error::BadRequest(body));
     }
@@ -189,13 +246,14 @@ pub async fn create_order(conn: ConnectionInfo,req: HttpRequest, mut payload: Mu
         for file in form.files.iter() {
             let new_file = NewOrderFile::create (
                 _order.id,
-                file.to_string()
+                file
             );
             diesel::insert_into(schema::order_files::table)
                 .values(&new_file)
                 .execute(&_connection)
                 .expect("E.");
         };
+        form.pins.saved();
 
         // создаем опции услуги и записываем id опций в вектор.
         let mut serve_ids = Vec::new();
@@ -264,7 +322,7 @@ pub struct DeleteOrderData {
     pub token: Option<String>,
     pub id:    Option<i32>,
 }
-pub async fn delete_order(req: HttpRequest, data: Json<DeleteOrderData>) -> Result<Json<i16>, Error> {
+pub async fn delete_order(req: HttpRequest, state: web::Data<AppState>, data: Json<DeleteOrderData>) -> Result<Json<i16>, Error> {
     use schema::orders::dsl::orders;
 
     if data.token.is_none() || data.token.as_deref().unwrap() != TOKEN {
@@ -291,6 +349,7 @@ pub async fn delete_order(req: HttpRequest, data: Json<DeleteOrderData>) -> Resu
         use crate::schema::{
             serve_items::dsl::serve_items,
             tech_categories_items::dsl::tech_categories_items,
+            order_files::dsl::order_files,
         };
 
         diesel::delete (
@@ -307,8 +366,22 @@ pub async fn delete_order(req: HttpRequest, data: Json<DeleteOrderData>) -> Resu
             )
             .execute(&_connection)
             .expect("E");
+        let _src_list = order_files
+            .filter(schema::order_files::order_id.eq(_order.id))
+            .select(schema::order_files::src)
+            .load::<String>(&_connection)
+            .expect("E");
+        diesel::delete(order_files.filter(schema::order_files::order_id.eq(_order.id)))
+            .execute(&_connection)
+            .expect("E");
         diesel::delete(&_order).execute(&_connection).expect("E");
 
+        // старые вложения могли остаться в общем хранилище
+        let (_private_storage, _storage) = (state.private_storage.clone(), state.storage.clone());
+        web::block(move || {
+            Blob::release(&*_private_storage, _src_list.clone());
+            Blob::release(&*_storage, _src_list);
+        }).await?;
         return Ok(Json(1));
     }
     else {
@@ -318,3 +391,105 @@ pub async fn delete_order(req: HttpRequest, data: Json<DeleteOrderData>) -> Resu
         return Err(Error::BadRequest(body));
     }
 }
+
+#[derive(Deserialize)]
+pub struct OrderFileData {
+    pub id:      Option<i32>,
+    pub expires: Option<i64>,
+    pub sign:    Option<String>,
+}
+pub async fn get_order_file(req: HttpRequest, state: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    // файл заказа отдается по подписанной ссылке (см. OrderFileResp),
+    // заказчику по cookie или менеджеру
+    let params_some = web::Query::<OrderFileData>::from_query(&req.query_string());
+    if params_some.is_err() {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "parametr 'id' not found!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+    let params = params_some.unwrap();
+    if params.id.is_none() || params.id.unwrap() < 1 {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "parametr 'id' not found!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+
+    let id = params.id.unwrap();
+    let _connection = establish_connection();
+    let _file = match schema::order_files::table
+        .filter(schema::order_files::id.eq(id))
+        .first::<OrderFile>(&_connection) {
+            Ok(file) => file,
+            Err(_) => return Err(Error::NotFound("file not found".to_string())),
+        };
+    let _order = schema::orders::table
+        .filter(schema::orders::id.eq(_file.order_id))
+        .first::<Order>(&_connection)
+        .expect("E");
+
+    let is_signed = params.expires.is_some() && params.sign.is_some() && verify_url_sign (
+        &format!("/order_file?id={}", id),
+        params.expires.unwrap(),
+        params.sign.as_deref().unwrap(),
+        state.key.as_ref(),
+    );
+    if !is_signed
+        && get_cookie_user_id(&req).await != _order.user_id
+        && get_request_user(&req, 0).await.perm != 60 {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "Permission Denied".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+
+    // вложения из общего хранилища не отдаем: их переносит `migrate-order-files`
+    let _storage = state.private_storage.clone();
+    let key = match _storage.get_key(&_file.src) {
+        Some(key) => key,
+        None => return Err(Error::NotFound("file not found".to_string())),
+    };
+
+    // имя файла у клиента может быть не латиницей - отдаем его в filename*
+    let disposition = ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters:  vec![DispositionParam::FilenameExt(ExtendedValue {
+            charset:      Charset::Ext("UTF-8".to_string()),
+            language_tag: None,
+            value:        _file.original_name.clone().into_bytes(),
+        })],
+    };
+
+    // файл не читаем в память: с диска отдает NamedFile (с Range),
+    // из S3 - потоком
+    if let Some(path) = _storage.get_local_path(&key) {
+        let named = match NamedFile::open_async(&path).await {
+            Ok(named) => named,
+            Err(_) => return Err(Error::NotFound("file not found".to_string())),
+        };
+        let mut response = named
+            .set_content_disposition(disposition)
+            .use_etag(false)
+            .use_last_modified(false)
+            .into_response(&req);
+        let headers = response.headers_mut();
+        if let Ok(mime) = HeaderValue::from_str(&_file.mime) {
+            headers.insert(CONTENT_TYPE, mime);
+        }
+        headers.insert(HeaderName::from_static("x-content-type-options"), HeaderValue::from_static("nosniff"));
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("private, no-store"));
+        return Ok(response);
+    }
+
+    let (_exists_storage, _key) = (_storage.clone(), key.clone());
+    if !web::block(move || _exists_storage.exists(&_key)).await? {
+        return Err(Error::NotFound("file not found".to_string()));
+    }
+    return Ok(HttpResponse::Ok()
+        .content_type(_file.mime.as_str())
+        .insert_header(disposition)
+        .insert_header(("X-Content-Type-Options", "nosniff"))
+        .insert_header(("Cache-Control", "private, no-store"))
+        .streaming(get_stream(_storage, key)));
+}
@@ -7,15 +7,16 @@ use actix_web::{
 use crate::schema;
 use crate::models::{
     Item, Categories, Feedback, CookieUser,
-    Tag, Cat, SmallTag, CatDetail, Serve,
+    Tag, Cat, SmallCat, SmallTag, CatDetail, Serve,
     Blog, Service, Store, Wiki, Work, ContentBlock,
     ServeCategories, TechCategories, CookieStat,
     SmallFile, File, FeaturedItem, StatPage,
+    SearchItem, SearchFacets, MESSAGE_FILES_TYPES, Image,
 };
 use crate::utils::{
     establish_connection, get_request_user,
     get_stat_page, get_is_ajax_page, get_is_ajax,
-    ErrorParams, UserResp, OwnerResp,
+    ErrorParams, UserResp, OwnerResp, ImageVariant,
 };
 use crate::diesel::{
     RunQueryDsl,
@@ -24,6 +25,7 @@ use crate::diesel::{
 };
 use serde::{Deserialize, Serialize};
 use crate::errors::Error;
+use crate::views::get_search_filters;
 use actix_web::dev::ConnectionInfo;
 
 
@@ -49,6 +51,7 @@ pub fn pages_routes(config: &mut web::ServiceConfig) {
 
     config.route("/edit_file", web::get().to(edit_file_page));
     config.route("/image", web::get().to(image_page));
+    config.route("/album", web::get().to(album_page));
 
     config.route("/blog", web::get().to(get_blog_page));
     config.route("/help", web::get().to(get_help_page));
@@ -740,8 +743,8 @@ pub async fn edit_file_page(req: HttpRequest) -> Result<Json<EditFileResp>, Erro
 #[derive(Serialize)]
 pub struct ImageResp {
     pub item: Item,
-    pub prev: Option<File>,
-    pub next: Option<File>,
+    pub prev: Option<SmallFile>,
+    pub next: Option<SmallFile>,
 }
 #[derive(Deserialize)]
 pub struct ImageData {
@@ -762,7 +765,7 @@ pub async fn image_page(req: HttpRequest) -> Result<Json<ImageResp>, Error> {
     }
 
     let params = params_some.unwrap();
-    if params.id.is_none() && params.id.unwrap() < 1 {
+    if params.id.is_none() || params.id.unwrap() < 1 {
         let body = serde_json::to_string(&ErrorParams {
             error: "parametr 'id' not found!".to_string(),
         }).unwrap();
@@ -771,43 +774,33 @@ pub async fn image_page(req: HttpRequest) -> Result<Json<ImageResp>, Error> {
 
     let _id = params.id.unwrap();
     let _connection = establish_connection();
-    let _file = files
+    let _file = match files
         .filter(schema::files::id.eq(_id))
-        .first::<File>(&_connection)
-        .expect("E.");
+        .first::<File>(&_connection) {
+            Ok(file) => file,
+            Err(_) => return Err(Error::NotFound("file not found".to_string())),
+        };
 
-    let _item = items
+    // вложения переписки (item_types 11) и файлы удаленных объектов
+    // отдельной страницы не имеют
+    let _item = match items
         .filter(schema::items::id.eq(_file.item_id))
         .filter(schema::items::types.eq(_file.item_types))
-        .first::<Item>(&_connection)
-        .expect("E.");
+        .filter(schema::items::item_types.lt(10))
+        .first::<Item>(&_connection) {
+            Ok(item) if _file.item_types != MESSAGE_FILES_TYPES => item,
+            _ => return Err(Error::NotFound("file not found".to_string())),
+        };
 
-    let _images = _item.get_images_ids();
-    let _images_len = _images.len();
-    let mut prev: Option<File> = None;
-    let mut next: Option<File> = None;
-
-    for (i, obj) in _images.iter().enumerate().rev() {
-        if obj == &_id {
-            if (i + 1) != _images_len {
-                let _next = Some(&_images[i + 1]);
-                next = Some(files
-                    .filter(schema::files::id.eq(_next.unwrap()))
-                    .filter(schema::files::types.eq(_item.types))
-                    .first::<File>(&_connection)
-                    .expect("E"));
-            };
-            if i != 0 {
-                let _prev = Some(&_images[i - 1]);
-                prev = Some(files
-                    .filter(schema::files::id.eq(_prev.unwrap()))
-                    .filter(schema::files::types.eq(_item.types))
-                    .first::<File>(&_connection)
-                    .expect("E"));
-            };
-            break;
+    // соседние фото галереи; имя файла у загрузившего и его id не отдаем
+    let photos = _item.get_album();
+    let (mut prev, mut next) = (None, None);
+    if let Some(i) = photos.iter().position(|p| p.id == _id) {
+        if i > 0 {
+            prev = photos.get(i - 1).cloned();
         }
-    };
+        next = photos.get(i + 1).cloned();
+    }
 
     return Ok(Json(ImageResp {
         item: _item,
@@ -817,6 +810,91 @@ pub async fn image_page(req: HttpRequest) -> Result<Json<ImageResp>, Error> {
 }
 
 
+#[derive(Serialize)]
+pub struct AlbumResp {
+    pub item_id: i32,
+    pub types:   i16, // блог, услуга, товар ......
+    pub title:   String,
+    pub slug:    String,
+    pub photos:  Vec<SmallFile>, // в порядке галереи
+    pub current: Option<i32>,    // id открытого фото
+    pub prev:    Option<i32>,
+    pub next:    Option<i32>,
+}
+#[derive(Deserialize)]
+pub struct AlbumData {
+    pub item_id: Option<i32>,
+    pub id:      Option<i32>, // фото, с которого открыт альбом
+}
+pub async fn album_page(req: HttpRequest) -> Result<Json<AlbumResp>, Error> {
+    // альбом фото объекта любого типа: по item_id или по id одного из фото
+    let params_some = web::Query::<AlbumData>::from_query(&req.query_string());
+    if params_some.is_err() {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "parametrs not found!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+    let params = params_some.unwrap();
+    let _connection = establish_connection();
+
+    let _item = if params.id.is_some() && params.id.unwrap() > 0 {
+        let _file = match schema::files::table
+            .filter(schema::files::id.eq(params.id.unwrap()))
+            .first::<File>(&_connection) {
+                Ok(file) => file,
+                Err(_) => return Err(Error::NotFound("file not found".to_string())),
+            };
+        schema::items::table
+            .filter(schema::items::id.eq(_file.item_id))
+            .filter(schema::items::types.eq(_file.item_types))
+            .first::<Item>(&_connection)
+    }
+    else if params.item_id.is_some() && params.item_id.unwrap() > 0 {
+        schema::items::table
+            .filter(schema::items::id.eq(params.item_id.unwrap()))
+            .first::<Item>(&_connection)
+    }
+    else {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "parametr 'item_id' or 'id' not found!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    };
+    // удаленные объекты (item_types > 10) не показываем
+    let _item = match _item {
+        Ok(item) if item.item_types < 10 => item,
+        _ => return Err(Error::NotFound("item not found".to_string())),
+    };
+
+    let photos = _item.get_album();
+    let index = match params.id {
+        Some(id) => photos.iter().position(|p| p.id == id),
+        None if !photos.is_empty() => Some(0),
+        None => None,
+    };
+    let (current, prev, next) = match index {
+        Some(i) => (
+            Some(photos[i].id),
+            if i > 0 { Some(photos[i - 1].id) } else { None },
+            photos.get(i + 1).map(|p| p.id),
+        ),
+        None => (None, None, None),
+    };
+
+    return Ok(Json(AlbumResp {
+        item_id: _item.id,
+        types:   _item.types,
+        title:   _item.title,
+        slug:    _item.slug,
+        photos:  photos,
+        current: current,
+        prev:    prev,
+        next:    next,
+    }));
+}
+
+
 #[derive(Serialize)]
 pub struct ItemFilesResp {
     pub photos: Vec<SmallFile>,
@@ -836,6 +914,7 @@ pub struct ObjectPageResp {
     pub request_user: UserResp,
     pub object:       ItemDetailResp,
     pub category:     Cat,
+    pub breadcrumbs:  Vec<SmallCat>,
     pub cats:         Vec<Cat>,
     pub all_tags:     Vec<SmallTag>,
     pub prev:         Option<FeaturedItem>,
@@ -846,9 +925,11 @@ pub struct ObjectPageResp {
 pub struct CategoryPageResp {
     pub request_user:     UserResp,
     pub category:         CatDetail,
+    pub breadcrumbs:      Vec<SmallCat>,
     pub cats:             Vec<Cat>,
     pub all_tags:         Vec<SmallTag>,
     pub object_list:      Vec<Blog>,
+    pub facets:           SearchFacets,
     pub next_page_number: i16,
 }
 
@@ -862,6 +943,7 @@ pub struct ItemResp {
     pub price:       i32,
     pub price_acc:   Option<i32>,
     pub image:       String,
+    pub image_variants: Vec<ImageVariant>,
 }
 #[derive(Serialize)]
 pub struct CatDataResp {
@@ -890,6 +972,7 @@ pub struct ItemDetailResp {
     pub price:      i32,
     pub price_acc:  Option<i32>,
     pub image:      String,
+    pub image_variants: Vec<ImageVariant>,
     pub view:       i32,
     pub height:     f64, 
     pub seconds:    i32,
@@ -910,6 +993,7 @@ fn get_item_data(object: Item, perm: i16) -> ItemDetailResp {
             price:       0,
             price_acc:   None,
             image:       "".to_string(),
+            image_variants: Vec::new(),
             view:        0,
             height:      0.0, 
             seconds:     0,
@@ -929,6 +1013,7 @@ fn get_item_data(object: Item, perm: i16) -> ItemDetailResp {
             price:       object.price,
             price_acc:   object.price_acc,
             image:       object.get_image(),
+            image_variants: object.get_image_variants(),
             view:        object.view,
             height:      object.height, 
             seconds:     object.seconds,
@@ -1008,7 +1093,7 @@ async fn get_item_page (
         .first::<Item>(&_connection)
         .expect("E");
     let _category = categories
-        .filter(schema::categories::slug.eq(cat_slug))
+        .filter(schema::categories::slug.eq(Categories::get_actual_slug(&cat_slug, _item.types)))
         .filter(schema::categories::types.eq(_item.types))
         .first::<Categories>(&_connection)
         .expect("E");
@@ -1031,19 +1116,22 @@ async fn get_item_page (
 
 
     let cat_data = Cat {
-        name:  _category.name.clone(),
-        slug:  _category.slug.clone(),
-        count: _category.count,
-        id:    _category.id,
-        image: Some(_category.get_image()),
-        types: _category.types,
+        name:      _category.name.clone(),
+        slug:      _category.slug.clone(),
+        count:     _category.count,
+        id:        _category.id,
+        image:     Some(_category.get_image()),
+        types:     _category.types,
+        parent_id: _category.parent_id,
     };
+    let _breadcrumbs = Categories::get_breadcrumbs(_category.id);
     let _request_user = get_request_user(&req, is_ajax).await;
     let perm = _request_user.perm;
     return Ok(Json(ObjectPageResp {
         request_user: _request_user,
         object:       get_item_data(_item, perm),
         category:     cat_data,
+        breadcrumbs:  _breadcrumbs,
         cats:         _cats,
         all_tags:     _tags,
         prev:         prev,
@@ -1116,7 +1204,7 @@ async fn item_category_page (
     let _connection = establish_connection();
 
     let _category = categories
-        .filter(schema::categories::slug.eq(slug))
+        .filter(schema::categories::slug.eq(Categories::get_actual_slug(&slug, types)))
         .filter(schema::categories::types.eq(types))
         .select((
             schema::categories::name,
@@ -1168,18 +1256,56 @@ async fn item_category_page (
         _tags = Vec::new();
     }
 
+    // фильтры (см. get_search_filters) сужают список внутри категории
+    let mut filters = get_search_filters(&req)?;
+    let is_filtered = !filters.is_empty();
+    filters.scope_cat_ids = Categories::get_descendant_ids(_category.id);
+
+    let _breadcrumbs = Categories::get_breadcrumbs(_category.id);
     let _request_user = get_request_user(&req, is_ajax).await;
-    let _res = block(move || Categories::get_blogs_list(_category.id, page, 20, _request_user.perm == 60)).await?;
-    let _dict = match _res {
-        Ok(_ok) => {object_list = _ok.0; next_page_number = _ok.1},
-        Err(_error) => {object_list = Vec::new(); next_page_number = 0},
-    };
+    let is_admin = _request_user.perm == 60;
+    let cat_id = _category.id;
+    let _facets: SearchFacets;
+    if is_filtered {
+        // i64: page приходит из запроса, в i32 произведение переполнится
+        let offset = (page as i64 - 1) * 20;
+        let (_items, _counts, _f) = block(move || (
+            SearchItem::search("", types, &filters, 20, offset, is_admin),
+            SearchItem::get_counts("", &filters, is_admin),
+            SearchItem::get_facets("", types, &filters, is_admin),
+        )).await?;
+        object_list = _items
+            .into_iter()
+            .map(|i| Blog {
+                id:          i.id,
+                slug:        i.slug,
+                image:       i.image,
+                description: i.description,
+                types:       i.item_types,
+                title:       i.title,
+                created:     i.created,
+            })
+            .collect();
+        let count: i64 = _counts.iter().map(|c| c.count).sum();
+        next_page_number = if count > page as i64 * 20 { (page + 1) as i16 } else { 0 };
+        _facets = _f;
+    }
+    else {
+        let _res = block(move || Categories::get_blogs_list(cat_id, page, 20, is_admin)).await?;
+        let _dict = match _res {
+            Ok(_ok) => {object_list = _ok.0; next_page_number = _ok.1},
+            Err(_error) => {object_list = Vec::new(); next_page_number = 0},
+        };
+        _facets = block(move || SearchItem::get_facets("", types, &filters, is_admin)).await?;
+    }
     return Ok(Json(CategoryPageResp {
         request_user:     _request_user,
         category:         _category,
+        breadcrumbs:      _breadcrumbs,
         cats:             _cats,
         all_tags:         _tags,
         object_list:      object_list,
+        facets:           _facets,
         next_page_number: next_page_number,
     }));
 }
@@ -1229,9 +1355,17 @@ async fn item_categories_page (
 
     let mut categories: Vec<CatDataResp> = Vec::new();
     let cats = Categories::get_categories_for_types(types).expect("R.");
-    for cat in cats.iter() {
+    let lists = cats.iter()
+        .map(|cat| cat.get_items_list(6, types, is_superuser))
+        .collect::<Vec<_>>();
+    // варианты картинок всех объектов страницы - одним запросом
+    let variants = Image::get_variants_list(lists.iter()
+        .flatten()
+        .filter_map(|i| i.image.clone())
+        .collect());
+    for (cat, list) in cats.iter().zip(lists.iter()) {
         let mut stack = Vec::new();
-        for i in cat.get_items_list(6, types, is_superuser).iter() {
+        for i in list.iter() {
             stack.push( ItemResp {
                 id:          i.id,
                 slug:        i.slug.clone(),
@@ -1241,6 +1375,9 @@ async fn item_categories_page (
                 price:       i.price,
                 price_acc:   i.price_acc,
                 image:       i.get_image(),
+                image_variants: i.image.as_ref()
+                    .and_then(|src| variants.get(src).cloned())
+                    .unwrap_or_default(),
             });
         }
         categories.push( CatDataResp {
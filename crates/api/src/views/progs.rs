@@ -13,9 +13,10 @@ use crate::models::{
     NewTagItems,
     CookieUser,
     Categories,
-    Tag,
     Item,
     CookieStat,
+    SearchQuery,
+    Blob,
 };
 use serde::{Deserialize, Serialize};
 
@@ -23,18 +24,29 @@ use crate::utils::{
     establish_connection,
     get_cookie_user_id,
     get_request_user_id,
+    get_request_user,
+    update_category_counts,
+    update_tag_counts,
+    recount_counters,
+    cleanup_storage,
+    CleanupMode,
+    CleanupReport,
+    CounterDiff,
     ErrorParams, TOKEN,
 };
 use crate::diesel::{
     RunQueryDsl,
     ExpressionMethods,
     QueryDsl,
+    Connection,
 };
 use actix_multipart::Multipart;
 use std::str;
 use std::borrow::BorrowMut;
 use actix_web::dev::ConnectionInfo;
 use crate::errors::Error;
+use crate::views::{clear_sitemap_cache, clear_tag_cloud_cache, update_search_index};
+use crate::AppState;
 
 
 pub fn progs_routes(config: &mut web::ServiceConfig) {
@@ -51,10 +63,17 @@ pub fn progs_routes(config: &mut web::ServiceConfig) {
     config.route("/create_category", web::post().to(create_category));
     config.route("/edit_category", web::post().to(edit_category));
     config.route("/delete_category", web::post().to(delete_category));
+    config.route("/move_category", web::post().to(move_category));
+    config.route("/merge_categories", web::post().to(merge_categories));
 
     config.route("/create_files", web::post().to(create_files));
     config.route("/edit_file", web::post().to(edit_file));
     config.route("/delete_file", web::post().to(delete_file));
+    config.route("/cleanup_media", web::get().to(cleanup_media_page));
+    config.route("/cleanup_media", web::post().to(cleanup_media));
+
+    config.route("/recount", web::get().to(recount_page));
+    config.route("/recount", web::post().to(recount));
 }
 
 pub async fn create_c_user(conn: ConnectionInfo, req: &HttpRequest) -> CookieUser {
@@ -161,6 +180,7 @@ pub struct HistoryData {
     pub height:    f64,
     pub seconds:   i32,
     pub template:  String,
+    pub search_id: Option<i32>, // переход из выдачи поиска на объект
 }
 pub async fn create_history (
     conn: ConnectionInfo,
@@ -226,6 +246,11 @@ pub async fn create_history (
     else {
         plus_page_stat(p_page_id, p_height, p_seconds, is_cookie_stats_exists)
     }
+    // каждый переход из выдачи - клик, в том числе у вернувшихся посетителей
+    if data.search_id.is_some() && p_object_id > 0 {
+        let search_id = data.search_id.unwrap();
+        block(move || SearchQuery::create_click(search_id, p_object_id)).await?;
+    }
     let _res = block(move || CookieStat::create (
         user.id,
         p_page_id,
@@ -325,7 +350,7 @@ pub async fn create_feedback(req: HttpRequest, mut payload: actix_multipart::Mul
 }
 
 
-pub async fn create_item(req: HttpRequest, mut payload: Multipart) -> Result<Json<i16>, Error> {
+pub async fn create_item(req: HttpRequest, state: web::Data<AppState>, mut payload: Multipart) -> Result<Json<i16>, Error> {
     let user_id = get_request_user_id(&req).await;
     if user_id < 1 {
         let body = serde_json::to_string(&ErrorParams {
@@ -334,7 +359,7 @@ pub async fn create_item(req: HttpRequest, mut payload: Multipart) -> Result<Jso
         return Err(Error::BadRequest(body));
     }
 
-    let form = crate::utils::item_form(payload.borrow_mut(), user_id).await;
+    let mut form = crate::utils::item_form(payload.borrow_mut(), user_id, &state.storage).await?;
     if form.token != TOKEN.to_string() {
         let body = serde_json::to_string(&ErrorParams {
             error: "Permission Denied".to_string(),
@@ -355,110 +380,113 @@ pub async fn create_item(req: HttpRequest, mut payload: Multipart) -> Result<Jso
         form.slug.clone(),
     );
 
-    let _item = diesel::insert_into(schema::items::table)
-        .values(&new_item)
-        .get_result::<Item>(&_connection)
-        .expect("E.");
-
-    for category_id in form.category_list.iter() {
-        let new_category = NewCategory {
-            categories_id: *category_id,
-            item_id:       _item.id,
-            types:         types,
-        };
-        diesel::insert_into(schema::category::table)
-            .values(&new_category)
-            .execute(&_connection)
-            .expect("E.");
-    };
-    for tag_id in form.tags_list.iter() {
-        let new_tag = NewTagItems {
-            tag_id: *tag_id,
-            item_id: _item.id,
-            types:   types,
-            created: chrono::Local::now().naive_utc(),
+    // объект, его связи и счетчики создаются в одной транзакции
+    let item_id = _connection.transaction::<_, Error, _>(|| {
+        let _item = diesel::insert_into(schema::items::table)
+            .values(&new_item)
+            .get_result::<Item>(&_connection)?;
+
+        for category_id in form.category_list.iter() {
+            let new_category = NewCategory {
+                categories_id: *category_id,
+                item_id:       _item.id,
+                types:         types,
+            };
+            diesel::insert_into(schema::category::table)
+                .values(&new_category)
+                .execute(&_connection)?;
         };
-        diesel::insert_into(schema::tags_items::table)
-            .values(&new_tag)
-            .execute(&_connection)
-            .expect("Error.");
-    }
+        for tag_id in form.tags_list.iter() {
+            let new_tag = NewTagItems {
+                tag_id: *tag_id,
+                item_id: _item.id,
+                types:   types,
+                created: chrono::Local::now().naive_utc(),
+            };
+            diesel::insert_into(schema::tags_items::table)
+                .values(&new_tag)
+                .execute(&_connection)?;
+        }
 
-    // создаем связь с тех категориями, которые будут
-    // расширять списки опций, предлагая доп возможности и услуги
-    for cat_id in form.close_tech_cats_list.iter() {
-        let new_cat = NewTechCategoriesItem {
-            category_id: *cat_id,
-            item_id:     _item.id,
-            types:       types,
-            is_active:   2,
-        };
-        diesel::insert_into(schema::tech_categories_items::table)
-            .values(&new_cat)
-            .execute(&_connection)
-            .expect("Error.");
-    }
+        // создаем связь с тех категориями, которые будут
+        // расширять списки опций, предлагая доп возможности и услуги
+        for cat_id in form.close_tech_cats_list.iter() {
+            let new_cat = NewTechCategoriesItem {
+                category_id: *cat_id,
+                item_id:     _item.id,
+                types:       types,
+                is_active:   2,
+            };
+            diesel::insert_into(schema::tech_categories_items::table)
+                .values(&new_cat)
+                .execute(&_connection)?;
+        }
 
-    // создаем опции услуги и записываем id опций в вектор.
-    let mut serve_ids = Vec::new();
-    for serve_id in form.serve_list.iter() {
-        let new_serve_form = NewServeItems {
-            serve_id: *serve_id,
-            item_id:  _item.id,
-            types:    types,
-        };
-        diesel::insert_into(schema::serve_items::table)
-            .values(&new_serve_form)
-            .execute(&_connection)
-            .expect("Error.");
-        serve_ids.push(*serve_id);
-    }
+        // создаем опции услуги и записываем id опций в вектор.
+        let mut serve_ids = Vec::new();
+        for serve_id in form.serve_list.iter() {
+            let new_serve_form = NewServeItems {
+                serve_id: *serve_id,
+                item_id:  _item.id,
+                types:    types,
+            };
+            diesel::insert_into(schema::serve_items::table)
+                .values(&new_serve_form)
+                .execute(&_connection)?;
+            serve_ids.push(*serve_id);
+        }
 
-    // получаем опции, чтобы создать связи с их тех. категорией.
-    // это надо отрисовки тех категорий услуги, которые активны
-    let _serves = schema::serve::table
-        .filter(schema::serve::id.eq_any(serve_ids))
-        .load::<Serve>(&_connection)
-        .expect("E");
+        // получаем опции, чтобы создать связи с их тех. категорией.
+        // это надо отрисовки тех категорий услуги, которые активны
+        let _serves = schema::serve::table
+            .filter(schema::serve::id.eq_any(serve_ids))
+            .load::<Serve>(&_connection)?;
+
+        let mut tech_cat_ids = Vec::new();
+        let mut item_price = 0;
+        for _serve in _serves.iter() {
+            if !tech_cat_ids.iter().any(|&i| i==_serve.tech_cat_id) {
+                tech_cat_ids.push(_serve.tech_cat_id);
+            }
+            item_price += _serve.price;
+        }
 
-    let mut tech_cat_ids = Vec::new();
-    let mut item_price = 0;
-    for _serve in _serves.iter() {
-        if !tech_cat_ids.iter().any(|&i| i==_serve.tech_cat_id) {
-            tech_cat_ids.push(_serve.tech_cat_id);
+        for id in tech_cat_ids.iter() {
+            let new_cat = NewTechCategoriesItem {
+                category_id: *id,
+                item_id:     _item.id,
+                types:       types,
+                is_active:   1,
+            };
+            diesel::insert_into(schema::tech_categories_items::table)
+                .values(&new_cat)
+                .execute(&_connection)?;
         }
-        item_price += _serve.price;
-    }
 
-    for id in tech_cat_ids.iter() {
-        let new_cat = NewTechCategoriesItem {
-            category_id: *id,
-            item_id:     _item.id,
-            types:       types,
-            is_active:   1,
-        };
-        diesel::insert_into(schema::tech_categories_items::table)
-            .values(&new_cat)
-            .execute(&_connection)
-            .expect("Error.");
-    }
+        // фух. Связи созданы все, но надо еще посчитать цену
+        // услуги для калькулятора. Как? А  это будет сумма всех
+        // цен выбранных опций.
+        let price_acc = crate::utils::get_price_acc_values(&item_price);
+        diesel::update(&_item)
+            .set((
+                schema::items::price.eq(item_price),
+                schema::items::price_acc.eq(price_acc),
+            ))
+            .execute(&_connection)?;
 
-    // фух. Связи созданы все, но надо еще посчитать цену
-    // услуги для калькулятора. Как? А  это будет сумма всех
-    // цен выбранных опций.
-    let price_acc = crate::utils::get_price_acc_values(&item_price);
-    diesel::update(&_item)
-        .set((
-            schema::items::price.eq(item_price),
-            schema::items::price_acc.eq(price_acc),
-        ))
-        .execute(&_connection)
-        .expect("Error.");
+        update_category_counts(&_connection, form.category_list.clone())?;
+        update_tag_counts(&_connection, form.tags_list.clone())?;
+        Ok(_item.id)
+    })?;
+    form.pins.saved();
 
+    clear_sitemap_cache(&state);
+    clear_tag_cloud_cache(&state);
+    update_search_index(&state, item_id, false).await?;
     return Ok(Json(1));
 }
 
-pub async fn edit_item(req: HttpRequest, mut payload: Multipart) -> Result<Json<i16>, Error> {
+pub async fn edit_item(req: HttpRequest, state: web::Data<AppState>, mut payload: Multipart) -> Result<Json<i16>, Error> {
     use crate::utils::{
         item_form,
         get_price_acc_values,
@@ -471,7 +499,7 @@ pub async fn edit_item(req: HttpRequest, mut payload: Multipart) -> Result<Json<
         }).unwrap();
         return Err(Error::BadRequest(body));
     }
-    let form = item_form(payload.borrow_mut(), user_id).await;
+    let mut form = item_form(payload.borrow_mut(), user_id, &state.storage).await?;
     if form.token != TOKEN.to_string() {
         let body = serde_json::to_string(&ErrorParams {
             error: "Permission Denied".to_string(),
@@ -493,11 +521,9 @@ pub async fn edit_item(req: HttpRequest, mut payload: Multipart) -> Result<Json<
     }
 
     use crate::schema::{
-        tags::dsl::tags,
         items::dsl::items,
         serve_items::dsl::serve_items,
         tags_items::dsl::tags_items,
-        categories::dsl::categories,
         category::dsl::category,
         tech_categories_items::dsl::tech_categories_items,
         serve::dsl::serve,
@@ -507,187 +533,170 @@ pub async fn edit_item(req: HttpRequest, mut payload: Multipart) -> Result<Json<
         EditItem,
     };
 
-    let _categories = _item.get_categories_obj().expect("E");
-    let _tags = _item.get_tags_obj().expect("E");
-
-    for _category in _categories.iter() {
-        diesel::update(_category)
-            .set(schema::categories::count.eq(_category.count - 1))
-            .execute(&_connection)
-            .expect("Error.");
-    };
-    for _tag in _tags.iter() {
-        diesel::update(_tag)
-            .set(schema::tags::count.eq(_tag.count - 1))
-            .execute(&_connection)
-            .expect("Error.");
-    };
-
-    diesel::delete (
-        tags_items
-            .filter(schema::tags_items::item_id.eq(_item_id))
-            .filter(schema::tags_items::types.eq(_item.types))
-        )
-        .execute(&_connection)
-        .expect("E");
-    diesel::delete (
-        serve_items
-            .filter(schema::serve_items::item_id.eq(_item_id))
-            .filter(schema::serve_items::types.eq(_item.types))
-        )
-        .execute(&_connection)
-        .expect("E");
-    diesel::delete (
-        tech_categories_items
-            .filter(schema::tech_categories_items::item_id.eq(_item_id))
-            .filter(schema::tech_categories_items::types.eq(_item.types))
-        )
-        .execute(&_connection)
-        .expect("E");
-    diesel::delete (
-        category
-            .filter(schema::category::item_id.eq(_item_id))
-            .filter(schema::category::types.eq(_item.types))
-        )
-        .execute(&_connection)
-        .expect("E");
-
-    let _new_item = EditItem {
-        title:       form.title.clone(),
-        link:        form.link.clone(),
-        image:       form.main_image.clone(),
-        position:    form.position,
-        slug:        form.slug.clone(),
-    };
-
-    diesel::update(&_item)
-        .set(_new_item)
-        .execute(&_connection)
-        .expect("E");
-
-    for category_id in form.category_list.iter() {
-        let new_category = NewCategory {
-            categories_id: *category_id,
-            item_id:       _item.id,
-            types:         _item.types,
+    // старые категории и теги - их счетчики тоже надо пересчитать
+    let mut _category_ids: Vec<i32> = _item.get_categories_obj()
+        .expect("E")
+        .iter()
+        .map(|c| c.id)
+        .collect();
+    let mut _tag_ids: Vec<i32> = _item.get_tags_obj()
+        .expect("E")
+        .iter()
+        .map(|t| t.id)
+        .collect();
+
+    // связи, цена и счетчики меняются в одной транзакции
+    _connection.transaction::<_, Error, _>(|| {
+        diesel::delete (
+            tags_items
+                .filter(schema::tags_items::item_id.eq(_item_id))
+                .filter(schema::tags_items::types.eq(_item.types))
+            )
+            .execute(&_connection)?;
+        diesel::delete (
+            serve_items
+                .filter(schema::serve_items::item_id.eq(_item_id))
+                .filter(schema::serve_items::types.eq(_item.types))
+            )
+            .execute(&_connection)?;
+        diesel::delete (
+            tech_categories_items
+                .filter(schema::tech_categories_items::item_id.eq(_item_id))
+                .filter(schema::tech_categories_items::types.eq(_item.types))
+            )
+            .execute(&_connection)?;
+        diesel::delete (
+            category
+                .filter(schema::category::item_id.eq(_item_id))
+                .filter(schema::category::types.eq(_item.types))
+            )
+            .execute(&_connection)?;
+
+        let _new_item = EditItem {
+            title:       form.title.clone(),
+            link:        form.link.clone(),
+            image:       form.main_image.clone(),
+            position:    form.position,
+            slug:        form.slug.clone(),
+            updated:     chrono::Local::now().naive_utc() + chrono::Duration::hours(3),
         };
-        diesel::insert_into(schema::category::table)
-            .values(&new_category)
-            .execute(&_connection)
-            .expect("E.");
 
-        let _category = categories
-            .filter(schema::categories::id.eq(category_id))
-            .filter(schema::categories::types.eq(_item.types))
-            .first::<Categories>(&_connection)
-            .expect("E");
-        diesel::update(&_category)
-            .set(schema::categories::count.eq(_category.count + 1))
-            .execute(&_connection)
-            .expect("Error.");
-    }
-
-    for tag_id in form.tags_list.iter() {
-        let new_tag = NewTagItems {
-            tag_id: *tag_id,
-            item_id: _item.id,
-            types:   _item.types,
-            created: chrono::Local::now().naive_utc(),
-        };
-        diesel::insert_into(schema::tags_items::table)
-            .values(&new_tag)
-            .execute(&_connection)
-            .expect("Error.");
+        diesel::update(&_item)
+            .set(_new_item)
+            .execute(&_connection)?;
 
-        if _item.item_types < 10 {
-            let _tag = tags
-                .filter(schema::tags::id.eq(tag_id))
-                .first::<Tag>(&_connection)
-                .expect("E");
+        for category_id in form.category_list.iter() {
+            let new_category = NewCategory {
+                categories_id: *category_id,
+                item_id:       _item.id,
+                types:         _item.types,
+            };
+            diesel::insert_into(schema::category::table)
+                .values(&new_category)
+                .execute(&_connection)?;
+            _category_ids.push(*category_id);
+        }
 
-            diesel::update(&_tag)
-                .set(schema::tags::count.eq(_tag.count + 1))
-                .execute(&_connection)
-                .expect("Error.");
+        for tag_id in form.tags_list.iter() {
+            let new_tag = NewTagItems {
+                tag_id: *tag_id,
+                item_id: _item.id,
+                types:   _item.types,
+                created: chrono::Local::now().naive_utc(),
+            };
+            diesel::insert_into(schema::tags_items::table)
+                .values(&new_tag)
+                .execute(&_connection)?;
+            _tag_ids.push(*tag_id);
         }
-    }
 
-    // создаем связь с тех категориями, которые будут
-    // расширять списки опций, предлагая доп возможности и услуги
-    for cat_id in form.close_tech_cats_list.iter() {
-        let new_cat = NewTechCategoriesItem {
-            category_id: *cat_id,
-            item_id:     _item.id,
-            types:       _item.types,
-            is_active:   2,
-        };
-        diesel::insert_into(schema::tech_categories_items::table)
-            .values(&new_cat)
-            .execute(&_connection)
-            .expect("Error.");
-    }
+        // создаем связь с тех категориями, которые будут
+        // расширять списки опций, предлагая доп возможности и услуги
+        for cat_id in form.close_tech_cats_list.iter() {
+            let new_cat = NewTechCategoriesItem {
+                category_id: *cat_id,
+                item_id:     _item.id,
+                types:       _item.types,
+                is_active:   2,
+            };
+            diesel::insert_into(schema::tech_categories_items::table)
+                .values(&new_cat)
+                .execute(&_connection)?;
+        }
 
-    // создаем опции услуги и записываем id опций в вектор.
-    let mut serve_ids = Vec::new();
-    for serve_id in form.serve_list.iter() {
-        let new_serve_form = NewServeItems {
-            serve_id: *serve_id,
-            item_id:  _item.id,
-            types:    _item.types,
-        };
-        diesel::insert_into(schema::serve_items::table)
-            .values(&new_serve_form)
-            .execute(&_connection)
-            .expect("Error.");
-        serve_ids.push(*serve_id);
-    }
+        // создаем опции услуги и записываем id опций в вектор.
+        let mut serve_ids = Vec::new();
+        for serve_id in form.serve_list.iter() {
+            let new_serve_form = NewServeItems {
+                serve_id: *serve_id,
+                item_id:  _item.id,
+                types:    _item.types,
+            };
+            diesel::insert_into(schema::serve_items::table)
+                .values(&new_serve_form)
+                .execute(&_connection)?;
+            serve_ids.push(*serve_id);
+        }
 
-    // получаем опции, чтобы создать связи с их тех. категорией.
-    // это надо отрисовки тех категорий услуги, которые активны
-    let _serves = serve
-        .filter(schema::serve::id.eq_any(serve_ids))
-        .load::<Serve>(&_connection)
-        .expect("E");
+        // получаем опции, чтобы создать связи с их тех. категорией.
+        // это надо отрисовки тех категорий услуги, которые активны
+        let _serves = serve
+            .filter(schema::serve::id.eq_any(serve_ids))
+            .load::<Serve>(&_connection)?;
+
+        let mut tech_cat_ids = Vec::new();
+        let mut item_price = 0;
+        for _serve in _serves.iter() {
+            if !tech_cat_ids.iter().any(|&i| i==_serve.tech_cat_id) {
+                tech_cat_ids.push(_serve.tech_cat_id);
+            }
+            item_price += _serve.price;
+        }
 
-    let mut tech_cat_ids = Vec::new();
-    let mut item_price = 0;
-    for _serve in _serves.iter() {
-        if !tech_cat_ids.iter().any(|&i| i==_serve.tech_cat_id) {
-            tech_cat_ids.push(_serve.tech_cat_id);
+        for id in tech_cat_ids.iter() {
+            let new_cat = NewTechCategoriesItem {
+                category_id: *id,
+                item_id:     _item.id,
+                types:       _item.types,
+                is_active:   1,
+            };
+            diesel::insert_into(schema::tech_categories_items::table)
+                .values(&new_cat)
+                .execute(&_connection)?;
         }
-        item_price += _serve.price;
-    }
 
-    for id in tech_cat_ids.iter() {
-        let new_cat = NewTechCategoriesItem {
-            category_id: *id,
-            item_id:     _item.id,
-            types:       _item.types,
-            is_active:   1,
-        };
-        diesel::insert_into(schema::tech_categories_items::table)
-            .values(&new_cat)
-            .execute(&_connection)
-            .expect("Error.");
-    }
+        // фух. Связи созданы все, но надо еще посчитать цену
+        // услуги для калькулятора. Как? А  это будет сумма всех
+        // цен выбранных опций.
+        let price_acc = get_price_acc_values(&item_price);
+        diesel::update(&_item)
+            .set((
+                schema::items::price.eq(item_price),
+                schema::items::price_acc.eq(price_acc),
+            ))
+            .execute(&_connection)?;
 
-    // фух. Связи созданы все, но надо еще посчитать цену
-    // услуги для калькулятора. Как? А  это будет сумма всех
-    // цен выбранных опций.
-    let price_acc = get_price_acc_values(&item_price);
-    diesel::update(&_item)
-        .set((
-            schema::items::price.eq(item_price),
-            schema::items::price_acc.eq(price_acc),
-        ))
-        .execute(&_connection)
-        .expect("Error.");
+        // счетчики старых и новых категорий (вместе с их родителями) и тегов
+        update_category_counts(&_connection, _category_ids)?;
+        update_tag_counts(&_connection, _tag_ids)?;
+        Ok(())
+    })?;
+    form.pins.saved();
+
+    // прежняя картинка могла остаться без ссылок
+    if _item.image.is_some() && _item.image != form.main_image {
+        let (_storage, _image) = (state.storage.clone(), _item.image.clone().unwrap());
+        block(move || Blob::release(&*_storage, vec![_image])).await?;
+    }
 
+    clear_sitemap_cache(&state);
+    clear_tag_cloud_cache(&state);
+    update_search_index(&state, _item_id, false).await?;
     return Ok(Json(1));
 }
 
 
-pub async fn create_category(req: HttpRequest, mut payload: Multipart) -> Result<Json<i16>, Error> {
+pub async fn create_category(req: HttpRequest, state: web::Data<AppState>, mut payload: Multipart) -> Result<Json<i16>, Error> {
     use crate::utils::category_form;
 
     let user_id = get_request_user_id(&req).await;
@@ -698,7 +707,7 @@ pub async fn create_category(req: HttpRequest, mut payload: Multipart) -> Result
         return Err(Error::BadRequest(body));
     }
 
-    let form = category_form(payload.borrow_mut(), user_id).await;
+    let mut form = category_form(payload.borrow_mut(), user_id, &state.storage).await?;
     if form.token != TOKEN.to_string() {
         let body = serde_json::to_string(&ErrorParams {
             error: "Permission Denied".to_string(),
@@ -707,6 +716,8 @@ pub async fn create_category(req: HttpRequest, mut payload: Multipart) -> Result
     }
     let _connection = establish_connection();
 
+    let parent_id = get_category_parent(None, form.parent_id.unwrap_or(0), form.types)?;
+
     let new_cat = crate::models::NewCategories {
         name:        form.name.clone(),
         user_id:     user_id,
@@ -719,16 +730,20 @@ pub async fn create_category(req: HttpRequest, mut payload: Multipart) -> Result
         seconds:     0,
         types:       form.types,
         slug:        form.slug,
+        parent_id:   parent_id,
     };
     diesel::insert_into(schema::categories::table)
         .values(&new_cat)
         .execute(&_connection)
         .expect("E.");
+    form.pins.saved();
 
+    clear_sitemap_cache(&state);
+    clear_tag_cloud_cache(&state);
     return Ok(Json(1));
 }
 
-pub async fn edit_category(req: HttpRequest, mut payload: Multipart) -> Result<Json<i16>, Error> {
+pub async fn edit_category(req: HttpRequest, state: web::Data<AppState>, mut payload: Multipart) -> Result<Json<i16>, Error> {
     use crate::utils::category_form;
 
     let user_id = get_request_user_id(&req).await;
@@ -740,7 +755,7 @@ pub async fn edit_category(req: HttpRequest, mut payload: Multipart) -> Result<J
     }
 
     let _connection = establish_connection();
-    let form = category_form(payload.borrow_mut(), user_id).await;
+    let mut form = category_form(payload.borrow_mut(), user_id, &state.storage).await?;
     if form.token != TOKEN.to_string() {
         let body = serde_json::to_string(&ErrorParams {
             error: "Permission Denied".to_string(),
@@ -754,12 +769,20 @@ pub async fn edit_category(req: HttpRequest, mut payload: Multipart) -> Result<J
         return Err(Error::BadRequest(body));
     }
 
-    let _category = schema::categories::table
+    let _category = match schema::categories::table
         .filter(schema::categories::id.eq(form.id))
-        .first::<Categories>(&_connection)
-        .expect("E");
+        .first::<Categories>(&_connection) {
+            Ok(cat) => cat,
+            Err(_) => return Err(Error::NotFound("category not found".to_string())),
+        };
     
     if user_id == _category.user_id {
+        // parent_id в форме - перенос, с теми же проверками, что в move_category
+        let parent_id = match form.parent_id {
+            Some(_parent_id) => get_category_parent(Some(_category.id), _parent_id, _category.types)?,
+            None => _category.parent_id,
+        };
+
         let _new_cat = crate::models::EditCategories {
             name:        form.name.clone(),
             description: Some(form.description.clone()),
@@ -771,8 +794,21 @@ pub async fn edit_category(req: HttpRequest, mut payload: Multipart) -> Result<J
             .set(_new_cat)
             .execute(&_connection)
             .expect("E");
+        form.pins.saved();
+
+        if parent_id != _category.parent_id {
+            let _moved = _category.clone();
+            block(move || _moved.move_to(parent_id)).await??;
+        }
+
+        if _category.image.is_some() && _category.image != Some(form.image.clone()) {
+            let (_storage, _image) = (state.storage.clone(), _category.image.clone().unwrap());
+            block(move || Blob::release(&*_storage, vec![_image])).await?;
+        }
     }
 
+    clear_sitemap_cache(&state);
+    clear_tag_cloud_cache(&state);
     return Ok(Json(1));
 }
 
@@ -781,7 +817,7 @@ pub struct DeleteItemData {
     pub id:    Option<i32>,
     pub token: Option<String>,
 }
-pub async fn delete_item(req: HttpRequest, data: Json<DeleteItemData>) -> Result<Json<i16>, Error> {
+pub async fn delete_item(req: HttpRequest, state: web::Data<AppState>, data: Json<DeleteItemData>) -> Result<Json<i16>, Error> {
     let user_id = get_request_user_id(&req).await;
     if user_id < 1 {
         let body = serde_json::to_string(&ErrorParams {
@@ -824,61 +860,65 @@ pub async fn delete_item(req: HttpRequest, data: Json<DeleteItemData>) -> Result
         return Err(Error::BadRequest(body));
     }
 
-    let _src_list = files
+    let mut _src_list = files
         .filter(schema::files::item_id.eq(id))
         .filter(schema::files::item_types.eq(_item.types))
         .select(schema::files::src)
         .load::<String>(&_connection)
         .expect("E");
-
-    for f in _src_list.iter() {
-        std::fs::remove_file(f).expect("E");
+    if let Some(image) = _item.image.clone() {
+        _src_list.push(image);
     }
 
-    diesel::delete (
-        files
-            .filter(schema::files::item_id.eq(id))
-            .filter(schema::files::item_types.eq(_item.types))
-        )
-        .execute(&_connection)
-        .expect("E");
-    diesel::delete (
-        tags_items
-            .filter(schema::tags_items::item_id.eq(id))
-            .filter(schema::tags_items::types.eq(_item.types))
-        )
-        .execute(&_connection)
-        .expect("E");
-    diesel::delete (
-        category
-            .filter(schema::category::item_id.eq(id))
-            .filter(schema::category::types.eq(_item.types))
-        )
-        .execute(&_connection)
-        .expect("E");
-    diesel::delete(&_item).execute(&_connection).expect("E");
-
-    let _categories = _item.get_categories_obj().expect("E");
-    let _tags = _item.get_tags_obj().expect("E");
-
-    for _category in _categories.iter() {
-        diesel::update(_category)
-            .set(schema::categories::count.eq(_category.count - 1))
-            .execute(&_connection)
-            .expect("Error.");
-    };
-    for _tag in _tags.iter() {
-        diesel::update(_tag)
-            .set(schema::tags::count.eq(_tag.count - 1))
-            .execute(&_connection)
-            .expect("Error.");
-    };
-    
+    let _category_ids: Vec<i32> = _item.get_categories_obj()
+        .expect("E")
+        .iter()
+        .map(|c| c.id)
+        .collect();
+    let _tag_ids: Vec<i32> = _item.get_tags_obj()
+        .expect("E")
+        .iter()
+        .map(|t| t.id)
+        .collect();
+
+    _connection.transaction::<_, Error, _>(|| {
+        diesel::delete (
+            files
+                .filter(schema::files::item_id.eq(id))
+                .filter(schema::files::item_types.eq(_item.types))
+            )
+            .execute(&_connection)?;
+        diesel::delete (
+            tags_items
+                .filter(schema::tags_items::item_id.eq(id))
+                .filter(schema::tags_items::types.eq(_item.types))
+            )
+            .execute(&_connection)?;
+        diesel::delete (
+            category
+                .filter(schema::category::item_id.eq(id))
+                .filter(schema::category::types.eq(_item.types))
+            )
+            .execute(&_connection)?;
+        diesel::delete(&_item).execute(&_connection)?;
+
+        update_category_counts(&_connection, _category_ids)?;
+        update_tag_counts(&_connection, _tag_ids)?;
+        Ok(())
+    })?;
+
+    // файлы удаляются из хранилища, только если на них больше никто не ссылается
+    let _storage = state.storage.clone();
+    block(move || Blob::release(&*_storage, _src_list)).await?;
+
+    clear_sitemap_cache(&state);
+    clear_tag_cloud_cache(&state);
+    update_search_index(&state, id, true).await?;
     return Ok(Json(1));
 }
 
 
-pub async fn delete_category(req: HttpRequest, data: Json<DeleteItemData>) -> Result<Json<i16>, Error> {
+pub async fn delete_category(req: HttpRequest, state: web::Data<AppState>, data: Json<DeleteItemData>) -> Result<Json<i16>, Error> {
     let user_id = get_request_user_id(&req).await;
     if user_id < 1 {
         let body = serde_json::to_string(&ErrorParams {
@@ -915,14 +955,180 @@ pub async fn delete_category(req: HttpRequest, data: Json<DeleteItemData>) -> Re
         return Err(Error::BadRequest(body));
     }
 
-    diesel::delete(&_item)
-        .execute(&_connection)
-        .expect("E");
-    
+    // подкатегории переходят к родителю удаляемой категории,
+    // связи с объектами удаляются вместе с ней
+    _connection.transaction::<_, Error, _>(|| {
+        diesel::update(schema::categories::table.filter(schema::categories::parent_id.eq(id)))
+            .set(schema::categories::parent_id.eq(_item.parent_id))
+            .execute(&_connection)?;
+        diesel::delete(schema::category::table.filter(schema::category::categories_id.eq(id)))
+            .execute(&_connection)?;
+        diesel::delete(&_item)
+            .execute(&_connection)?;
+        if _item.parent_id.is_some() {
+            update_category_counts(&_connection, vec![_item.parent_id.unwrap()])?;
+        }
+        Ok(())
+    })?;
+
+    if let Some(image) = _item.image.clone() {
+        let _storage = state.storage.clone();
+        block(move || Blob::release(&*_storage, vec![image])).await?;
+    }
+
+    clear_sitemap_cache(&state);
+    clear_tag_cloud_cache(&state);
+    return Ok(Json(1));
+}
+
+fn get_category_parent(id: Option<i32>, parent_id: i32, types: i16) -> Result<Option<i32>, Error> {
+    // родитель для категории id (None - новая): 0 - корневая.
+    // родитель должен быть того же типа и не из поддерева самой категории
+    if parent_id < 1 {
+        return Ok(None);
+    }
+    let _connection = establish_connection();
+    let _parent_types = schema::categories::table
+        .filter(schema::categories::id.eq(parent_id))
+        .select(schema::categories::types)
+        .first::<i16>(&_connection);
+    if _parent_types.is_err() || _parent_types.unwrap() != types {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "parent category not found!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+    // нельзя перенести категорию в саму себя или в свою подкатегорию
+    if id.is_some() && Categories::get_descendant_ids(id.unwrap()).contains(&parent_id) {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "category can not be moved into its own subtree!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+    return Ok(Some(parent_id));
+}
+
+#[derive(Deserialize)]
+pub struct MoveCategoryData {
+    pub id:        Option<i32>,
+    pub parent_id: Option<i32>, // None или 0 - сделать корневой
+    pub token:     Option<String>,
+}
+pub async fn move_category(req: HttpRequest, state: web::Data<AppState>, data: Json<MoveCategoryData>) -> Result<Json<i16>, Error> {
+    let user_id = get_request_user_id(&req).await;
+    if user_id < 1 {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "Permission Denied".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+
+    if data.token.is_none() || data.token.as_deref().unwrap() != TOKEN {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "Permission Denied".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+
+    if data.id.is_none() || data.id.unwrap() < 1 {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "parametr 'id' not found!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+
+    let id = data.id.unwrap();
+    let _connection = establish_connection();
+    let _category = match schema::categories::table
+        .filter(schema::categories::id.eq(id))
+        .first::<Categories>(&_connection) {
+            Ok(cat) => cat,
+            Err(_) => return Err(Error::NotFound("category not found".to_string())),
+        };
+
+    if _category.user_id != user_id {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "Permission Denied".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+
+    let parent_id = get_category_parent(Some(id), data.parent_id.unwrap_or(0), _category.types)?;
+    if parent_id != _category.parent_id {
+        block(move || _category.move_to(parent_id)).await??;
+        clear_sitemap_cache(&state);
+        clear_tag_cloud_cache(&state);
+    }
+    return Ok(Json(1));
+}
+
+#[derive(Deserialize)]
+pub struct MergeData {
+    pub source_id: Option<i32>,
+    pub target_id: Option<i32>,
+    pub token:     Option<String>,
+}
+pub async fn merge_categories(req: HttpRequest, state: web::Data<AppState>, data: Json<MergeData>) -> Result<Json<i16>, Error> {
+    // вливаем категорию source_id в target_id
+    let _request_user = get_request_user(&req, 0).await;
+    if _request_user.perm != 60 {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "Permission Denied".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+    if data.token.is_none() || data.token.as_deref().unwrap() != TOKEN {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "Permission Denied".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+    if data.source_id.is_none() || data.target_id.is_none() {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "parametrs 'source_id' and 'target_id' not found!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+
+    let _connection = establish_connection();
+    let _source = schema::categories::table
+        .filter(schema::categories::id.eq(data.source_id.unwrap()))
+        .first::<Categories>(&_connection);
+    let _target = schema::categories::table
+        .filter(schema::categories::id.eq(data.target_id.unwrap()))
+        .first::<Categories>(&_connection);
+    if _source.is_err() || _target.is_err() {
+        return Err(Error::NotFound("category not found".to_string()));
+    }
+    let _source = _source.unwrap();
+    let _target = _target.unwrap();
+
+    if _source.types != _target.types {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "categories must be of the same type!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+    // нельзя влить категорию в саму себя или в свою подкатегорию
+    if Categories::get_descendant_ids(_source.id).contains(&_target.id) {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "category can not be merged into its own subtree!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+
+    let _image = _source.image.clone();
+    block(move || _source.merge(&_target)).await??;
+    if let Some(image) = _image {
+        let _storage = state.storage.clone();
+        block(move || Blob::release(&*_storage, vec![image])).await?;
+    }
+    clear_sitemap_cache(&state);
+    clear_tag_cloud_cache(&state);
     return Ok(Json(1));
 }
 
-pub async fn create_files(req: HttpRequest, mut payload: Multipart) -> Result<Json<i16>, Error> {
+pub async fn create_files(req: HttpRequest, state: web::Data<AppState>, mut payload: Multipart) -> Result<Json<i16>, Error> {
     let user_id = get_request_user_id(&req).await;
     if user_id < 1 {
         let body = serde_json::to_string(&ErrorParams {
@@ -931,7 +1137,7 @@ pub async fn create_files(req: HttpRequest, mut payload: Multipart) -> Result<Js
         return Err(Error::BadRequest(body));
     }
 
-    let form = crate::utils::files_form(payload.borrow_mut(), user_id).await;
+    let mut form = crate::utils::files_form(payload.borrow_mut(), user_id, &state.storage).await?;
     if form.id < 1 {
         let body = serde_json::to_string(&ErrorParams {
             error: "parametr 'id' not found!".to_string(),
@@ -973,17 +1179,18 @@ pub async fn create_files(req: HttpRequest, mut payload: Multipart) -> Result<Js
             _item.id,
             item_types,
             types,
-            file.to_string()
+            file
         );
         diesel::insert_into(schema::files::table)
             .values(&new_file)
             .execute(&_connection)
             .expect("E.");
     };
+    form.pins.saved();
     return Ok(Json(1));
 }
 
-pub async fn edit_file(req: HttpRequest, mut payload: Multipart) -> Result<Json<i16>, Error> {
+pub async fn edit_file(req: HttpRequest, state: web::Data<AppState>, mut payload: Multipart) -> Result<Json<i16>, Error> {
     let user_id = get_request_user_id(&req).await;
     if user_id < 1 {
         let body = serde_json::to_string(&ErrorParams {
@@ -992,7 +1199,7 @@ pub async fn edit_file(req: HttpRequest, mut payload: Multipart) -> Result<Json<
         return Err(Error::BadRequest(body));
     }  
 
-    let form = crate::utils::category_form(payload.borrow_mut(), user_id).await;
+    let form = crate::utils::category_form(payload.borrow_mut(), user_id, &state.storage).await?;
     if form.id < 1 {
         let body = serde_json::to_string(&ErrorParams {
             error: "parametr 'id' not found!".to_string(),
@@ -1033,7 +1240,7 @@ pub async fn edit_file(req: HttpRequest, mut payload: Multipart) -> Result<Json<
     return Ok(Json(1));
 }
 
-pub async fn delete_file(req: HttpRequest, data: Json<DeleteItemData>) -> Result<Json<i16>, Error> {
+pub async fn delete_file(req: HttpRequest, state: web::Data<AppState>, data: Json<DeleteItemData>) -> Result<Json<i16>, Error> {
     let user_id = get_request_user_id(&req).await;
     if user_id < 1 {
         let body = serde_json::to_string(&ErrorParams {
@@ -1073,16 +1280,17 @@ pub async fn delete_file(req: HttpRequest, data: Json<DeleteItemData>) -> Result
         return Err(Error::BadRequest(body));
     }
 
-    std::fs::remove_file(_file.src).expect("E");
-
     diesel::delete(files.filter(schema::files::id.eq(id)))
         .execute(&_connection)
         .expect("E");
 
+    let _storage = state.storage.clone();
+    block(move || Blob::release(&*_storage, vec![_file.src])).await?;
+
     return Ok(Json(1));
 }
 
-pub async fn publish_item(req: HttpRequest, data: Json<DeleteItemData>) -> Result<Json<i16>, Error> {
+pub async fn publish_item(req: HttpRequest, state: web::Data<AppState>, data: Json<DeleteItemData>) -> Result<Json<i16>, Error> {
     let user_id = get_request_user_id(&req).await;
     if user_id < 1 {
         let body = serde_json::to_string(&ErrorParams {
@@ -1120,38 +1328,13 @@ pub async fn publish_item(req: HttpRequest, data: Json<DeleteItemData>) -> Resul
     
     _item.make_publish();
 
-    let _categories: Vec<Categories>;
-    let _tags: Vec<Tag>;
-
-    let tags_o = _item.get_tags_obj().expect("E");
-    let categories_o = _item.get_categories_obj().expect("E");
-    let cats_res = block(move || categories_o).await;
-    let tags_res = block(move || tags_o).await;
-    _categories = match cats_res {
-        Ok(_ok) => _ok,
-        Err(_error) => Vec::new(),
-    };
-    for _category in _categories.iter() {
-        diesel::update(_category)
-            .set(schema::categories::count.eq(_category.count + 1))
-            .execute(&_connection)
-            .expect("Error.");
-    }
-    _tags = match tags_res {
-        Ok(_list) => _list,
-        Err(_error) => Vec::new(),
-    };
-    for _tag in _tags.iter() {
-        diesel::update(_tag)
-            .set(schema::tags::count.eq(_tag.count + 1))
-            .execute(&_connection)
-            .expect("Error.");
-    }
-
+    clear_sitemap_cache(&state);
+    clear_tag_cloud_cache(&state);
+    update_search_index(&state, id, false).await?;
     return Ok(Json(1));
 }
 
-pub async fn hide_item(req: HttpRequest, data: Json<DeleteItemData>) -> Result<Json<i16>, Error> {
+pub async fn hide_item(req: HttpRequest, state: web::Data<AppState>, data: Json<DeleteItemData>) -> Result<Json<i16>, Error> {
     let user_id = get_request_user_id(&req).await;
     if user_id < 1 {
         let body = serde_json::to_string(&ErrorParams {
@@ -1189,33 +1372,110 @@ pub async fn hide_item(req: HttpRequest, data: Json<DeleteItemData>) -> Result<J
     
     _item.make_hide();
 
-    let _categories: Vec<Categories>;
-    let _tags: Vec<Tag>;
+    clear_sitemap_cache(&state);
+    clear_tag_cloud_cache(&state);
+    update_search_index(&state, id, false).await?;
+    return Ok(Json(1));
+}
 
-    let tags_o = _item.get_tags_obj().expect("E");
-    let categories_o = _item.get_categories_obj().expect("E");
-    let cats_res = block(move || categories_o).await;
-    let tags_res = block(move || tags_o).await;
-    _categories = match cats_res {
-        Ok(_ok) => _ok,
-        Err(_error) => Vec::new(),
-    };
-    for _category in _categories.iter() {
-        diesel::update(_category)
-            .set(schema::categories::count.eq(_category.count + 1))
-            .execute(&_connection)
-            .expect("Error.");
+#[derive(Serialize)]
+pub struct RecountResp {
+    pub diffs: Vec<CounterDiff>,
+}
+pub async fn recount_page(req: HttpRequest) -> Result<Json<RecountResp>, Error> {
+    // отчет о счетчиках, которые разошлись с таблицами связей
+    let _request_user = get_request_user(&req, 0).await;
+    if _request_user.perm != 60 {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "Permission Denied".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
     }
-    _tags = match tags_res {
-        Ok(_list) => _list,
-        Err(_error) => Vec::new(),
-    };
-    for _tag in _tags.iter() {
-        diesel::update(_tag)
-            .set(schema::tags::count.eq(_tag.count + 1))
-            .execute(&_connection)
-            .expect("Error.");
+
+    let diffs = block(move || recount_counters(&establish_connection(), false)).await??;
+    return Ok(Json(RecountResp {
+        diffs: diffs,
+    }));
+}
+
+#[derive(Deserialize)]
+pub struct RecountData {
+    pub token: Option<String>,
+}
+pub async fn recount(req: HttpRequest, data: Json<RecountData>) -> Result<Json<RecountResp>, Error> {
+    // пересчитываем все счетчики, в ответе - исправленные расхождения
+    let _request_user = get_request_user(&req, 0).await;
+    if _request_user.perm != 60 {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "Permission Denied".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+    if data.token.is_none() || data.token.as_deref().unwrap() != TOKEN {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "Permission Denied".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
     }
 
-    return Ok(Json(1));
-}
\ No newline at end of file
+    let diffs = block(move || recount_counters(&establish_connection(), true)).await??;
+    return Ok(Json(RecountResp {
+        diffs: diffs,
+    }));
+}
+
+#[derive(Serialize)]
+pub struct CleanupResp {
+    pub reports: Vec<CleanupReport>, // общее и закрытое хранилища
+}
+fn cleanup_all_storages(state: &AppState, mode: CleanupMode) -> Result<CleanupResp, Error> {
+    let _connection = establish_connection();
+    let mut reports = Vec::new();
+    for storage in [&state.storage, &state.private_storage].iter() {
+        reports.push(cleanup_storage(&_connection, &***storage, mode).map_err(Error::InternalServerError)?);
+    }
+    return Ok(CleanupResp {
+        reports: reports,
+    });
+}
+
+pub async fn cleanup_media_page(req: HttpRequest, state: web::Data<AppState>) -> Result<Json<CleanupResp>, Error> {
+    // отчет о файлах хранилища, на которые не ссылается база
+    let _request_user = get_request_user(&req, 0).await;
+    if _request_user.perm != 60 {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "Permission Denied".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+
+    let _state = state.get_ref().clone();
+    let resp = block(move || cleanup_all_storages(&_state, CleanupMode::DryRun)).await??;
+    return Ok(Json(resp));
+}
+
+#[derive(Deserialize)]
+pub struct CleanupMediaData {
+    pub token: Option<String>,
+    pub mode:  Option<CleanupMode>, // dry_run (по умолчанию), quarantine, delete
+}
+pub async fn cleanup_media(req: HttpRequest, state: web::Data<AppState>, data: Json<CleanupMediaData>) -> Result<Json<CleanupResp>, Error> {
+    // переносим в карантин или удаляем файлы без ссылок
+    let _request_user = get_request_user(&req, 0).await;
+    if _request_user.perm != 60 {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "Permission Denied".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+    if data.token.is_none() || data.token.as_deref().unwrap() != TOKEN {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "Permission Denied".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+
+    let (_state, mode) = (state.get_ref().clone(), data.mode.unwrap_or(CleanupMode::DryRun));
+    let resp = block(move || cleanup_all_storages(&_state, mode)).await??;
+    return Ok(Json(resp));
+}
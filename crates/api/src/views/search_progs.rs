@@ -1,65 +1,203 @@
 use actix_web::{
     HttpRequest,
     web,
-    web::Json,
+    web::{block, Json},
 };
 
-use crate::models::{Item, Blog, Service, Store, Wiki, Work, Help};
+use crate::models::{
+    SearchItem, SearchTypeCount, SearchFilters, SearchFacets, SearchSuggestion,
+    SearchQuery, SearchQueryStat, SearchCtrStat,
+    Categories, Tag,
+};
 use serde::{Deserialize, Serialize};
 
 use crate::utils::{
-    get_request_user, get_is_ajax,
-    ErrorParams, UserResp,
+    get_request_user, get_cookie_user_id, switch_keyboard_layout,
+    ErrorParams, UserResp, SearchBackend, TOKEN,
 };
 use crate::errors::Error;
+use crate::AppState;
 
 
 pub fn search_routes(config: &mut web::ServiceConfig) {
-    config.route("/search", web::get().to(empty_search_page));
     config.route("/search", web::get().to(search_page));
-    config.route("/search_blogs", web::get().to(search_blogs_page));
-    config.route("/search_services", web::get().to(search_services_page));
-    config.route("/search_stores", web::get().to(search_stores_page));
-    config.route("/search_wikis", web::get().to(search_wikis_page));
-    config.route("/search_works", web::get().to(search_works_page));
-    config.route("/search_help", web::get().to(search_helps_page));
+    config.route("/search/suggest", web::get().to(search_suggest_page));
+    config.route("/search_stats/top", web::get().to(search_top_page));
+    config.route("/search_stats/zero_results", web::get().to(search_zero_results_page));
+    config.route("/search_stats/ctr", web::get().to(search_ctr_page));
+    config.route("/search_rebuild", web::post().to(search_rebuild));
 }
 
+pub async fn update_search_index(state: &web::Data<AppState>, item_id: i32, is_deleted: bool) -> Result<(), Error> {
+    // после изменения объекта: обновить его в индексе. словарь опечаток
+    // пересобирается по всей базе, это делает `search-words` по cron
+    let search = state.search.clone();
+    block(move || {
+        if is_deleted {
+            search.delete_item(item_id);
+        }
+        else {
+            search.index_item(item_id);
+        }
+    }).await?;
+    return Ok(());
+}
 
-#[derive(Serialize)]
-pub struct EmptySearchResp {
-    pub request_user: UserResp,
+pub async fn update_search_index_items(state: &web::Data<AppState>, item_ids: Vec<i32>) -> Result<(), Error> {
+    // после изменения тега: переиндексировать объекты с ним
+    let search = state.search.clone();
+    block(move || {
+        for item_id in item_ids.into_iter() {
+            search.index_item(item_id);
+        }
+    }).await?;
+    return Ok(());
 }
-pub async fn empty_search_page(req: HttpRequest) -> Result<Json<EmptySearchResp>, Error> {
-    return Ok(Json(EmptySearchResp {
-        request_user: get_request_user(&req, get_is_ajax(&req)).await,
-    }));
+
+
+// фильтры передаются в query: cats=1,2 tags=rust,web tech_cats=3
+// price_min=1000 price_max=50000 date_from=2023-01-31 date_to=2023-12-31
+#[derive(Deserialize)]
+pub struct SearchFiltersData {
+    pub cats:      Option<String>,
+    pub tags:      Option<String>,
+    pub tech_cats: Option<String>,
+    pub price_min: Option<i32>,
+    pub price_max: Option<i32>,
+    pub date_from: Option<String>,
+    pub date_to:   Option<String>,
+}
+
+fn get_ids(list: &Option<String>, name: &str) -> Result<Vec<i32>, Error> {
+    let mut ids = Vec::new();
+    for s in list.as_deref().unwrap_or("").split(",").filter(|s| !s.trim().is_empty()) {
+        match s.trim().parse::<i32>() {
+            Ok(id) => ids.push(id),
+            Err(_) => {
+                let body = serde_json::to_string(&ErrorParams {
+                    error: format!("parametr '{}' must be a list of ids!", name),
+                }).unwrap();
+                return Err(Error::BadRequest(body));
+            },
+        };
+    }
+    return Ok(ids);
+}
+pub fn get_date(date: &Option<String>, name: &str) -> Result<Option<chrono::NaiveDate>, Error> {
+    if date.is_none() || date.as_deref().unwrap() == "" {
+        return Ok(None);
+    }
+    return match chrono::NaiveDate::parse_from_str(date.as_deref().unwrap(), "%Y-%m-%d") {
+        Ok(d) => Ok(Some(d)),
+        Err(_) => {
+            let body = serde_json::to_string(&ErrorParams {
+                error: format!("parametr '{}' must be YYYY-MM-DD!", name),
+            }).unwrap();
+            Err(Error::BadRequest(body))
+        },
+    };
+}
+pub fn get_next_day(date: chrono::NaiveDate, name: &str) -> Result<chrono::NaiveDate, Error> {
+    // граница "до" включает весь день; у последней даты chrono следующей нет
+    return date.succ_opt().ok_or_else(|| {
+        let body = serde_json::to_string(&ErrorParams {
+            error: format!("parametr '{}' is out of range!", name),
+        }).unwrap();
+        Error::BadRequest(body)
+    });
 }
 
+pub fn get_search_filters(req: &HttpRequest) -> Result<SearchFilters, Error> {
+    // фильтры из query. категории берутся вместе с подкатегориями,
+    // теги - по slug или id, date_to включает весь указанный день
+    let params_some = web::Query::<SearchFiltersData>::from_query(&req.query_string());
+    if params_some.is_err() {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "parametrs not found!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+    let params = params_some.unwrap();
+
+    let mut filters = SearchFilters::default();
+    for id in get_ids(&params.cats, "cats")?.into_iter() {
+        filters.cat_ids.extend(Categories::get_descendant_ids(id));
+    }
+    for slug in params.tags.as_deref().unwrap_or("").split(",").filter(|s| !s.trim().is_empty()) {
+        match Tag::get_tag_by_slug(slug.trim()) {
+            Some(tag) => filters.tag_ids.push(tag.id),
+            None => return Err(Error::NotFound("tag not found".to_string())),
+        };
+    }
+    filters.tech_cat_ids = get_ids(&params.tech_cats, "tech_cats")?;
+    filters.price_min = params.price_min;
+    filters.price_max = params.price_max;
+    filters.date_from = get_date(&params.date_from, "date_from")?
+        .map(|d| d.and_hms(0, 0, 0));
+    filters.date_to = match get_date(&params.date_to, "date_to")? {
+        Some(d) => Some(get_next_day(d, "date_to")?.and_hms(0, 0, 0)),
+        None => None,
+    };
+    return Ok(filters);
+}
+
+
+fn get_search_result (
+    search:   &dyn SearchBackend,
+    q:        &str,
+    types:    i16,
+    filters:  &SearchFilters,
+    offset:   i64,
+    is_admin: bool
+) -> (Vec<SearchItem>, Vec<SearchTypeCount>, SearchFacets) {
+    // если бэкенд ищет сам - подставляем найденное им в фильтры
+    let mut filters = filters.clone();
+    if let Some((ids, ranks)) = search.find_ids(q) {
+        filters.index_ids = Some(ids);
+        filters.index_ranks = ranks;
+    }
+    return (
+        SearchItem::search(q, types, &filters, 20, offset, is_admin),
+        SearchItem::get_counts(q, &filters, is_admin),
+        SearchItem::get_facets(q, types, &filters, is_admin),
+    );
+}
+
+fn get_types_count(counts: &Vec<SearchTypeCount>, types: i16) -> i64 {
+    // найдено объектов выбранного типа (0 - всех)
+    return counts
+        .iter()
+        .filter(|c| types == 0 || c.types == types)
+        .map(|c| c.count)
+        .sum();
+}
 
 #[derive(Deserialize)]
 pub struct SearchPageData {
     pub q:       Option<String>,
+    pub types:   Option<i16>,
+    pub page:    Option<i32>,
     pub is_ajax: Option<i16>,
 }
 #[derive(Serialize)]
 pub struct SearchPageResp {
-    pub request_user:   UserResp,
-    pub works_list:     Vec<Work>,
-    pub services_list:  Vec<Service>,
-    pub wikis_list:     Vec<Wiki>,
-    pub blogs_list:     Vec<Blog>,
-    pub stores_list:    Vec<Store>,
-    pub helps_list:     Vec<Help>,
-    pub works_count:    usize,
-    pub services_count: usize,
-    pub wikis_count:    usize,
-    pub blogs_count:    usize,
-    pub stores_count:   usize,
-    pub helps_count:    usize,
-    pub q:              String,
+    pub request_user:     UserResp,
+    pub search_id:        i32, // передается в create_history при переходе из выдачи
+    pub q:                String,
+    pub types:            i16,
+    pub items_list:       Vec<SearchItem>,
+    pub items_count:      i64,
+    pub counts:           Vec<SearchTypeCount>,
+    pub facets:           Option<SearchFacets>,
+    pub corrected_q:      Option<String>, // запрос в другой раскладке или с исправленными опечатками, по которому найдена выдача
+    pub did_you_mean:     Option<String>, // исправленный запрос, если по выбранному типу ничего не найдено
+    pub next_page_number: i16,
 }
-pub async fn search_page(req: HttpRequest) -> Result<Json<SearchPageResp>, Error> {
+pub async fn search_page(req: HttpRequest, state: web::Data<AppState>) -> Result<Json<SearchPageResp>, Error> {
+    // поиск по всем объектам (types = 0) или по одному типу (1-6).
+    // counts - сколько найдено каждого типа, для вкладок,
+    // facets - значения фильтров среди найденного выбранного типа.
+    // без q отдается пустая страница поиска
     let params_some = web::Query::<SearchPageData>::from_query(&req.query_string());
     if params_some.is_err() {
         let body = serde_json::to_string(&ErrorParams {
@@ -69,13 +207,13 @@ pub async fn search_page(req: HttpRequest) -> Result<Json<SearchPageResp>, Error
     }
 
     let params = params_some.unwrap();
-    if params.q.is_none() {
+    let types = params.types.unwrap_or(0);
+    if types < 0 || types > 6 {
         let body = serde_json::to_string(&ErrorParams {
-            error: "parametr 'q' not found!".to_string(),
+            error: "parametr 'types' must be from 0 to 6!".to_string(),
         }).unwrap();
         return Err(Error::BadRequest(body));
     }
-
     let is_ajax: i16;
     if params.is_ajax.is_some() && params.is_ajax.unwrap() > 0 {
         is_ajax = params.is_ajax.unwrap();
@@ -83,368 +221,251 @@ pub async fn search_page(req: HttpRequest) -> Result<Json<SearchPageResp>, Error
     else {
         is_ajax = 0;
     }
-
-    let _request_user = get_request_user(&req, is_ajax).await;
-    let _q = params.q.as_deref().unwrap().to_string();
-    let _q_standalone = "%".to_owned() + &_q + "%";
-    let is_admin = _request_user.perm > 59; 
-
-    let (works_list, works_count) = Item::search_works(&_q_standalone, 3, 0, is_admin);
-    let (services_list, services_count) = Item::search_services(&_q_standalone, 3, 0, is_admin);
-    let (wikis_list, wikis_count) = Item::search_wikis(&_q_standalone, 3, 0, is_admin);
-    let (blogs_list, blogs_count) = Item::search_blogs(&_q_standalone, 3, 0, is_admin);
-    let (stores_list, stores_count) = Item::search_stores(&_q_standalone, 3, 0, is_admin);
-    let (helps_list, helps_count) = Item::search_helps(&_q_standalone, 3, 0, is_admin);
-
-    return Ok(Json(SearchPageResp {
-        request_user:  _request_user,
-        works_list:     works_list,
-        services_list:  services_list,
-        wikis_list:     wikis_list,
-        blogs_list:     blogs_list,
-        stores_list:    stores_list,
-        helps_list:     helps_list,
-        works_count:    works_count,
-        services_count: services_count,
-        wikis_count:    wikis_count,
-        blogs_count:    blogs_count,
-        stores_count:   stores_count,
-        helps_count:    helps_count,
-        q:              _q,
-    }));
-}
-
-#[derive(Deserialize)]
-struct SearchItemsPageData {
-    pub q:       Option<String>,
-    pub page:    Option<i32>,
-    pub is_ajax: Option<i16>,
-}
-fn get_q_page_is_ajax(req: &HttpRequest) -> (String, i32, i16) {
-    let params_some = web::Query::<SearchItemsPageData>::from_query(&req.query_string());
-    let q: String;
     let page: i32;
-    let is_ajax: i16;
-    if params_some.is_ok() {
-        let params = params_some.unwrap();
-        if params.page.is_some() && params.page.unwrap() > 1 {
-            page = params.page.unwrap();
-        }
-        else {
-            page = 1;
-        }
-        if params.is_ajax.is_some() && params.is_ajax.unwrap() > 0 {
-            is_ajax = params.is_ajax.unwrap();
-        }
-        else {
-            is_ajax = 0;
-        }
-        if params.q.is_none() {
-            q = String::new();
-        }
-        else {
-            q = params.q.as_deref().unwrap().to_string();
-        }
+    if params.page.is_some() && params.page.unwrap() > 1 {
+        page = params.page.unwrap();
     }
     else {
         page = 1;
-        is_ajax = 0;
-        q = String::new();
     }
 
-    return (q, page, is_ajax);
-}
-
-#[derive(Serialize)]
-pub struct SearchBlogsResp {
-    pub request_user:     UserResp,
-    pub blogs_list:       Vec<Blog>,
-    pub blogs_count:      usize,
-    pub q:                String,
-    pub next_page_number: i16,
-}
-pub async fn search_blogs_page(req: HttpRequest) -> Result<Json<SearchBlogsResp>, Error> {
-    let (q, page, is_ajax) = get_q_page_is_ajax(&req);
+    let filters = get_search_filters(&req)?;
     let _request_user = get_request_user(&req, is_ajax).await;
+    let q = params.q.as_deref().unwrap_or("").trim().to_string();
     if q.is_empty() {
-        return Ok(Json(SearchBlogsResp {
+        return Ok(Json(SearchPageResp {
             request_user:     _request_user,
-            blogs_list:       Vec::new(),
-            blogs_count:      0,
+            search_id:        0,
             q:                q,
+            types:            types,
+            items_list:       Vec::new(),
+            items_count:      0,
+            counts:           Vec::new(),
+            facets:           None,
+            corrected_q:      None,
+            did_you_mean:     None,
             next_page_number: 0,
         }));
     }
 
-    let _q_standalone = "%".to_owned() + &q + "%";
-    let mut next_page_number = 0;
-    let offset: i32;
-    let next_item: i32;
-    
-    if page > 1 {
-        offset = (page - 1) * 20;
-        next_item = page * 20 + 1;
-    }
-    else {
-        offset = 0;
-        next_item = 21;
-    }
+    let is_admin = _request_user.perm > 59;
+    // i64: page приходит из запроса, в i32 произведение переполнится
+    let offset = (page as i64 - 1) * 20;
+    let _q = q.clone();
+    let search = state.search.clone();
+    let (items_list, counts, facets, corrected_q, did_you_mean) = block(move || {
+        let mut corrected_q = None;
+        let mut did_you_mean = None;
+        let (mut items_list, mut counts, mut facets) = get_search_result(&*search, &_q, types, &filters, offset, is_admin);
+        if get_types_count(&counts, types) == 0 {
+            // по выбранному типу ничего (даже если нашлись другие типы):
+            // пробуем тот же запрос в другой раскладке, затем с исправленными
+            // опечатками (похожие по триграммам слова словаря)
+            let switched = switch_keyboard_layout(&_q);
+            let mut variants = Vec::new();
+            if switched != _q.to_lowercase() {
+                variants.push(switched.clone());
+            }
+            did_you_mean = SearchItem::get_did_you_mean(&_q)
+                .or_else(|| SearchItem::get_did_you_mean(&switched));
+            if did_you_mean.is_some() {
+                variants.push(did_you_mean.clone().unwrap());
+            }
+            for variant in variants.into_iter() {
+                let result = get_search_result(&*search, &variant, types, &filters, offset, is_admin);
+                if get_types_count(&result.1, types) > 0 {
+                    (items_list, counts, facets) = result;
+                    corrected_q = Some(variant);
+                    break;
+                }
+            }
+            // нашлось в другой раскладке - опечатки не подсказываем
+            if corrected_q.as_ref() == Some(&switched) {
+                did_you_mean = None;
+            }
+        }
+        (items_list, counts, facets, corrected_q, did_you_mean)
+    }).await?;
 
-    let (items_list, items_count) = Item::search_blogs(&_q_standalone, 20, offset.into(), _request_user.perm > 59);
-    if items_count >= (next_item as usize) {
+    let items_count = get_types_count(&counts, types);
+    let mut next_page_number = 0;
+    if items_count > page as i64 * 20 {
         next_page_number = page + 1;
     }
 
-    return Ok(Json(SearchBlogsResp {
+    let user_id = get_cookie_user_id(&req).await;
+    let (_q, _corrected_q) = (q.clone(), corrected_q.clone());
+    let search_id = block(move || SearchQuery::create (
+        user_id,
+        &_q,
+        types,
+        page,
+        items_count as i32,
+        _corrected_q,
+    )).await?;
+
+    return Ok(Json(SearchPageResp {
         request_user:     _request_user,
-        blogs_list:       items_list,
-        blogs_count:      items_count,
+        search_id:        search_id,
         q:                q,
+        types:            types,
+        items_list:       items_list,
+        items_count:      items_count,
+        counts:           counts,
+        facets:           Some(facets),
+        corrected_q:      corrected_q,
+        did_you_mean:     did_you_mean,
         next_page_number: next_page_number as i16,
     }));
 }
 
-#[derive(Serialize)]
-pub struct SearchServicesResp {
-    pub request_user:     UserResp,
-    pub services_list:    Vec<Service>,
-    pub services_count:   usize,
-    pub q:                String,
-    pub next_page_number: i16,
-}
-pub async fn search_services_page(req: HttpRequest) -> Result<Json<SearchServicesResp>, Error> {
-    let (q, page, is_ajax) = get_q_page_is_ajax(&req);
-    let _request_user = get_request_user(&req, is_ajax).await;
-    if q.is_empty() {
-        return Ok(Json(SearchServicesResp {
-            request_user:     _request_user,
-            services_list:    Vec::new(),
-            services_count:   0,
-            q:                q,
-            next_page_number: 0,
-        }));
-    }
-
-    let _q_standalone = "%".to_owned() + &q + "%";
-    let mut next_page_number = 0;
-    let offset: i32;
-    let next_item: i32;
-    
-    if page > 1 {
-        offset = (page - 1) * 20;
-        next_item = page * 20 + 1;
-    }
-    else {
-        offset = 0;
-        next_item = 21;
-    }
-
-    let (items_list, items_count) = Item::search_services(&_q_standalone, 20, offset.into(), _request_user.perm > 59);
-    if items_count >= (next_item as usize) {
-        next_page_number = page + 1;
-    }
 
-    return Ok(Json(SearchServicesResp {
-        request_user:     _request_user,
-        services_list:    items_list,
-        services_count:   items_count,
-        q:                q,
-        next_page_number: next_page_number as i16,
-    }));
+#[derive(Deserialize)]
+pub struct SearchSuggestData {
+    pub q:     Option<String>,
+    pub limit: Option<i64>,
 }
-
 #[derive(Serialize)]
-pub struct SearchStoresResp {
-    pub request_user:     UserResp,
-    pub stores_list:      Vec<Store>,
-    pub stores_count:     usize,
-    pub q:                String,
-    pub next_page_number: i16,
+pub struct SearchSuggestGroup {
+    pub types: i16,
+    pub items: Vec<SearchSuggestion>,
 }
-pub async fn search_stores_page(req: HttpRequest) -> Result<Json<SearchStoresResp>, Error> {
-    let (q, page, is_ajax) = get_q_page_is_ajax(&req);
-    let _request_user = get_request_user(&req, is_ajax).await;
-    if q.is_empty() {
-        return Ok(Json(SearchStoresResp {
-            request_user:     _request_user,
-            stores_list:      Vec::new(),
-            stores_count:     0,
-            q:                q,
-            next_page_number: 0,
-        }));
-    }
-
-    let _q_standalone = "%".to_owned() + &q + "%";
-    let mut next_page_number = 0;
-    let offset: i32;
-    let next_item: i32;
-    
-    if page > 1 {
-        offset = (page - 1) * 20;
-        next_item = page * 20 + 1;
+#[derive(Serialize)]
+pub struct SearchSuggestResp {
+    pub q:          String,
+    pub items:      Vec<SearchSuggestGroup>, // объекты по типам 1-6, пустые типы пропускаются
+    pub categories: Vec<SearchSuggestion>,
+    pub tags:       Vec<SearchSuggestion>,
+}
+pub async fn search_suggest_page(req: HttpRequest) -> Result<Json<SearchSuggestResp>, Error> {
+    // подсказки строки поиска по мере набора: limit (по умолчанию 5, до 10)
+    // на каждую группу. меньше 2 символов - пустой ответ
+    let params_some = web::Query::<SearchSuggestData>::from_query(&req.query_string());
+    if params_some.is_err() {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "parametrs not found!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
     }
-    else {
-        offset = 0;
-        next_item = 21;
+    let params = params_some.unwrap();
+    let q = params.q.as_deref().unwrap_or("").trim().to_string();
+    let limit = params.limit.unwrap_or(5).clamp(1, 10);
+
+    let mut resp = SearchSuggestResp {
+        q:          q.clone(),
+        items:      Vec::new(),
+        categories: Vec::new(),
+        tags:       Vec::new(),
+    };
+    if q.chars().count() < 2 {
+        return Ok(Json(resp));
     }
 
-    let (items_list, items_count) = Item::search_stores(&_q_standalone, 20, offset.into(), _request_user.perm > 59);
-    if items_count >= (next_item as usize) {
-        next_page_number = page + 1;
+    let suggestions = block(move || SearchItem::get_suggestions(&q, limit)).await?;
+    for s in suggestions.into_iter() {
+        match s.kind.as_str() {
+            "item" => match resp.items.iter_mut().find(|g| g.types == s.types) {
+                Some(group) => group.items.push(s),
+                None => resp.items.push(SearchSuggestGroup {
+                    types: s.types,
+                    items: vec![s],
+                }),
+            },
+            "category" => resp.categories.push(s),
+            _ => resp.tags.push(s),
+        };
     }
-
-    return Ok(Json(SearchStoresResp {
-        request_user:     _request_user,
-        stores_list:      items_list,
-        stores_count:     items_count,
-        q:                q,
-        next_page_number: next_page_number as i16,
-    }));
+    return Ok(Json(resp));
 }
 
-#[derive(Serialize)]
-pub struct SearchWikisResp {
-    pub request_user:     UserResp,
-    pub wikis_list:       Vec<Wiki>,
-    pub wikis_count:      usize,
-    pub q:                String,
-    pub next_page_number: i16,
-}
-pub async fn search_wikis_page(req: HttpRequest) -> Result<Json<SearchWikisResp>, Error> {
-    let (q, page, is_ajax) = get_q_page_is_ajax(&req);
-    let _request_user = get_request_user(&req, is_ajax).await;
-    if q.is_empty() {
-        return Ok(Json(SearchWikisResp {
-            request_user:     _request_user,
-            wikis_list:       Vec::new(),
-            wikis_count:      0,
-            q:                q,
-            next_page_number: 0,
-        }));
-    }
 
-    let _q_standalone = "%".to_owned() + &q + "%";
-    let mut next_page_number = 0;
-    let offset: i32;
-    let next_item: i32;
-    
-    if page > 1 {
-        offset = (page - 1) * 20;
-        next_item = page * 20 + 1;
-    }
-    else {
-        offset = 0;
-        next_item = 21;
+#[derive(Deserialize)]
+pub struct SearchStatsData {
+    pub date_from: Option<String>,
+    pub date_to:   Option<String>,
+    pub limit:     Option<i64>,
+}
+async fn get_search_stats_params(req: &HttpRequest) -> Result<(chrono::NaiveDateTime, chrono::NaiveDateTime, i64), Error> {
+    // отчеты только для администратора. период по умолчанию -
+    // последние 30 дней, date_to включает весь указанный день
+    let _request_user = get_request_user(&req, 0).await;
+    if _request_user.perm != 60 {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "Permission Denied".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
     }
-
-    let (items_list, items_count) = Item::search_wikis(&_q_standalone, 20, offset.into(), _request_user.perm > 59);
-    if items_count >= (next_item as usize) {
-        next_page_number = page + 1;
+    let params_some = web::Query::<SearchStatsData>::from_query(&req.query_string());
+    if params_some.is_err() {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "parametrs not found!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
     }
-
-    return Ok(Json(SearchWikisResp {
-        request_user:     _request_user,
-        wikis_list:       items_list,
-        wikis_count:      items_count,
-        q:                q,
-        next_page_number: next_page_number as i16,
-    }));
+    let params = params_some.unwrap();
+    // даты в базе по московскому времени, как и у остальной статистики
+    let today = (chrono::Local::now().naive_utc() + chrono::Duration::hours(3)).date();
+    let date_to = get_next_day(get_date(&params.date_to, "date_to")?.unwrap_or(today), "date_to")?;
+    let date_from = match get_date(&params.date_from, "date_from")? {
+        Some(d) => d,
+        None => match date_to.checked_sub_signed(chrono::Duration::days(30)) {
+            Some(d) => d,
+            None => return Err(Error::BadRequest(serde_json::to_string(&ErrorParams {
+                error: "parametr 'date_to' is out of range!".to_string(),
+            }).unwrap())),
+        },
+    };
+    let limit = params.limit.unwrap_or(50).clamp(1, 500);
+    return Ok((date_from.and_hms(0, 0, 0), date_to.and_hms(0, 0, 0), limit));
 }
 
-#[derive(Serialize)]
-pub struct SearchWorksResp {
-    pub request_user:     UserResp,
-    pub works_list:       Vec<Work>,
-    pub works_count:      usize,
-    pub q:                String,
-    pub next_page_number: i16,
+pub async fn search_top_page(req: HttpRequest) -> Result<Json<Vec<SearchQueryStat>>, Error> {
+    let (date_from, date_to, limit) = get_search_stats_params(&req).await?;
+    let stats = block(move || SearchQuery::get_top(date_from, date_to, limit)).await?;
+    return Ok(Json(stats));
+}
+pub async fn search_zero_results_page(req: HttpRequest) -> Result<Json<Vec<SearchQueryStat>>, Error> {
+    let (date_from, date_to, limit) = get_search_stats_params(&req).await?;
+    let stats = block(move || SearchQuery::get_zero_results(date_from, date_to, limit)).await?;
+    return Ok(Json(stats));
+}
+pub async fn search_ctr_page(req: HttpRequest) -> Result<Json<SearchCtrStat>, Error> {
+    let (date_from, date_to, _) = get_search_stats_params(&req).await?;
+    let stats = block(move || SearchQuery::get_ctr(date_from, date_to)).await?;
+    return Ok(Json(stats));
 }
-pub async fn search_works_page(req: HttpRequest) -> Result<Json<SearchWorksResp>, Error> {
-    let (q, page, is_ajax) = get_q_page_is_ajax(&req);
-    let _request_user = get_request_user(&req, is_ajax).await;
-    if q.is_empty() {
-        return Ok(Json(SearchWorksResp {
-            request_user:     _request_user,
-            works_list:       Vec::new(),
-            works_count:      0,
-            q:                q,
-            next_page_number: 0,
-        }));
-    }
-
-    let _q_standalone = "%".to_owned() + &q + "%";
-    let mut next_page_number = 0;
-    let offset: i32;
-    let next_item: i32;
-    
-    if page > 1 {
-        offset = (page - 1) * 20;
-        next_item = page * 20 + 1;
-    }
-    else {
-        offset = 0;
-        next_item = 21;
-    }
 
-    let (items_list, items_count) = Item::search_works(&_q_standalone, 20, offset.into(), _request_user.perm > 59);
-    if items_count >= (next_item as usize) {
-        next_page_number = page + 1;
-    }
 
-    return Ok(Json(SearchWorksResp {
-        request_user:     _request_user,
-        works_list:       items_list,
-        works_count:      items_count,
-        q:                q,
-        next_page_number: next_page_number as i16,
-    }));
+#[derive(Deserialize)]
+pub struct SearchRebuildData {
+    pub token: Option<String>,
 }
-
 #[derive(Serialize)]
-pub struct SearchHelpsResp {
-    pub request_user:     UserResp,
-    pub helps_list:       Vec<Help>,
-    pub helps_count:      usize,
-    pub q:                String,
-    pub next_page_number: i16,
+pub struct SearchRebuildResp {
+    pub backend: String,
+    pub count:   usize,
 }
-pub async fn search_helps_page(req: HttpRequest) -> Result<Json<SearchHelpsResp>, Error> {
-    let (q, page, is_ajax) = get_q_page_is_ajax(&req);
-    let _request_user = get_request_user(&req, is_ajax).await;
-    if q.is_empty() {
-        return Ok(Json(SearchHelpsResp {
-            request_user:     _request_user,
-            helps_list:       Vec::new(),
-            helps_count:      0,
-            q:                q,
-            next_page_number: 0,
-        }));
-    }
-
-    let _q_standalone = "%".to_owned() + &q + "%";
-    let mut next_page_number = 0;
-    let offset: i32;
-    let next_item: i32;
-    
-    if page > 1 {
-        offset = (page - 1) * 20;
-        next_item = page * 20 + 1;
-    }
-    else {
-        offset = 0;
-        next_item = 21;
+pub async fn search_rebuild(req: HttpRequest, state: web::Data<AppState>, data: Json<SearchRebuildData>) -> Result<Json<SearchRebuildResp>, Error> {
+    // пересобрать поисковый индекс по базе, то же что `search-rebuild`
+    let _request_user = get_request_user(&req, 0).await;
+    if _request_user.perm != 60 {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "Permission Denied".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
     }
-
-    let (items_list, items_count) = Item::search_helps(&_q_standalone, 20, offset.into(), _request_user.perm > 59);
-    if items_count >= (next_item as usize) {
-        next_page_number = page + 1;
+    if data.token.is_none() || data.token.as_deref().unwrap() != TOKEN {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "Permission Denied".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
     }
 
-    return Ok(Json(SearchHelpsResp {
-        request_user:     _request_user,
-        helps_list:       items_list,
-        helps_count:      items_count,
-        q:                q,
-        next_page_number: next_page_number as i16,
+    let search = state.search.clone();
+    let count = block(move || search.rebuild()).await?
+        .map_err(Error::InternalServerError)?;
+    return Ok(Json(SearchRebuildResp {
+        backend: state.search.name().to_string(),
+        count:   count,
     }));
-}
\ No newline at end of file
+}
@@ -8,10 +8,13 @@ use crate::diesel::{
     RunQueryDsl,
     ExpressionMethods,
     QueryDsl,
+    Connection,
 };
 use crate::utils::{
     establish_connection, get_is_ajax,
     get_request_user, get_stat_page,
+    update_serve_category_counts,
+    update_tech_category_counts,
     ErrorParams, TOKEN, UserResp,
 };
 use crate::schema;
@@ -28,6 +31,7 @@ use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 use std::str;
 use crate::errors::Error;
+use crate::AppState;
 
 
 pub fn serve_routes(config: &mut web::ServiceConfig) {
@@ -476,7 +480,7 @@ pub async fn edit_serve_page(req: HttpRequest) -> Result<Json<EditServeResp>, Er
 }
 
 
-pub async fn create_tech_categories(req: HttpRequest, mut payload: Multipart) -> Result<Json<i16>, Error> {
+pub async fn create_tech_categories(req: HttpRequest, state: web::Data<AppState>, mut payload: Multipart) -> Result<Json<i16>, Error> {
     use crate::utils::category_form;
 
     let _request_user = get_request_user(&req, 3).await;
@@ -489,7 +493,7 @@ pub async fn create_tech_categories(req: HttpRequest, mut payload: Multipart) ->
     }
 
     let _connection = establish_connection();
-    let form = category_form(payload.borrow_mut(), user_id).await;
+    let form = category_form(payload.borrow_mut(), user_id, &state.storage).await?;
 
     if form.token != TOKEN.to_string() {
         let body = serde_json::to_string(&ErrorParams {
@@ -550,15 +554,18 @@ pub async fn create_serve_categories(req: HttpRequest, mut payload: Multipart) -
         height:          0.0,
         seconds:         0,
     };
-    let _new_serve = diesel::insert_into(schema::serve_categories::table)
-        .values(&new_cat)
-        .execute(&_connection)
-        .expect("E.");
+    _connection.transaction::<_, Error, _>(|| {
+        diesel::insert_into(schema::serve_categories::table)
+            .values(&new_cat)
+            .execute(&_connection)?;
+        update_tech_category_counts(&_connection, vec![form.tech_categories])?;
+        Ok(())
+    })?;
     
     return Ok(Json(1));
 }
 
-pub async fn edit_tech_category(req: HttpRequest, mut payload: Multipart) -> Result<Json<i16>, Error> {
+pub async fn edit_tech_category(req: HttpRequest, state: web::Data<AppState>, mut payload: Multipart) -> Result<Json<i16>, Error> {
     use crate::utils::category_form;
 
     let _request_user = get_request_user(&req, 3).await;
@@ -569,7 +576,7 @@ pub async fn edit_tech_category(req: HttpRequest, mut payload: Multipart) -> Res
         }).unwrap();
         return Err(Error::BadRequest(body));
     }
-    let form = category_form(payload.borrow_mut(), user_id).await;
+    let form = category_form(payload.borrow_mut(), user_id, &state.storage).await?;
     let _connection = establish_connection();
     if form.id < 1 {
         let body = serde_json::to_string(&ErrorParams {
@@ -825,21 +832,14 @@ pub async fn create_serve(req: HttpRequest, mut payload: Multipart) -> Result<Js
         seconds:          0,
     };
 
-    let _serve = diesel::insert_into(schema::serve::table)
-        .values(&_new_serve)
-        .get_result::<Serve>(&_connection)
-        .expect("E.");
-
-    if form.is_default {
-        diesel::update(&_category)
-            .set(schema::serve_categories::default_price.eq(_category.default_price + _serve.price))
-            .execute(&_connection)
-            .expect("E.");
-    }
-    diesel::update(&_category)
-        .set(schema::serve_categories::count.eq(_category.count + 1))
-        .execute(&_connection)
-        .expect("E.");
+    // число опций и цена по умолчанию пересчитываются в той же транзакции
+    _connection.transaction::<_, Error, _>(|| {
+        diesel::insert_into(schema::serve::table)
+            .values(&_new_serve)
+            .execute(&_connection)?;
+        update_serve_category_counts(&_connection, vec![_category.id])?;
+        Ok(())
+    })?;
         
     return Ok(Json(1));
 }
@@ -885,31 +885,6 @@ pub async fn edit_serve(req: HttpRequest, mut payload: Multipart) -> Result<Json
         return Err(Error::BadRequest(body));
     }
     let is_default = form.is_default;
-    if _serve.is_default {
-        // если опция дефолтная
-        if !is_default {
-            // если в форме галочка снята
-            diesel::update(&_category)
-                .set(schema::serve_categories::default_price
-                    .eq(_category.default_price - _serve.price)
-                )
-                .execute(&_connection)
-                .expect("E.");
-            }
-        }
-    else {
-        // если опция не дефолтная
-        if is_default {
-            // если в форме галочка поставлена
-            diesel::update(&_category)
-                .set(schema::serve_categories::default_price
-                        .eq(_category.default_price + _serve.price)
-                )
-                .execute(&_connection)
-                .expect("E.");
-        }
-    }
-
     let __serve = crate::models::EditServe { 
         name:             form.name.clone(),
         description:      Some(form.description.clone()),
@@ -921,10 +896,15 @@ pub async fn edit_serve(req: HttpRequest, mut payload: Multipart) -> Result<Json
         serve_id:         form.serve_id,
     };
 
-    diesel::update(&_serve)
-        .set(__serve)
-        .execute(&_connection)
-        .expect("E");
+    // опция могла сменить категорию, цену или галочку "по умолчанию" -
+    // пересчитываем и старую, и новую категорию
+    _connection.transaction::<_, Error, _>(|| {
+        diesel::update(&_serve)
+            .set(__serve)
+            .execute(&_connection)?;
+        update_serve_category_counts(&_connection, vec![_category.id, form.serve_categories])?;
+        Ok(())
+    })?;
 
     return Ok(Json(1));
 }
@@ -969,27 +949,11 @@ pub async fn delete_serve(req: HttpRequest, data: Json<DeleteServeData>) -> Resu
         return Err(Error::BadRequest(body));
     }
 
-    let _category = schema::serve_categories::table
-        .filter(schema::serve_categories::id.eq(_serve.serve_categories))
-        .first::<ServeCategories>(&_connection)
-        .expect("E");
-    if _serve.is_default {
-        diesel::update(&_category)
-            .set((
-                schema::serve_categories::count.eq(&_category.count - 1),
-                schema::serve_categories::default_price.eq(&_category.default_price - _serve.price),
-            ))
-            .execute(&_connection)
-            .expect("Error.");
-    }
-    else {
-        diesel::update(&_category)
-            .set(schema::serve_categories::count.eq(&_category.count - 1))
-            .execute(&_connection)
-            .expect("Error.");
-    }
-
-    diesel::delete(&_serve).execute(&_connection).expect("E");
+    _connection.transaction::<_, Error, _>(|| {
+        diesel::delete(&_serve).execute(&_connection)?;
+        update_serve_category_counts(&_connection, vec![_serve.serve_categories])?;
+        Ok(())
+    })?;
     return Ok(Json(1));
 }
 
@@ -1056,10 +1020,6 @@ pub async fn delete_serve_category(req: HttpRequest, data: Json<DeleteServeData>
         return Err(Error::BadRequest(body));
     }
     
-    let _connection = establish_connection();
-
-    use crate::schema::tech_categories::dsl::tech_categories;
-
     let _connection = establish_connection();
     let s_category = schema::serve_categories::table
         .filter(schema::serve_categories::id.eq(data.id.unwrap()))
@@ -1073,16 +1033,11 @@ pub async fn delete_serve_category(req: HttpRequest, data: Json<DeleteServeData>
         return Err(Error::BadRequest(body));
     }
 
-    let _category = tech_categories
-        .filter(schema::tech_categories::id.eq(s_category.tech_categories))
-        .first::<TechCategories>(&_connection)
-        .expect("E");
-
-    diesel::delete(&s_category).execute(&_connection).expect("E");
-    diesel::update(&_category)
-        .set(schema::tech_categories::count.eq(&_category.count - 1))
-        .execute(&_connection)
-        .expect("E");
+    _connection.transaction::<_, Error, _>(|| {
+        diesel::delete(&s_category).execute(&_connection)?;
+        update_tech_category_counts(&_connection, vec![s_category.tech_categories])?;
+        Ok(())
+    })?;
 
     return Ok(Json(1));
 }
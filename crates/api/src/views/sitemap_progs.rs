@@ -0,0 +1,98 @@
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    web,
+    web::block,
+};
+use serde::Deserialize;
+use crate::utils::{
+    get_sitemap_index,
+    get_items_sitemap,
+    get_categories_sitemap,
+    get_robots_txt,
+    get_sitemap_pages_count,
+};
+use crate::errors::Error;
+use crate::AppState;
+
+
+pub fn sitemap_routes(config: &mut web::ServiceConfig) {
+    config.route("/sitemap.xml", web::get().to(sitemap_index_page));
+    config.route("/sitemap_items.xml", web::get().to(sitemap_items_page));
+    config.route("/sitemap_categories.xml", web::get().to(sitemap_categories_page));
+    config.route("/robots.txt", web::get().to(robots_page));
+}
+
+// карты сайта сбрасываются при публикации / скрытии / изменении
+// объектов и категорий - см. clear_sitemap_cache
+pub fn clear_sitemap_cache(state: &web::Data<AppState>) {
+    state.cache.clear("sitemap:");
+}
+
+fn xml_response(xml: String) -> HttpResponse {
+    return HttpResponse::Ok()
+        .content_type("application/xml; charset=utf-8")
+        .body(xml);
+}
+
+pub async fn sitemap_index_page(state: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    let key = "sitemap:index";
+    if let Some(xml) = state.cache.get(key) {
+        return Ok(xml_response(xml));
+    }
+    let xml = block(move || get_sitemap_index()).await?;
+    state.cache.set(key, xml.clone());
+    return Ok(xml_response(xml));
+}
+
+#[derive(Deserialize)]
+pub struct SitemapPageData {
+    pub page: Option<i64>,
+}
+pub async fn sitemap_items_page(req: HttpRequest, state: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    let params_some = web::Query::<SitemapPageData>::from_query(&req.query_string());
+    let page: i64;
+    if params_some.is_ok() && params_some.as_ref().unwrap().page.is_some() {
+        page = params_some.unwrap().page.unwrap();
+    }
+    else {
+        page = 1;
+    }
+    // несуществующие страницы не попадают в кеш
+    if page < 1 || page > block(move || get_sitemap_pages_count()).await? {
+        return Err(Error::NotFound("sitemap page not found".to_string()));
+    }
+
+    let key = format!("sitemap:items:{}", page);
+    if let Some(xml) = state.cache.get(&key) {
+        return Ok(xml_response(xml));
+    }
+    let xml = block(move || get_items_sitemap(page)).await?;
+    state.cache.set(&key, xml.clone());
+    return Ok(xml_response(xml));
+}
+
+pub async fn sitemap_categories_page(state: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    let key = "sitemap:categories";
+    if let Some(xml) = state.cache.get(key) {
+        return Ok(xml_response(xml));
+    }
+    let xml = block(move || get_categories_sitemap()).await?;
+    state.cache.set(key, xml.clone());
+    return Ok(xml_response(xml));
+}
+
+pub async fn robots_page(state: web::Data<AppState>) -> HttpResponse {
+    let key = "robots";
+    let txt: String;
+    if let Some(cached) = state.cache.get(key) {
+        txt = cached;
+    }
+    else {
+        txt = get_robots_txt();
+        state.cache.set(key, txt.clone());
+    }
+    return HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .body(txt);
+}
@@ -1,5 +1,6 @@
 use actix_web::{
     HttpRequest,
+    HttpResponse,
     web,
     web::Json,
 };
@@ -10,20 +11,24 @@ use crate::diesel::{
     RunQueryDsl,
     ExpressionMethods,
     QueryDsl,
+    Connection,
 };
 use crate::utils::{
     establish_connection, get_is_ajax,
     get_request_user, get_stat_page,
     get_is_ajax_page, PageStatData,
     ErrorParams, TOKEN, UserResp,
+    get_request_user_id,
 };
 use crate::schema;
 use crate::models::{
     Tag, SmallTag, NewTag, TagItems,
     Item, Blog, Service, Store, Wiki, Work, Help,
-    EditTag,
+    EditTag, SlugRedirect, Categories,
 };
+use crate::views::update_search_index_items;
 use crate::errors::Error;
+use crate::AppState;
 
 
 pub fn tag_routes(config: &mut web::ServiceConfig) {
@@ -44,13 +49,21 @@ pub fn tag_routes(config: &mut web::ServiceConfig) {
         .route(web::post().to(edit_tag))
     );
     config.route("/delete_tag", web::get().to(delete_tag));
+    config.route("/merge_tags", web::post().to(merge_tags));
+    config.route("/tags_autocomplete", web::get().to(tags_autocomplete));
+    config.route("/tags_suggest", web::get().to(tags_suggest));
+    config.route("/tag_cloud", web::get().to(tag_cloud_page));
+}
+
+// облака тегов сбрасываются при изменении объектов, категорий и тегов
+pub fn clear_tag_cloud_cache(state: &web::Data<AppState>) {
+    state.cache.clear("tag_cloud:");
 }
 
 
 #[derive(Serialize)]
 pub struct CreateTagPageResp {
     pub request_user: UserResp,
-    pub all_tags:     Vec<Tag>,
 }
 pub async fn create_tag_page(req: HttpRequest) -> Result<Json<CreateTagPageResp>, Error> {
     let _request_user = get_request_user(&req, get_is_ajax(&req)).await;
@@ -61,19 +74,14 @@ pub async fn create_tag_page(req: HttpRequest) -> Result<Json<CreateTagPageResp>
         return Err(Error::BadRequest(body));
     }
 
-    let _connection = establish_connection();
-    let all_tags = schema::tags::table
-        .load::<Tag>(&_connection)
-        .expect("Error.");
-    
+    // существующие теги редактор подбирает через /tags_autocomplete
     return Ok(Json(CreateTagPageResp {
         request_user: _request_user,
-        all_tags:     all_tags,
     }));
 }
 
 
-pub async fn create_tag(req: HttpRequest, mut payload: Multipart) -> Result<Json<i16>, Error> {
+pub async fn create_tag(req: HttpRequest, state: web::Data<AppState>, mut payload: Multipart) -> Result<Json<i16>, Error> {
     let _request_user = get_request_user(&req, 3).await;
     let user_id = _request_user.id;
     if user_id < 1 {
@@ -82,7 +90,7 @@ pub async fn create_tag(req: HttpRequest, mut payload: Multipart) -> Result<Json
         }).unwrap();
         return Err(Error::BadRequest(body));
     }
-    let form = crate::utils::category_form(payload.borrow_mut(), user_id).await;
+    let form = crate::utils::category_form(payload.borrow_mut(), user_id, &state.storage).await?;
     if form.token != TOKEN.to_string() {
         let body = serde_json::to_string(&ErrorParams {
             error: "Permission Denied".to_string(),
@@ -91,9 +99,11 @@ pub async fn create_tag(req: HttpRequest, mut payload: Multipart) -> Result<Json
     }
 
     let _connection = establish_connection();
-            
+    // slug можно задать вручную, иначе он строится из названия
+    let slug_source = if form.slug.is_empty() { &form.name } else { &form.slug };
     let new_tag = NewTag {
         name:     form.name.clone(),
+        slug:     Tag::get_unique_slug(slug_source, 0),
         position: form.position,
         count:    0,
         user_id:  _request_user.id,
@@ -159,10 +169,11 @@ pub async fn tag_page(req: HttpRequest) -> Result<Json<TagPageResp>, Error> {
     let _connection = establish_connection();
     let _request_user = get_request_user(&req, is_ajax).await;
     let is_admin = _request_user.perm == 60;
-    let _tag = schema::tags::table
-        .filter(schema::tags::name.eq(params.slug.as_deref().unwrap()))
-        .first::<Tag>(&_connection)
-        .expect("E");
+    let _tag_some = Tag::get_tag_by_slug(params.slug.as_deref().unwrap());
+    if _tag_some.is_none() {
+        return Err(Error::NotFound("tag not found".to_string()));
+    }
+    let _tag = _tag_some.unwrap();
     let _tag_items = schema::tags_items::table
         .filter(schema::tags_items::tag_id.eq(&_tag.id))
         .load::<TagItems>(&_connection)
@@ -246,10 +257,11 @@ pub async fn tag_blogs_page(req: HttpRequest) -> Result<Json<TagBlogsPageResp>,
 
     let _connection = establish_connection();
     let _request_user = get_request_user(&req, is_ajax).await;
-    let _tag = schema::tags::table
-        .filter(schema::tags::name.eq(params.slug.as_deref().unwrap()))
-        .first::<Tag>(&_connection)
-        .expect("E");
+    let _tag_some = Tag::get_tag_by_slug(params.slug.as_deref().unwrap());
+    if _tag_some.is_none() {
+        return Err(Error::NotFound("tag not found".to_string()));
+    }
+    let _tag = _tag_some.unwrap();
     let _tag_items = schema::tags_items::table
         .filter(schema::tags_items::tag_id.eq(&_tag.id))
         .filter(schema::tags_items::types.eq(1))
@@ -309,10 +321,11 @@ pub async fn tag_services_page(req: HttpRequest) -> Result<Json<TagServicesPageR
 
     let _connection = establish_connection();
     let _request_user = get_request_user(&req, is_ajax).await;
-    let _tag = schema::tags::table
-        .filter(schema::tags::name.eq(params.slug.as_deref().unwrap()))
-        .first::<Tag>(&_connection)
-        .expect("E");
+    let _tag_some = Tag::get_tag_by_slug(params.slug.as_deref().unwrap());
+    if _tag_some.is_none() {
+        return Err(Error::NotFound("tag not found".to_string()));
+    }
+    let _tag = _tag_some.unwrap();
     let _tag_items = schema::tags_items::table
         .filter(schema::tags_items::tag_id.eq(&_tag.id))
         .filter(schema::tags_items::types.eq(2))
@@ -372,10 +385,11 @@ pub async fn tag_stores_page(req: HttpRequest) -> Result<Json<TagStoresPageResp>
 
     let _connection = establish_connection();
     let _request_user = get_request_user(&req, is_ajax).await;
-    let _tag = schema::tags::table
-        .filter(schema::tags::name.eq(params.slug.as_deref().unwrap()))
-        .first::<Tag>(&_connection)
-        .expect("E");
+    let _tag_some = Tag::get_tag_by_slug(params.slug.as_deref().unwrap());
+    if _tag_some.is_none() {
+        return Err(Error::NotFound("tag not found".to_string()));
+    }
+    let _tag = _tag_some.unwrap();
     let _tag_items = schema::tags_items::table
         .filter(schema::tags_items::tag_id.eq(&_tag.id))
         .filter(schema::tags_items::types.eq(3))
@@ -435,10 +449,11 @@ pub async fn tag_wikis_page(req: HttpRequest) -> Result<Json<TagWikisPageResp>,
 
     let _connection = establish_connection();
     let _request_user = get_request_user(&req, is_ajax).await;
-    let _tag = schema::tags::table
-        .filter(schema::tags::name.eq(params.slug.as_deref().unwrap()))
-        .first::<Tag>(&_connection)
-        .expect("E");
+    let _tag_some = Tag::get_tag_by_slug(params.slug.as_deref().unwrap());
+    if _tag_some.is_none() {
+        return Err(Error::NotFound("tag not found".to_string()));
+    }
+    let _tag = _tag_some.unwrap();
     let _tag_items = schema::tags_items::table
         .filter(schema::tags_items::tag_id.eq(&_tag.id))
         .filter(schema::tags_items::types.eq(4))
@@ -498,10 +513,11 @@ pub async fn tag_works_page(req: HttpRequest) -> Result<Json<TagWorksPageResp>,
 
     let _connection = establish_connection();
     let _request_user = get_request_user(&req, is_ajax).await;
-    let _tag = schema::tags::table
-        .filter(schema::tags::name.eq(params.slug.as_deref().unwrap()))
-        .first::<Tag>(&_connection)
-        .expect("E");
+    let _tag_some = Tag::get_tag_by_slug(params.slug.as_deref().unwrap());
+    if _tag_some.is_none() {
+        return Err(Error::NotFound("tag not found".to_string()));
+    }
+    let _tag = _tag_some.unwrap();
     let _tag_items = schema::tags_items::table
         .filter(schema::tags_items::tag_id.eq(&_tag.id))
         .filter(schema::tags_items::types.eq(5))
@@ -561,10 +577,11 @@ pub async fn tag_helps_page(req: HttpRequest) -> Result<Json<TagHelpsPageResp>,
 
     let _connection = establish_connection();
     let _request_user = get_request_user(&req, is_ajax).await;
-    let _tag = schema::tags::table
-        .filter(schema::tags::name.eq(params.slug.as_deref().unwrap()))
-        .first::<Tag>(&_connection)
-        .expect("E");
+    let _tag_some = Tag::get_tag_by_slug(params.slug.as_deref().unwrap());
+    if _tag_some.is_none() {
+        return Err(Error::NotFound("tag not found".to_string()));
+    }
+    let _tag = _tag_some.unwrap();
     let _tag_items = schema::tags_items::table
         .filter(schema::tags_items::tag_id.eq(&_tag.id))
         .filter(schema::tags_items::types.eq(6))
@@ -662,7 +679,7 @@ pub async fn edit_tag_page(req: HttpRequest) -> Result<Json<EditTagResp>, Error>
 }
 
 
-pub async fn edit_tag(req: HttpRequest, mut payload: Multipart) -> Result<Json<i16>, Error> {
+pub async fn edit_tag(req: HttpRequest, state: web::Data<AppState>, mut payload: Multipart) -> Result<Json<i16>, Error> {
     let _request_user = get_request_user(&req, get_is_ajax(&req)).await;
     if _request_user.id < 1 {
         let body = serde_json::to_string(&ErrorParams {
@@ -670,7 +687,7 @@ pub async fn edit_tag(req: HttpRequest, mut payload: Multipart) -> Result<Json<i
         }).unwrap();
         return Err(Error::BadRequest(body));
     }
-    let form = crate::utils::category_form(payload.borrow_mut(), _request_user.id).await;
+    let form = crate::utils::category_form(payload.borrow_mut(), _request_user.id, &state.storage).await?;
     if form.id < 1 {
         let body = serde_json::to_string(&ErrorParams {
             error: "parametr 'id' not found!".to_string(),
@@ -696,16 +713,31 @@ pub async fn edit_tag(req: HttpRequest, mut payload: Multipart) -> Result<Json<i
         }).unwrap();
         return Err(Error::BadRequest(body));
     }
+    let slug_source = if form.slug.is_empty() { &form.name } else { &form.slug };
     let _new_tag = EditTag {
         name:     form.name.clone(),
+        slug:     Tag::get_unique_slug(slug_source, _tag.id),
         position: form.position,
     };
 
-    diesel::update(&_tag)
-        .set(_new_tag)
-        .execute(&_connection)
-        .expect("E");
-
+    let is_slug_changed = _new_tag.slug != _tag.slug;
+    let is_name_changed = _new_tag.name != _tag.name;
+    _connection.transaction::<_, Error, _>(|| {
+        diesel::update(&_tag)
+            .set(&_new_tag)
+            .execute(&_connection)?;
+        // старые ссылки на тег продолжают работать
+        if is_slug_changed {
+            SlugRedirect::create(&_connection, 1, _tag.slug.clone(), _tag.id, _tag.id)?;
+        }
+        Ok(())
+    })?;
+
+    clear_tag_cloud_cache(&state);
+    // имя тега есть в поисковом документе объекта
+    if is_name_changed {
+        update_search_index_items(&state, _tag.get_item_ids()).await?;
+    }
     return Ok(Json(1));
 }
 
@@ -714,7 +746,7 @@ pub struct DeleteItemData {
     pub token: Option<String>,
     pub id:    Option<i32>,
 }
-pub async fn delete_tag(req: HttpRequest, data: Json<DeleteItemData>) -> Result<Json<i16>, Error> {
+pub async fn delete_tag(req: HttpRequest, state: web::Data<AppState>, data: Json<DeleteItemData>) -> Result<Json<i16>, Error> {
     let _request_user = get_request_user(&req, 3).await;
     if _request_user.id < 1 {
         let body = serde_json::to_string(&ErrorParams {
@@ -749,16 +781,217 @@ pub async fn delete_tag(req: HttpRequest, data: Json<DeleteItemData>) -> Result<
     }
     use crate::schema::tags_items::dsl::tags_items;
 
-    diesel::delete(
-        tags_items.filter(
-            schema::tags_items::tag_id.eq(_tag.id))
-        )
-        .execute(&_connection)
-        .expect("E");
+    let item_ids = _tag.get_item_ids();
+    _connection.transaction::<_, Error, _>(|| {
+        diesel::delete(
+            tags_items.filter(
+                schema::tags_items::tag_id.eq(_tag.id))
+            )
+            .execute(&_connection)?;
+
+        diesel::delete(&_tag)
+            .execute(&_connection)?;
+        Ok(())
+    })?;
+
+    clear_tag_cloud_cache(&state);
+    update_search_index_items(&state, item_ids).await?;
+    return Ok(Json(1));
+}
 
-    diesel::delete(&_tag)
-        .execute(&_connection)
-        .expect("E");
+#[derive(Deserialize)]
+pub struct MergeTagsData {
+    pub source_id: Option<i32>,
+    pub target_id: Option<i32>,
+    pub token:     Option<String>,
+}
+pub async fn merge_tags(req: HttpRequest, state: web::Data<AppState>, data: Json<MergeTagsData>) -> Result<Json<i16>, Error> {
+    // вливаем тег source_id в target_id
+    let _request_user = get_request_user(&req, 0).await;
+    if _request_user.perm != 60 {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "Permission Denied".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+    if data.token.is_none() || data.token.as_deref().unwrap() != TOKEN {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "Permission Denied".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+    if data.source_id.is_none() || data.target_id.is_none() || data.source_id == data.target_id {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "parametrs 'source_id' and 'target_id' not found!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
 
+    let _connection = establish_connection();
+    let _source = schema::tags::table
+        .filter(schema::tags::id.eq(data.source_id.unwrap()))
+        .first::<Tag>(&_connection);
+    let _target = schema::tags::table
+        .filter(schema::tags::id.eq(data.target_id.unwrap()))
+        .first::<Tag>(&_connection);
+    if _source.is_err() || _target.is_err() {
+        return Err(Error::NotFound("tag not found".to_string()));
+    }
+    let _source = _source.unwrap();
+    let _target = _target.unwrap();
+
+    // объекты влитого тега получают в индексе имя target
+    let item_ids = _source.get_item_ids();
+    web::block(move || _source.merge(&_target)).await??;
+    clear_tag_cloud_cache(&state);
+    update_search_index_items(&state, item_ids).await?;
     return Ok(Json(1));
 }
+
+#[derive(Deserialize)]
+pub struct TagsAutocompleteData {
+    pub q:     Option<String>,
+    pub limit: Option<i64>,
+}
+pub async fn tags_autocomplete(req: HttpRequest) -> Result<Json<Vec<SmallTag>>, Error> {
+    if get_request_user_id(&req).await < 1 {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "Permission Denied".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+    let params_some = web::Query::<TagsAutocompleteData>::from_query(&req.query_string());
+    if params_some.is_err() {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "parametrs not found!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+    let params = params_some.unwrap();
+    if params.q.is_none() || params.q.as_deref().unwrap().trim() == "" {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "parametr 'q' not found!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+    let q = params.q.clone().unwrap();
+    let limit = params.limit.unwrap_or(10).clamp(1, 50);
+
+    let _tags = web::block(move || Tag::search(&q, limit)).await?;
+    return Ok(Json(_tags));
+}
+
+#[derive(Deserialize)]
+pub struct TagsSuggestData {
+    pub item_id: Option<i32>,
+    pub limit:   Option<i64>,
+}
+pub async fn tags_suggest(req: HttpRequest) -> Result<Json<Vec<SmallTag>>, Error> {
+    let user_id = get_request_user_id(&req).await;
+    if user_id < 1 {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "Permission Denied".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+    let params_some = web::Query::<TagsSuggestData>::from_query(&req.query_string());
+    if params_some.is_err() {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "parametrs not found!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+    let params = params_some.unwrap();
+    if params.item_id.is_none() || params.item_id.unwrap() < 1 {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "parametr 'item_id' not found!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+    let item_id = params.item_id.unwrap();
+    let limit = params.limit.unwrap_or(10).clamp(1, 50);
+
+    // подсказки - по тексту объекта, в т.ч. черновика: только автору и админу
+    let _connection = establish_connection();
+    let _owner_id = schema::items::table
+        .filter(schema::items::id.eq(item_id))
+        .select(schema::items::user_id)
+        .first::<i32>(&_connection);
+    if _owner_id.is_err() {
+        return Err(Error::NotFound("item not found".to_string()));
+    }
+    if _owner_id.unwrap() != user_id {
+        let _perm = schema::users::table
+            .filter(schema::users::id.eq(user_id))
+            .select(schema::users::perm)
+            .first::<i16>(&_connection)
+            .unwrap_or(0);
+        if _perm != 60 {
+            let body = serde_json::to_string(&ErrorParams {
+                error: "Permission Denied".to_string(),
+            }).unwrap();
+            return Err(Error::BadRequest(body));
+        }
+    }
+
+    let _tags = web::block(move || Tag::get_suggestions(item_id, limit)).await?;
+    return Ok(Json(_tags));
+}
+
+#[derive(Deserialize)]
+pub struct TagCloudData {
+    pub types:    Option<i16>,
+    pub cat_slug: Option<String>,
+    pub limit:    Option<i64>,
+}
+pub async fn tag_cloud_page(req: HttpRequest, state: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    // облако тегов типа объектов (1-6), cat_slug сужает его до категории
+    // вместе с подкатегориями
+    let params_some = web::Query::<TagCloudData>::from_query(&req.query_string());
+    if params_some.is_err() {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "parametrs not found!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+    let params = params_some.unwrap();
+    if params.types.is_none() || params.types.unwrap() < 1 || params.types.unwrap() > 6 {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "parametr 'types' not found!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+    let types = params.types.unwrap();
+    let limit = params.limit.unwrap_or(50).clamp(1, 200);
+
+    let mut cat_id = 0;
+    if params.cat_slug.is_some() && params.cat_slug.as_deref().unwrap() != "" {
+        let cat_slug = Categories::get_actual_slug(params.cat_slug.as_deref().unwrap(), types);
+        let _connection = establish_connection();
+        let _cat_id = schema::categories::table
+            .filter(schema::categories::slug.eq(&cat_slug))
+            .filter(schema::categories::types.eq(types))
+            .select(schema::categories::id)
+            .first::<i32>(&_connection);
+        if _cat_id.is_err() {
+            return Err(Error::NotFound("category not found".to_string()));
+        }
+        cat_id = _cat_id.unwrap();
+    }
+
+    let key = format!("tag_cloud:{}:{}:{}", types, cat_id, limit);
+    if let Some(json) = state.cache.get(&key) {
+        return Ok(HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json));
+    }
+    let _tags = web::block(move || {
+        let cat_ids = if cat_id > 0 { Categories::get_descendant_ids(cat_id) } else { Vec::new() };
+        Tag::get_cloud(types, cat_ids, limit)
+    }).await?;
+    let json = serde_json::to_string(&_tags).unwrap();
+    state.cache.set(&key, json.clone());
+    return Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(json));
+}
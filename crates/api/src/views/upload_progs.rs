@@ -0,0 +1,337 @@
+use actix_web::{
+    HttpRequest,
+    web,
+    web::{block, Json},
+};
+use crate::schema;
+use crate::models::{
+    Item,
+    Message,
+    Order,
+    NewFile,
+    NewOrderFile,
+    MESSAGE_FILES_TYPES,
+};
+use serde::{Deserialize, Serialize};
+use crate::utils::{
+    establish_connection,
+    get_request_user_id,
+    get_cookie_user_id,
+    get_file_types,
+    store_upload,
+    ErrorParams, UploadKind, UploadPins, UploadSession, UploadedFiles,
+    UPLOAD_CHUNK_SIZE, TOKEN,
+};
+use crate::diesel::{
+    PgConnection,
+    QueryResult,
+    RunQueryDsl,
+    ExpressionMethods,
+    QueryDsl,
+};
+use crate::errors::Error;
+use crate::AppState;
+
+
+// докачиваемая загрузка (см. utils/resumable.rs):
+// 1. POST /uploads {token, kind, filename, size, sha256} - получаем id
+// 2. PATCH /uploads/{id}?offset=N, тело - байты куска; при обрыве
+//    GET /uploads/{id} отдает offset, с которого продолжать
+// 3. POST /uploads/{id}/finalize {token, target, target_id} - проверка
+//    sha256, запись в хранилище и привязка к объекту, заказу или сообщению
+pub fn upload_routes(config: &mut web::ServiceConfig) {
+    config.route("/uploads", web::post().to(create_upload));
+    config.service(web::resource("/uploads/{id}")
+        .app_data(web::PayloadConfig::new(UPLOAD_CHUNK_SIZE))
+        .route(web::get().to(get_upload))
+        .route(web::patch().to(upload_chunk))
+        .route(web::delete().to(delete_upload))
+    );
+    config.route("/uploads/{id}/finalize", web::post().to(finalize_upload));
+}
+
+fn permission_denied() -> Error {
+    let body = serde_json::to_string(&ErrorParams {
+        error: "Permission Denied".to_string(),
+    }).unwrap();
+    return Error::BadRequest(body);
+}
+
+async fn get_owner_id(req: &HttpRequest, kind: UploadKind) -> i32 {
+    // файлы заказа загружает cookie-пользователь, остальные - вошедший
+    return match kind {
+        UploadKind::Order => get_cookie_user_id(req).await,
+        _ => get_request_user_id(req).await,
+    };
+}
+
+async fn get_own_session(req: &HttpRequest, id: String) -> Result<UploadSession, Error> {
+    let session = block(move || UploadSession::open(&id)).await??;
+    let owner_id = get_owner_id(req, session.kind).await;
+    if owner_id < 1 || owner_id != session.owner_id {
+        return Err(Error::NotFound("upload not found".to_string()));
+    }
+    return Ok(session);
+}
+
+#[derive(Serialize)]
+pub struct UploadStatusResp {
+    pub id:         String,
+    pub offset:     usize, // сколько байт получено
+    pub size:       usize,
+    pub chunk_size: usize, // наибольший кусок
+}
+impl UploadStatusResp {
+    fn new(session: &UploadSession, offset: usize) -> UploadStatusResp {
+        UploadStatusResp {
+            id:         session.id.clone(),
+            offset:     offset,
+            size:       session.size,
+            chunk_size: UPLOAD_CHUNK_SIZE,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateUploadData {
+    pub token:    Option<String>,
+    pub kind:     Option<UploadKind>, // media (по умолчанию) или order
+    pub filename: Option<String>,
+    pub size:     Option<usize>,
+    pub sha256:   Option<String>,     // контрольная сумма всего файла, hex
+}
+pub async fn create_upload(req: HttpRequest, data: Json<CreateUploadData>) -> Result<Json<UploadStatusResp>, Error> {
+    if data.token.is_none() || data.token.as_deref().unwrap() != TOKEN {
+        return Err(permission_denied());
+    }
+    let kind = data.kind.unwrap_or(UploadKind::Media);
+    if kind == UploadKind::Image {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "parametr 'kind' must be 'media' or 'order'!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+    if data.filename.is_none() || data.size.is_none() || data.sha256.is_none() {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "parametrs 'filename', 'size' and 'sha256' not found!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+    let owner_id = get_owner_id(&req, kind).await;
+    if owner_id < 1 {
+        return Err(permission_denied());
+    }
+
+    let (filename, size, sha256) = (data.filename.clone().unwrap(), data.size.unwrap(), data.sha256.clone().unwrap());
+    let session = block(move || UploadSession::create(owner_id, kind, &filename, size, &sha256)).await??;
+    return Ok(Json(UploadStatusResp::new(&session, 0)));
+}
+
+pub async fn get_upload(req: HttpRequest, id: web::Path<String>) -> Result<Json<UploadStatusResp>, Error> {
+    let session = get_own_session(&req, id.into_inner()).await?;
+    let offset = session.get_offset()?;
+    return Ok(Json(UploadStatusResp::new(&session, offset)));
+}
+
+#[derive(Deserialize)]
+pub struct UploadChunkData {
+    pub offset: Option<usize>,
+}
+pub async fn upload_chunk(req: HttpRequest, id: web::Path<String>, body: web::Bytes) -> Result<Json<UploadStatusResp>, Error> {
+    let params_some = web::Query::<UploadChunkData>::from_query(&req.query_string());
+    if params_some.is_err() || params_some.as_ref().unwrap().offset.is_none() {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "parametr 'offset' not found!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+    let offset = params_some.unwrap().offset.unwrap();
+    let session = get_own_session(&req, id.into_inner()).await?;
+
+    let _session = session.clone();
+    let new_offset = block(move || _session.append(offset, &body)).await??;
+    return Ok(Json(UploadStatusResp::new(&session, new_offset)));
+}
+
+pub async fn delete_upload(req: HttpRequest, id: web::Path<String>) -> Result<Json<i16>, Error> {
+    let session = get_own_session(&req, id.into_inner()).await?;
+    block(move || session.remove()).await?;
+    return Ok(Json(1));
+}
+
+#[derive(Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadTarget {
+    Item,    // галерея объекта, kind=media
+    Message, // вложение сообщения, kind=media
+    Order,   // вложение заказа, kind=order
+}
+#[derive(Deserialize)]
+pub struct FinalizeUploadData {
+    pub token:     Option<String>,
+    pub target:    Option<UploadTarget>,
+    pub target_id: Option<i32>,
+}
+#[derive(Serialize)]
+pub struct FinalizeUploadResp {
+    pub id:    i32,    // строка files или order_files
+    pub types: i16,    // фото, видео, аудио, документ
+    pub src:   String,
+    pub mime:  String,
+    pub size:  i32,
+}
+fn insert_target_file(_connection: &PgConnection, target: &UploadTarget, target_id: i32, owner_id: i32, item_types: i16, types: i16, file: &UploadedFiles) -> QueryResult<i32> {
+    // строка files или order_files загруженного файла, возвращает ее id.
+    // вложения сообщений читает Message::get_files (MESSAGE_FILES_TYPES)
+    return match target {
+        UploadTarget::Order => diesel::insert_into(schema::order_files::table)
+            .values(&NewOrderFile::create(target_id, file))
+            .returning(schema::order_files::id)
+            .get_result::<i32>(_connection),
+        UploadTarget::Message | UploadTarget::Item => diesel::insert_into(schema::files::table)
+            .values(&NewFile::create(owner_id, target_id, item_types, types, file))
+            .returning(schema::files::id)
+            .get_result::<i32>(_connection),
+    };
+}
+
+pub async fn finalize_upload(req: HttpRequest, state: web::Data<AppState>, id: web::Path<String>, data: Json<FinalizeUploadData>) -> Result<Json<FinalizeUploadResp>, Error> {
+    if data.token.is_none() || data.token.as_deref().unwrap() != TOKEN {
+        return Err(permission_denied());
+    }
+    if data.target.is_none() || data.target_id.is_none() || data.target_id.unwrap() < 1 {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "parametrs 'target' and 'target_id' not found!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+    let session = get_own_session(&req, id.into_inner()).await?;
+    let target = data.target.as_ref().unwrap();
+    if (*target == UploadTarget::Order) != (session.kind == UploadKind::Order) {
+        let body = serde_json::to_string(&ErrorParams {
+            error: "upload kind does not match the target!".to_string(),
+        }).unwrap();
+        return Err(Error::BadRequest(body));
+    }
+
+    // права проверяем до записи в хранилище
+    let (target_id, owner_id) = (data.target_id.unwrap(), session.owner_id);
+    let _connection = establish_connection();
+    let item_types = match target {
+        UploadTarget::Item => {
+            let _item = schema::items::table
+                .filter(schema::items::id.eq(target_id))
+                .first::<Item>(&_connection)?;
+            if _item.user_id != owner_id {
+                return Err(permission_denied());
+            }
+            _item.types
+        },
+        UploadTarget::Message => {
+            let message = schema::messages::table
+                .filter(schema::messages::id.eq(target_id))
+                .first::<Message>(&_connection)?;
+            if message.user_id != owner_id {
+                return Err(permission_denied());
+            }
+            MESSAGE_FILES_TYPES
+        },
+        UploadTarget::Order => {
+            let _order = schema::orders::table
+                .filter(schema::orders::id.eq(target_id))
+                .first::<Order>(&_connection)?;
+            if _order.user_id != owner_id {
+                return Err(permission_denied());
+            }
+            7
+        },
+    };
+
+    let _session = session.clone();
+    // хеш считается по файлу на диске, в память он не читается
+    let tmp = block(move || _session.read()).await??;
+    let storage = match session.kind {
+        UploadKind::Order => &state.private_storage,
+        _ => &state.storage,
+    };
+    let stored = store_upload(tmp, session.filename.clone(), owner_id, session.kind, storage, Some(&session.sha256)).await;
+    let file: UploadedFiles = match stored {
+        Ok(file) => file,
+        Err(Error::BadRequest(body)) => {
+            // файл не той суммы или типа уже не исправить докачкой
+            block(move || session.remove()).await?;
+            return Err(Error::BadRequest(body));
+        },
+        Err(e) => return Err(e),
+    };
+    // если строка files не сохранится, файл освободит pins
+    let mut pins = UploadPins::new(storage);
+    pins.push(&file);
+
+    let types = get_file_types(&file.mime);
+    let id = insert_target_file(&_connection, target, target_id, owner_id, item_types, types, &file)?;
+    pins.saved();
+    block(move || session.remove()).await?;
+
+    return Ok(Json(FinalizeUploadResp {
+        id:    id,
+        types: types,
+        src:   file.src,
+        mime:  file.mime,
+        size:  file.size,
+    }));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::NewMessage;
+
+    // нужна база DATABASE_URL со схемой main.sql и хотя бы одним пользователем:
+    // cargo test -- --ignored
+    #[test]
+    #[ignore]
+    fn message_upload_is_read_by_get_files() {
+        let _connection = establish_connection();
+        let user_id = schema::users::table
+            .select(schema::users::id)
+            .first::<i32>(&_connection)
+            .expect("no users in DATABASE_URL");
+        let message = diesel::insert_into(schema::messages::table)
+            .values(&NewMessage {
+                user_id: user_id,
+                chat_id: 0,
+                created: chrono::Local::now().naive_utc(),
+                content: None,
+                view:    1,
+                types:   1,
+            })
+            .get_result::<Message>(&_connection)
+            .expect("E.");
+        let file = UploadedFiles {
+            name:   "test.pdf".to_string(),
+            key:    "test.pdf".to_string(),
+            src:    "/media/test/message_upload_is_read_by_get_files.pdf".to_string(),
+            mime:   "application/pdf".to_string(),
+            size:   1,
+            width:  None,
+            height: None,
+        };
+        let id = insert_target_file(&_connection, &UploadTarget::Message, message.id, user_id, MESSAGE_FILES_TYPES, 4, &file);
+        let (_, _, _, docs) = message.get_files();
+
+        diesel::delete (
+            schema::files::table
+                .filter(schema::files::item_id.eq(message.id))
+                .filter(schema::files::item_types.eq(MESSAGE_FILES_TYPES))
+            )
+            .execute(&_connection)
+            .expect("E.");
+        diesel::delete(schema::messages::table.filter(schema::messages::id.eq(message.id)))
+            .execute(&_connection)
+            .expect("E.");
+        let id = id.expect("E.");
+        assert!(docs.iter().any(|f| f.id == id));
+    }
+}